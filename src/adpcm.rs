@@ -0,0 +1,153 @@
+use crate::wav::WavFile;
+
+/// Step sizes of the ADPCM-A decoder, also used by the OKI chips
+pub const ADPCM_A_STEPS: [i32; 49] = [
+    16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66, 73, 80, 88, 97, 107, 118, 130,
+    143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449, 494, 544, 598, 658, 724, 796,
+    876, 963, 1060, 1166, 1282, 1411, 1552,
+];
+/// Change of the step index for the 3 magnitude bits of a nibble
+pub const ADPCM_A_STEP_CHANGES: [i32; 8] = [-1, -1, -1, -1, 2, 5, 7, 9];
+
+/// Factor of the ADPCM-B difference for each nibble, in eighths of the step
+const ADPCM_B_DIFFERENCES: [i32; 16] =
+    [1, 3, 5, 7, 9, 11, 13, 15, -1, -3, -5, -7, -9, -11, -13, -15];
+/// Factor of the ADPCM-B step for each nibble, in 64ths
+const ADPCM_B_STEP_FACTORS: [i32; 16] = [
+    57, 57, 57, 57, 77, 102, 128, 153, 57, 57, 57, 57, 77, 102, 128, 153,
+];
+/// Change of the step index of the OKI ADPCM for the 3 magnitude bits of a nibble
+const OKI_STEP_CHANGES: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+const ADPCM_B_MIN_STEP: i32 = 127;
+const ADPCM_B_MAX_STEP: i32 = 24576;
+
+/// Sample decoded from the memory of a chip, `end` is exclusive
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecodedSample {
+    /// Command starting the first playback
+    pub command_index: usize,
+    pub start: u32,
+    pub end: u32,
+    pub rate: u32,
+    pub samples: Vec<i16>,
+}
+
+impl DecodedSample {
+    pub fn to_wav(&self) -> WavFile {
+        WavFile::from_i16(self.rate, &self.samples)
+    }
+}
+
+/// Nibbles of the data, high nibble first
+fn nibbles(data: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    data.iter().flat_map(|byte| [byte >> 4, byte & 0x0F])
+}
+
+/// Decode ADPCM-A data (YM2610 ADPCM-A, YM2608 rhythm), 12 bits samples scaled to 16 bits
+pub fn decode_adpcm_a(data: &[u8]) -> Vec<i16> {
+    let mut accumulator: i32 = 0;
+    let mut step_index: i32 = 0;
+    nibbles(data)
+        .map(|nibble| {
+            let step = ADPCM_A_STEPS[step_index as usize];
+            let magnitude = (nibble & 0x07) as i32;
+            let difference = (2 * magnitude + 1) * step / 8;
+            accumulator += if nibble & 0x08 != 0 {
+                -difference
+            } else {
+                difference
+            };
+            // the accumulator wraps on 12 bits
+            accumulator = ((accumulator & 0xFFF) << 20) >> 20;
+            step_index = (step_index + ADPCM_A_STEP_CHANGES[magnitude as usize]).clamp(0, 48);
+
+            (accumulator << 4) as i16
+        })
+        .collect()
+}
+
+/// Decode ADPCM-B data (delta-T unit of the Y8950, YM2608 and YM2610)
+pub fn decode_adpcm_b(data: &[u8]) -> Vec<i16> {
+    let mut accumulator: i32 = 0;
+    let mut step = ADPCM_B_MIN_STEP;
+    nibbles(data)
+        .map(|nibble| {
+            let nibble = nibble as usize;
+            accumulator = (accumulator + ADPCM_B_DIFFERENCES[nibble] * step / 8)
+                .clamp(i16::MIN as i32, i16::MAX as i32);
+            step = (step * ADPCM_B_STEP_FACTORS[nibble] / 64)
+                .clamp(ADPCM_B_MIN_STEP, ADPCM_B_MAX_STEP);
+
+            accumulator as i16
+        })
+        .collect()
+}
+
+/// Decoder of the OKI/Dialogic ADPCM of the OKIM6258 and OKIM6295, 12 bits samples
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct OkiAdpcm {
+    pub signal: i32,
+    pub step_index: i32,
+}
+
+impl OkiAdpcm {
+    /// Decode the next nibble, returns the new 12 bits signal
+    pub fn decode(&mut self, nibble: u8) -> i32 {
+        let step = ADPCM_A_STEPS[self.step_index as usize];
+        let magnitude = (nibble & 0x07) as i32;
+        let mut difference = step / 8;
+        if magnitude & 0x04 != 0 {
+            difference += step;
+        }
+        if magnitude & 0x02 != 0 {
+            difference += step / 2;
+        }
+        if magnitude & 0x01 != 0 {
+            difference += step / 4;
+        }
+        let difference = if nibble & 0x08 != 0 {
+            -difference
+        } else {
+            difference
+        };
+        // the signal saturates on 12 bits
+        self.signal = (self.signal + difference).clamp(-2048, 2047);
+        self.step_index = (self.step_index + OKI_STEP_CHANGES[magnitude as usize]).clamp(0, 48);
+
+        self.signal
+    }
+}
+
+/// Decode OKIM6295 ADPCM data, high nibble first, scaled to 16 bits
+pub fn decode_oki_adpcm(data: &[u8]) -> Vec<i16> {
+    let mut decoder = OkiAdpcm::default();
+    nibbles(data)
+        .map(|nibble| (decoder.decode(nibble) << 4) as i16)
+        .collect()
+}
+
+/// Sample rate of the ADPCM-B unit, `delta_n / 65536` of the rate of the chip
+pub fn adpcm_b_rate(chip_rate: u32, delta_n: u16) -> u32 {
+    (chip_rate as u64 * delta_n as u64 / 65536) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_adpcm_a, decode_adpcm_b, decode_oki_adpcm};
+
+    #[test]
+    fn decoders() {
+        // largest positive steps, then as many negative ones
+        let up = decode_adpcm_b(&[0x77, 0x77]);
+        assert_eq!(up, [238, 806, 2163, 5406]);
+        let down = decode_adpcm_b(&[0x77, 0x77, 0xFF, 0xFF]);
+        assert!(down[7] < down[3]);
+
+        let a = decode_adpcm_a(&[0x70, 0x08]);
+        assert_eq!(a, [30 << 4, 34 << 4, 38 << 4, 35 << 4]);
+
+        let oki = decode_oki_adpcm(&[0x70, 0x0F]);
+        assert_eq!(oki, [30 << 4, 34 << 4, 37 << 4, -15 << 4]);
+    }
+}
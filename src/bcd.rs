@@ -37,8 +37,6 @@ pub fn decimal_to_bcd(decimal: u32) -> Vec<u8> {
 
 #[cfg(test)]
 mod test_utils {
-    use bytes::{Buf, Bytes};
-
     use crate::bcd::decimal_to_bcd;
 
     use super::bcd_from_bytes;
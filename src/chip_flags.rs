@@ -0,0 +1,273 @@
+use crate::header::HeaderData;
+use crate::legacy::{LEGACY_SN76489_FEEDBACK, LEGACY_SN76489_SHIFT_REGISTER_WIDTH};
+
+/// AY8910 flags used when the header leaves them at 0
+pub const DEFAULT_AY_FLAGS: u8 = 0x01;
+
+/// Chip variant stored in `HeaderData::ay8910_chip_type`
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AyChipType {
+    AY8910,
+    AY8912,
+    AY8913,
+    AY8930,
+    AY8914,
+    YM2149,
+    YM3439,
+    YMZ284,
+    YMZ294,
+    Unknown(u8),
+}
+
+impl AyChipType {
+    pub fn from_raw(raw: u8) -> Self {
+        match raw {
+            0x00 => AyChipType::AY8910,
+            0x01 => AyChipType::AY8912,
+            0x02 => AyChipType::AY8913,
+            0x03 => AyChipType::AY8930,
+            0x04 => AyChipType::AY8914,
+            0x10 => AyChipType::YM2149,
+            0x11 => AyChipType::YM3439,
+            0x12 => AyChipType::YMZ284,
+            0x13 => AyChipType::YMZ294,
+            raw => AyChipType::Unknown(raw),
+        }
+    }
+
+    pub fn to_raw(self) -> u8 {
+        match self {
+            AyChipType::AY8910 => 0x00,
+            AyChipType::AY8912 => 0x01,
+            AyChipType::AY8913 => 0x02,
+            AyChipType::AY8930 => 0x03,
+            AyChipType::AY8914 => 0x04,
+            AyChipType::YM2149 => 0x10,
+            AyChipType::YM3439 => 0x11,
+            AyChipType::YMZ284 => 0x12,
+            AyChipType::YMZ294 => 0x13,
+            AyChipType::Unknown(raw) => raw,
+        }
+    }
+
+    /// Yamaha variants, which have the clock divider pin
+    pub fn is_yamaha(self) -> bool {
+        matches!(
+            self,
+            AyChipType::YM2149 | AyChipType::YM3439 | AyChipType::YMZ284 | AyChipType::YMZ294
+        )
+    }
+}
+
+/// Flags of an AY8910, also used for the SSG part of the YM2203 and YM2608
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AyFlags {
+    pub legacy_output: bool,
+    pub single_output: bool,
+    pub discrete_output: bool,
+    pub raw_output: bool,
+    /// Pin 26 of the YM2149 and compatibles pulled low, halving the clock
+    pub ym2149_pin26_low: bool,
+}
+
+impl Default for AyFlags {
+    fn default() -> Self {
+        AyFlags::from_raw(DEFAULT_AY_FLAGS)
+    }
+}
+
+impl AyFlags {
+    /// 0 is read as the documented default of 0x01
+    pub fn from_raw(raw: u8) -> Self {
+        let raw = if raw == 0 { DEFAULT_AY_FLAGS } else { raw };
+        AyFlags {
+            legacy_output: raw & 0x01 != 0,
+            single_output: raw & 0x02 != 0,
+            discrete_output: raw & 0x04 != 0,
+            raw_output: raw & 0x08 != 0,
+            ym2149_pin26_low: raw & 0x10 != 0,
+        }
+    }
+
+    /// Flags with every field false have no encoding of their own: they give 0, which
+    /// `from_raw` reads as the default flags. Every other value round-trips.
+    pub fn to_raw(self) -> u8 {
+        (self.legacy_output as u8)
+            | (self.single_output as u8) << 1
+            | (self.discrete_output as u8) << 2
+            | (self.raw_output as u8) << 3
+            | (self.ym2149_pin26_low as u8) << 4
+    }
+}
+
+/// Flags of the SN76489, every field is true when the feature is enabled
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sn76489Flags {
+    pub frequency_0_is_0x400: bool,
+    pub output_negate: bool,
+    /// Game Gear stereo, stored inverted (on when the bit is clear)
+    pub stereo: bool,
+    /// /8 clock divider, stored inverted (on when the bit is clear)
+    pub clock_divider_8: bool,
+    /// XNOR noise of the NCR8496 / PSSJ-3
+    pub xnor_noise: bool,
+}
+
+impl Default for Sn76489Flags {
+    fn default() -> Self {
+        Sn76489Flags::from_raw(0)
+    }
+}
+
+impl Sn76489Flags {
+    pub fn from_raw(raw: u8) -> Self {
+        Sn76489Flags {
+            frequency_0_is_0x400: raw & 0x01 != 0,
+            output_negate: raw & 0x02 != 0,
+            stereo: raw & 0x04 == 0,
+            clock_divider_8: raw & 0x08 == 0,
+            xnor_noise: raw & 0x10 != 0,
+        }
+    }
+
+    pub fn to_raw(self) -> u8 {
+        (self.frequency_0_is_0x400 as u8)
+            | (self.output_negate as u8) << 1
+            | (!self.stereo as u8) << 2
+            | (!self.clock_divider_8 as u8) << 3
+            | (self.xnor_noise as u8) << 4
+    }
+}
+
+/// Everything needed to emulate the noise channel and output stage of the SN76489
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sn76489Config {
+    pub feedback: u16,
+    pub shift_register_width: u8,
+    pub flags: Sn76489Flags,
+}
+
+impl HeaderData {
+    pub fn ay_chip_type(&self) -> AyChipType {
+        AyChipType::from_raw(self.ay8910_chip_type)
+    }
+
+    pub fn set_ay_chip_type(&mut self, chip_type: AyChipType) {
+        self.ay8910_chip_type = chip_type.to_raw();
+    }
+
+    pub fn ay_flags(&self) -> AyFlags {
+        AyFlags::from_raw(self.ay8910_flags)
+    }
+
+    pub fn set_ay_flags(&mut self, flags: AyFlags) {
+        self.ay8910_flags = flags.to_raw();
+    }
+
+    pub fn ym2203_ay_flags(&self) -> AyFlags {
+        AyFlags::from_raw(self.ym2203_ay8910_flags)
+    }
+
+    pub fn set_ym2203_ay_flags(&mut self, flags: AyFlags) {
+        self.ym2203_ay8910_flags = flags.to_raw();
+    }
+
+    pub fn ym2608_ay_flags(&self) -> AyFlags {
+        AyFlags::from_raw(self.ym2608_ay8910_flags)
+    }
+
+    pub fn set_ym2608_ay_flags(&mut self, flags: AyFlags) {
+        self.ym2608_ay8910_flags = flags.to_raw();
+    }
+
+    /// Feedback and shift register width of 0 are read as the Sega defaults of 0x0009 and 16
+    pub fn sn76489_config(&self) -> Sn76489Config {
+        let feedback = match self.effective_sn76489_feedback() {
+            0 => LEGACY_SN76489_FEEDBACK,
+            feedback => feedback,
+        };
+        let shift_register_width = match self.effective_sn76489_shift_register_width() {
+            0 => LEGACY_SN76489_SHIFT_REGISTER_WIDTH,
+            width => width,
+        };
+
+        Sn76489Config {
+            feedback,
+            shift_register_width,
+            flags: Sn76489Flags::from_raw(self.sn76489_flags),
+        }
+    }
+
+    pub fn set_sn76489_config(&mut self, config: Sn76489Config) {
+        self.sn76489_feedback = config.feedback;
+        self.sn76489_shift_register_width = config.shift_register_width;
+        self.sn76489_flags = config.flags.to_raw();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::header::HeaderData;
+
+    use super::{AyChipType, AyFlags, Sn76489Flags};
+
+    #[test]
+    fn ay_round_trip() {
+        for raw in 0..=0xFF {
+            assert_eq!(AyChipType::from_raw(raw).to_raw(), raw);
+        }
+        for raw in 0x01..=0x1F {
+            assert_eq!(AyFlags::from_raw(raw).to_raw(), raw);
+        }
+        assert_eq!(
+            AyFlags::from_raw(AyFlags::default().to_raw()),
+            AyFlags::default()
+        );
+        // no flag set is stored as 0, which stands for the default
+        let none = AyFlags {
+            legacy_output: false,
+            single_output: false,
+            discrete_output: false,
+            raw_output: false,
+            ym2149_pin26_low: false,
+        };
+        assert_eq!(none.to_raw(), 0x00);
+        assert_eq!(AyFlags::from_raw(none.to_raw()), AyFlags::default());
+
+        let mut header = HeaderData::default();
+        assert_eq!(header.ay_flags(), AyFlags::default());
+        assert!(header.ay_flags().legacy_output);
+
+        header.set_ay_chip_type(AyChipType::YM2149);
+        header.set_ay_flags(AyFlags {
+            ym2149_pin26_low: true,
+            ..Default::default()
+        });
+        assert_eq!(header.ay8910_chip_type, 0x10);
+        assert_eq!(header.ay8910_flags, 0x11);
+    }
+
+    #[test]
+    fn sn76489_defaults() {
+        for raw in 0x00..=0x1F {
+            assert_eq!(Sn76489Flags::from_raw(raw).to_raw(), raw);
+        }
+
+        let header = HeaderData {
+            version: 151,
+            sn76489_clock: 3579545,
+            sn76489_flags: 0x04,
+            ..Default::default()
+        };
+        let config = header.sn76489_config();
+        assert_eq!(config.feedback, 0x0009);
+        assert_eq!(config.shift_register_width, 16);
+        assert!(!config.flags.stereo);
+        assert!(config.flags.clock_divider_8);
+    }
+}
@@ -0,0 +1,792 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::command::Command;
+use crate::datablock::DATA_TYPE_YM2612_PCM;
+use crate::systems::System;
+use crate::vgmfile::VgmFile;
+
+/// Address of the Game Gear stereo register in the SN76489 register file,
+/// after the 8 tone/volume registers
+pub const SN76489_STEREO_REGISTER: u32 = 0x08;
+
+/// Start of the WonderSwan memory in its register file, after the I/O registers
+pub const WONDERSWAN_MEMORY: u32 = 0x100;
+
+/// A chip in the file, `instance` is 1 for the second chip of a dual chip setup
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChipId {
+    pub system: System,
+    pub instance: u8,
+}
+
+/// Shadow copy of the registers of a chip, shared between snapshots until one of them writes.
+///
+/// Addresses are the register numbers of the command, with the port in bits 8 and up for the
+/// chips that have several (`port << 8 | register`). Registers that were never written read as `None`.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct RegisterFile {
+    values: Arc<Vec<Option<u16>>>,
+}
+
+impl RegisterFile {
+    pub fn get(&self, address: u32) -> Option<u16> {
+        self.values.get(address as usize).copied().flatten()
+    }
+
+    pub fn set(&mut self, address: u32, value: u16) {
+        let values = Arc::make_mut(&mut self.values);
+        if values.len() <= address as usize {
+            values.resize(address as usize + 1, None);
+        }
+        values[address as usize] = Some(value);
+    }
+
+    /// Every written register as `(address, value)`, by increasing address
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u16)> + '_ {
+        self.values
+            .iter()
+            .enumerate()
+            .filter_map(|(address, value)| value.map(|value| (address as u32, value)))
+    }
+}
+
+/// Bit of the command addresses selecting the second chip, it is not part of the addresses of
+/// the register files
+fn second_chip_bit(system: &System, address: u32) -> u32 {
+    match system {
+        System::SegaPcm
+        | System::Scsp
+        | System::Vsu
+        | System::X1_010
+        | System::Ymf278B
+        | System::Ymf271
+        | System::K051649
+        | System::K054539
+        | System::C140
+        | System::Es5503
+        | System::C352 => 0x8000,
+        System::WonderSwan if address >= WONDERSWAN_MEMORY => 0x8000,
+        _ => 0x80,
+    }
+}
+
+/// Key on register of the FM chips, each write only keys the channel in its low 3 bits
+fn key_on_register(system: &System) -> Option<u32> {
+    match system {
+        System::Ym2612 | System::Ym2203 | System::Ym2608 | System::Ym2610 => Some(0x28),
+        System::Ym2151 => Some(0x08),
+        _ => None,
+    }
+}
+
+/// Register write of a command once the port and address latch are resolved, the address is
+/// the one of the register file of the chip
+pub(crate) fn register_write(cmd: &Command) -> Option<(ChipId, u32, u16)> {
+    let two_ports = |port: u32, register: u8| port << 8 | register as u32;
+    let chip = cmd.chip()?;
+
+    let (address, value) = match *cmd {
+        Command::YM2413Write { register, value }
+        | Command::YM2612Port0Write { register, value }
+        | Command::YM2151Write { register, value }
+        | Command::YM2203Write { register, value }
+        | Command::YM2608Port0Write { register, value }
+        | Command::YM2610Port0Write { register, value }
+        | Command::YM3812Write { register, value }
+        | Command::YM3526Write { register, value }
+        | Command::Y8950Write { register, value }
+        | Command::YMZ280BWrite { register, value }
+        | Command::YMF262Port0Write { register, value }
+        | Command::AY8910Write { register, value }
+        | Command::RF5C68Write { register, value }
+        | Command::RF5C164Write { register, value }
+        | Command::GameBoyDMGWrite { register, value }
+        | Command::NESAPUWrite { register, value }
+        | Command::MultiPCMWrite { register, value }
+        | Command::uPD7759Write { register, value }
+        | Command::OKIM6258Write { register, value }
+        | Command::OKIM6295Write { register, value }
+        | Command::HuC6280Write { register, value }
+        | Command::K053260Write { register, value }
+        | Command::PokeyWrite { register, value }
+        | Command::WonderSwanWrite { register, value }
+        | Command::SAA1099Write { register, value }
+        | Command::ES5506Write { register, value }
+        | Command::GA20Write { register, value } => (register as u32, value as u16),
+
+        Command::YM2612Port1Write { register, value }
+        | Command::YM2608Port1Write { register, value }
+        | Command::YM2610Port1Write { register, value }
+        | Command::YMF262Port1Write { register, value } => (two_ports(1, register), value as u16),
+
+        Command::YMF278BWrite {
+            port,
+            register,
+            value,
+        }
+        | Command::YMF271Write {
+            port,
+            register,
+            value,
+        }
+        | Command::SCC1Write {
+            port,
+            register,
+            value,
+        } => (two_ports(port as u32, register), value as u16),
+
+        Command::PWMWrite { register, value }
+        | Command::QSoundWrite { register, value }
+        | Command::ES5506Write16 { register, value } => (register as u32, value),
+
+        Command::WonderSwanWrite16 { offset, value } => {
+            (WONDERSWAN_MEMORY + offset as u32, value as u16)
+        }
+
+        Command::SegaPCMWrite { offset, value }
+        | Command::SCSPWrite { offset, value }
+        | Command::VSUWrite { offset, value }
+        | Command::X1010Write { offset, value } => (offset as u32, value as u16),
+
+        Command::K054539Write { register, value }
+        | Command::C140Write { register, value }
+        | Command::ES5503Write { register, value } => (register as u32, value as u16),
+
+        Command::C352Write { register, value } => (register as u32, value),
+
+        Command::GameGearPSGStereo { value } => (SN76489_STEREO_REGISTER, value as u16),
+
+        // handled by `ChipState::update`, or memory writes that are not registers
+        _ => return None,
+    };
+
+    let address = match chip.instance {
+        0 => address,
+        _ => address & !second_chip_bit(&chip.system, address),
+    };
+    Some((chip, address, value))
+}
+
+/// Command writing a register, the inverse of `register_write`
+fn register_command(chip: &ChipId, address: u32, value: u16) -> Option<Command> {
+    let system = &chip.system;
+    let address = match chip.instance {
+        0 => address,
+        _ => address | second_chip_bit(system, address),
+    };
+    let register = address as u8;
+    let port = (address >> 8) as u8;
+    let byte = value as u8;
+
+    Some(match (system, port) {
+        (System::Sn76489, _) if address == SN76489_STEREO_REGISTER => {
+            Command::GameGearPSGStereo { value: byte }
+        }
+        (System::Ym2413, 0) => Command::YM2413Write {
+            register,
+            value: byte,
+        },
+        (System::Ym2612, 0) => Command::YM2612Port0Write {
+            register,
+            value: byte,
+        },
+        (System::Ym2612, 1) => Command::YM2612Port1Write {
+            register,
+            value: byte,
+        },
+        (System::Ym2151, 0) => Command::YM2151Write {
+            register,
+            value: byte,
+        },
+        (System::Ym2203, 0) => Command::YM2203Write {
+            register,
+            value: byte,
+        },
+        (System::Ym2608, 0) => Command::YM2608Port0Write {
+            register,
+            value: byte,
+        },
+        (System::Ym2608, 1) => Command::YM2608Port1Write {
+            register,
+            value: byte,
+        },
+        (System::Ym2610, 0) => Command::YM2610Port0Write {
+            register,
+            value: byte,
+        },
+        (System::Ym2610, 1) => Command::YM2610Port1Write {
+            register,
+            value: byte,
+        },
+        (System::Ym3812, 0) => Command::YM3812Write {
+            register,
+            value: byte,
+        },
+        (System::Ym3526, 0) => Command::YM3526Write {
+            register,
+            value: byte,
+        },
+        (System::Y8950, 0) => Command::Y8950Write {
+            register,
+            value: byte,
+        },
+        (System::Ymz280b, 0) => Command::YMZ280BWrite {
+            register,
+            value: byte,
+        },
+        (System::Ymf262, 0) => Command::YMF262Port0Write {
+            register,
+            value: byte,
+        },
+        (System::Ymf262, 1) => Command::YMF262Port1Write {
+            register,
+            value: byte,
+        },
+        (System::Ay8910, 0) => Command::AY8910Write {
+            register,
+            value: byte,
+        },
+        (System::Rf5c68, 0) => Command::RF5C68Write {
+            register,
+            value: byte,
+        },
+        (System::Rf5c164, 0) => Command::RF5C164Write {
+            register,
+            value: byte,
+        },
+        (System::GameboyDmg, 0) => Command::GameBoyDMGWrite {
+            register,
+            value: byte,
+        },
+        (System::NesApu, 0) => Command::NESAPUWrite {
+            register,
+            value: byte,
+        },
+        (System::MultiPcm, 0) => Command::MultiPCMWrite {
+            register,
+            value: byte,
+        },
+        (System::Upd7759, 0) => Command::uPD7759Write {
+            register,
+            value: byte,
+        },
+        (System::Okim6258, 0) => Command::OKIM6258Write {
+            register,
+            value: byte,
+        },
+        (System::Okim6295, 0) => Command::OKIM6295Write {
+            register,
+            value: byte,
+        },
+        (System::HuC6280, 0) => Command::HuC6280Write {
+            register,
+            value: byte,
+        },
+        (System::K053260, 0) => Command::K053260Write {
+            register,
+            value: byte,
+        },
+        (System::Pokey, 0) => Command::PokeyWrite {
+            register,
+            value: byte,
+        },
+        (System::Saa1099, 0) => Command::SAA1099Write {
+            register,
+            value: byte,
+        },
+        (System::Ga20, 0) => Command::GA20Write {
+            register,
+            value: byte,
+        },
+        (System::Ymf278B, _) => Command::YMF278BWrite {
+            port,
+            register,
+            value: byte,
+        },
+        (System::Ymf271, _) => Command::YMF271Write {
+            port,
+            register,
+            value: byte,
+        },
+        (System::K051649, _) => Command::SCC1Write {
+            port,
+            register,
+            value: byte,
+        },
+        (System::Pwm, _) => Command::PWMWrite { register, value },
+        (System::QSound, _) => Command::QSoundWrite { register, value },
+        (System::SegaPcm, _) => Command::SegaPCMWrite {
+            offset: address as u16,
+            value: byte,
+        },
+        (System::Scsp, _) => Command::SCSPWrite {
+            offset: address as u16,
+            value: byte,
+        },
+        (System::WonderSwan, _) if address >= WONDERSWAN_MEMORY => Command::WonderSwanWrite16 {
+            offset: (address - WONDERSWAN_MEMORY) as u16,
+            value: byte,
+        },
+        (System::WonderSwan, _) => Command::WonderSwanWrite {
+            register,
+            value: byte,
+        },
+        // the 8 bit writes go to the same registers
+        (System::Es5506, _) => Command::ES5506Write16 { register, value },
+        (System::Vsu, _) => Command::VSUWrite {
+            offset: address as u16,
+            value: byte,
+        },
+        (System::X1_010, _) => Command::X1010Write {
+            offset: address as u16,
+            value: byte,
+        },
+        (System::K054539, _) => Command::K054539Write {
+            register: address as u16,
+            value: byte,
+        },
+        (System::C140, _) => Command::C140Write {
+            register: address as u16,
+            value: byte,
+        },
+        (System::Es5503, _) => Command::ES5503Write {
+            register: address as u16,
+            value: byte,
+        },
+        (System::C352, _) => Command::C352Write {
+            register: address as u16,
+            value,
+        },
+        _ => return None,
+    })
+}
+
+/// Order in which the registers of a chip are restored: the OPN frequency latch is shared by
+/// the channels, so every latch write goes right before the register that applies it.
+/// Key on registers go last.
+fn restore_order(system: &System, address: u32) -> (u8, u32, u8) {
+    let register = address & 0xFF;
+    match system {
+        System::Ym2612 | System::Ym2203 | System::Ym2608 | System::Ym2610 => match register {
+            0x28 => (2, address, 0),
+            0xA4..=0xA6 | 0xAC..=0xAE => (1, address - 4, 0),
+            0xA0..=0xA2 | 0xA8..=0xAA => (1, address, 1),
+            _ => (0, address, 0),
+        },
+        System::Ym2151 if register == 0x08 => (2, address, 0),
+        _ => (0, address, 0),
+    }
+}
+
+/// State of every chip of a file, kept up to date by feeding it every command in order.
+/// Cloning it is cheap, the register files are only copied when a clone writes to them.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ChipState {
+    chips: HashMap<ChipId, RegisterFile>,
+    /// Last write to the key on register of the FM chips for each channel
+    key_on: HashMap<ChipId, [Option<u8>; 8]>,
+    /// Last latch byte written to the SN76489, data bytes go to the register it selects
+    sn76489_latch: u8,
+    /// Concatenation of the YM2612 PCM data blocks, read by the 0x8n commands
+    ym2612_pcm: Arc<Vec<u8>>,
+    ym2612_pcm_offset: usize,
+}
+
+impl ChipState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, cmd: &Command) {
+        match *cmd {
+            Command::PSGWrite { value } => self.write_sn76489(value),
+            Command::DataBlock {
+                data_type: DATA_TYPE_YM2612_PCM,
+                ref data,
+                ..
+            } => Arc::make_mut(&mut self.ym2612_pcm).extend_from_slice(data),
+            Command::SeekPCM { offset } => self.ym2612_pcm_offset = offset as usize,
+            Command::YM2612Port0Address2AWriteWait { .. } => {
+                let value = self.ym2612_pcm.get(self.ym2612_pcm_offset).copied();
+                self.ym2612_pcm_offset += 1;
+                if let Some(value) = value {
+                    self.set(Self::first_chip(System::Ym2612), 0x2A, value as u16);
+                }
+            }
+            _ => {
+                if let Some((chip, address, value)) = register_write(cmd) {
+                    if key_on_register(&chip.system) == Some(address) {
+                        let channels = self.key_on.entry(chip.clone()).or_default();
+                        channels[value as usize & 0x07] = Some(value as u8);
+                    }
+                    self.set(chip, address, value);
+                }
+            }
+        }
+    }
+
+    /// The SN76489 is seen as 8 registers, `channel * 2` for the tone or noise mode
+    /// and `channel * 2 + 1` for the volume, holding the value assembled from latch and data bytes
+    fn write_sn76489(&mut self, value: u8) {
+        if value & 0x80 != 0 {
+            self.sn76489_latch = value;
+        }
+        let address = ((self.sn76489_latch >> 4) & 0x07) as u32;
+        let is_tone = address & 0x01 == 0 && address != 6;
+
+        let previous = self.register(System::Sn76489, address).unwrap_or(0);
+        let new = match (value & 0x80 != 0, is_tone) {
+            (true, true) => previous & 0x3F0 | (value & 0x0F) as u16,
+            (false, true) => previous & 0x00F | ((value & 0x3F) as u16) << 4,
+            (_, false) => (value & 0x0F) as u16,
+        };
+        self.set(Self::first_chip(System::Sn76489), address, new);
+    }
+
+    fn first_chip(system: System) -> ChipId {
+        ChipId {
+            system,
+            instance: 0,
+        }
+    }
+
+    fn set(&mut self, chip: ChipId, address: u32, value: u16) {
+        self.chips.entry(chip).or_default().set(address, value);
+    }
+
+    /// Register of the first chip of a system, `None` if it was never written
+    pub fn register(&self, system: System, address: u32) -> Option<u16> {
+        self.registers(&Self::first_chip(system))?.get(address)
+    }
+
+    pub fn registers(&self, chip: &ChipId) -> Option<&RegisterFile> {
+        self.chips.get(chip)
+    }
+
+    /// Every chip that received a register write
+    pub fn chips(&self) -> impl Iterator<Item = (&ChipId, &RegisterFile)> {
+        self.chips.iter()
+    }
+
+    pub fn sn76489_latch(&self) -> u8 {
+        self.sn76489_latch
+    }
+
+    /// Position of the next byte read by a 0x8n command in the YM2612 PCM data
+    pub fn ym2612_pcm_offset(&self) -> usize {
+        self.ym2612_pcm_offset
+    }
+
+    /// Concatenation of the YM2612 PCM data blocks seen so far
+    pub fn ym2612_pcm(&self) -> &[u8] {
+        &self.ym2612_pcm
+    }
+
+    /// Register writes that bring chips in their power on state to this state, followed by
+    /// the seek of the YM2612 PCM data. The PCM data itself is not included.
+    /// The key on register is written for every channel, the last written channel goes last.
+    pub fn to_commands(&self) -> Vec<Command> {
+        let mut chips: Vec<(&ChipId, &RegisterFile)> = self.chips.iter().collect();
+        chips.sort_by_key(|(chip, _)| {
+            let index = System::ALL.iter().position(|system| *system == chip.system);
+            (index, chip.instance)
+        });
+
+        let mut commands = vec![];
+        for (chip, registers) in chips {
+            if chip.system == System::Sn76489 {
+                commands.extend(self.sn76489_commands(registers));
+                continue;
+            }
+
+            let key_on = key_on_register(&chip.system);
+            let mut writes: Vec<(u32, u16)> = registers
+                .iter()
+                .filter(|(address, _)| Some(*address) != key_on)
+                .collect();
+            writes.sort_by_key(|(address, _)| restore_order(&chip.system, *address));
+            if let (Some(address), Some(channels)) = (key_on, self.key_on.get(chip)) {
+                let last = registers.get(address);
+                let mut keys: Vec<u16> = channels.iter().flatten().map(|&v| v as u16).collect();
+                keys.sort_by_key(|value| Some(*value) == last);
+                writes.extend(keys.into_iter().map(|value| (address, value)));
+            }
+            commands.extend(
+                writes
+                    .into_iter()
+                    .filter_map(|(address, value)| register_command(chip, address, value)),
+            );
+        }
+
+        if !self.ym2612_pcm.is_empty() {
+            commands.push(Command::SeekPCM {
+                offset: self.ym2612_pcm_offset as u32,
+            });
+        }
+
+        commands
+    }
+
+    /// Latch and data bytes for every SN76489 register, the latched register goes last
+    fn sn76489_commands(&self, registers: &RegisterFile) -> Vec<Command> {
+        let latched = ((self.sn76489_latch >> 4) & 0x07) as u32;
+        let mut writes: Vec<(u32, u16)> = registers
+            .iter()
+            .filter(|(address, _)| *address < SN76489_STEREO_REGISTER)
+            .collect();
+        writes.sort_by_key(|(address, _)| *address == latched);
+
+        let mut commands = vec![];
+        if let Some(stereo) = registers.get(SN76489_STEREO_REGISTER) {
+            commands.push(Command::GameGearPSGStereo {
+                value: stereo as u8,
+            });
+        }
+        for (address, value) in writes {
+            let latch = 0x80 | (address as u8) << 4;
+            commands.push(Command::PSGWrite {
+                value: latch | (value & 0x0F) as u8,
+            });
+            if address & 0x01 == 0 && address != 6 {
+                commands.push(Command::PSGWrite {
+                    value: (value >> 4) as u8 & 0x3F,
+                });
+            }
+        }
+
+        commands
+    }
+}
+
+impl VgmFile {
+    /// State of the chips before the command at `index` runs
+    pub fn chip_state_at(&self, index: usize) -> ChipState {
+        let mut state = ChipState::new();
+        for cmd in &self.commands[..index] {
+            state.update(cmd);
+        }
+
+        state
+    }
+
+    /// State of the chips at a sample position, after every command of that sample ran
+    pub fn chip_state_at_sample(&self, sample: u64) -> ChipState {
+        let mut state = ChipState::new();
+        for (position, _, cmd) in self.timeline() {
+            if position > sample {
+                break;
+            }
+            state.update(cmd);
+        }
+
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::systems::System;
+
+    use super::{ChipId, ChipState};
+
+    #[test]
+    fn restore() {
+        let mut state = ChipState::new();
+        for cmd in [
+            Command::YM2612Port0Write {
+                register: 0x28,
+                value: 0xF0,
+            },
+            Command::YM2612Port0Write {
+                register: 0xA4,
+                value: 0x22,
+            },
+            Command::YM2612Port0Write {
+                register: 0xA0,
+                value: 0x69,
+            },
+            Command::PSGWrite { value: 0xAE },
+            Command::PSGWrite { value: 0x0F },
+            Command::PSGWrite { value: 0xFF },
+            Command::C140Write {
+                register: 0x1F5,
+                value: 0x01,
+            },
+        ] {
+            state.update(&cmd);
+        }
+
+        let commands = state.to_commands();
+        assert_eq!(
+            commands[..3],
+            [
+                Command::PSGWrite { value: 0xAE },
+                Command::PSGWrite { value: 0x0F },
+                Command::PSGWrite { value: 0xFF },
+            ]
+        );
+        assert_eq!(
+            commands[3..6],
+            [
+                Command::YM2612Port0Write {
+                    register: 0xA4,
+                    value: 0x22,
+                },
+                Command::YM2612Port0Write {
+                    register: 0xA0,
+                    value: 0x69,
+                },
+                Command::YM2612Port0Write {
+                    register: 0x28,
+                    value: 0xF0,
+                },
+            ]
+        );
+
+        let mut restored = ChipState::new();
+        for cmd in &commands {
+            restored.update(cmd);
+        }
+        assert_eq!(restored, state);
+    }
+
+    #[test]
+    fn restore_frequencies() {
+        let mut state = ChipState::new();
+        let frequencies = [
+            (0x000, 0x22, 0x11),
+            (0x001, 0x33, 0x44),
+            (0x002, 0x0C, 0x55),
+            // channel 3 special mode
+            (0x00A, 0x1B, 0x77),
+            (0x100, 0x2A, 0x66),
+        ];
+        let ym2612 = |address: u32, value| match address >> 8 {
+            0 => Command::YM2612Port0Write {
+                register: address as u8,
+                value,
+            },
+            _ => Command::YM2612Port1Write {
+                register: address as u8,
+                value,
+            },
+        };
+        // every latch written before the others are applied
+        for &(channel, high, _) in &frequencies {
+            state.update(&ym2612(0xA4 + channel, high));
+        }
+        for &(channel, _, low) in &frequencies {
+            state.update(&ym2612(0xA0 + channel, low));
+        }
+
+        let commands = state.to_commands();
+        let expected: Vec<Command> = frequencies
+            .iter()
+            .flat_map(|&(channel, high, low)| {
+                [ym2612(0xA4 + channel, high), ym2612(0xA0 + channel, low)]
+            })
+            .collect();
+        assert_eq!(commands, expected);
+    }
+
+    #[test]
+    fn key_on_and_second_chip() {
+        let mut state = ChipState::new();
+        for cmd in [
+            Command::YM2612Port0Write {
+                register: 0x28,
+                value: 0xF0,
+            },
+            Command::YM2612Port0Write {
+                register: 0x28,
+                value: 0xF5,
+            },
+            Command::YM2612Port0Write {
+                register: 0x28,
+                value: 0x01,
+            },
+            Command::GameBoyDMGWrite {
+                register: 0x80 | 0x12,
+                value: 0xF3,
+            },
+            Command::C140Write {
+                register: 0x8000 | 0x05,
+                value: 0x80,
+            },
+        ] {
+            state.update(&cmd);
+        }
+
+        let second = |system| ChipId {
+            system,
+            instance: 1,
+        };
+        let registers = state.registers(&second(System::GameboyDmg)).unwrap();
+        assert_eq!(registers.get(0x12), Some(0xF3));
+        assert_eq!(
+            state.registers(&second(System::C140)).unwrap().get(5),
+            Some(0x80)
+        );
+        assert_eq!(state.register(System::GameboyDmg, 0x12), None);
+
+        // channels 0 and 5 are on, the last write to channel 1 goes last
+        let ym2612 = |value| Command::YM2612Port0Write {
+            register: 0x28,
+            value,
+        };
+        let commands = state.to_commands();
+        assert_eq!(commands[..3], [ym2612(0xF0), ym2612(0xF5), ym2612(0x01)]);
+        assert!(commands.contains(&Command::GameBoyDMGWrite {
+            register: 0x80 | 0x12,
+            value: 0xF3,
+        }));
+
+        let mut restored = ChipState::new();
+        for cmd in &commands {
+            restored.update(cmd);
+        }
+        assert_eq!(restored, state);
+    }
+
+    #[test]
+    fn shadow_registers() {
+        let mut state = ChipState::new();
+        for cmd in [
+            Command::YM2612Port0Write {
+                register: 0xA4,
+                value: 0x22,
+            },
+            Command::YM2612Port1Write {
+                register: 0xA4,
+                value: 0x1A,
+            },
+            // tone 1 = 0x0FE, then volume 1
+            Command::PSGWrite { value: 0xAE },
+            Command::PSGWrite { value: 0x0F },
+            Command::PSGWrite { value: 0xB5 },
+            Command::DataBlock {
+                data_type: 0x00,
+                data_size: 2,
+                data: vec![0x80, 0x7F],
+            },
+            Command::SeekPCM { offset: 1 },
+        ] {
+            state.update(&cmd);
+        }
+
+        assert_eq!(state.register(System::Ym2612, 0xA4), Some(0x22));
+        assert_eq!(state.register(System::Ym2612, 0x1A4), Some(0x1A));
+        assert_eq!(state.register(System::Sn76489, 2), Some(0x0FE));
+        assert_eq!(state.register(System::Sn76489, 3), Some(0x05));
+        assert_eq!(state.register(System::Sn76489, 0), None);
+
+        let snapshot = state.clone();
+        state.update(&Command::YM2612Port0Address2AWriteWait { n: 1 });
+        assert_eq!(state.register(System::Ym2612, 0x2A), Some(0x7F));
+        assert_eq!(snapshot.register(System::Ym2612, 0x2A), None);
+        assert_ne!(state, snapshot);
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+
+use crate::chip_state::ChipId;
+use crate::command::Command;
+use crate::systems::System;
+use crate::vgmfile::VgmFile;
+
+/// Bit of the clock fields declaring a second chip
+const DUAL_CHIP_FLAG: u32 = 0x40000000;
+/// Bits of the clock fields holding the clock
+const CLOCK_MASK: u32 = 0x3FFFFFFF;
+
+/// Commands sent to a chip, `streams` counts the DAC streams set up to write to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChipUsage {
+    pub chip: ChipId,
+    pub writes: usize,
+    pub streams: usize,
+}
+
+/// Chips used by the commands compared to the clocks of the header
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChipUsageDiff {
+    /// Chips with a clock that receive nothing
+    pub unused: Vec<ChipId>,
+    /// Chips receiving commands without a clock
+    pub undeclared: Vec<ChipUsage>,
+}
+
+/// Changes made by `VgmFile::strip_unused_chips`
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChipStripReport {
+    /// Chips removed from the header, the second chip alone when only the dual chip flag was
+    /// cleared
+    pub removed: Vec<ChipId>,
+    /// Indexes of the commands writing to a chip without a clock
+    pub orphan_commands: Vec<usize>,
+}
+
+/// Chips of the header, the K052539 and ES5505 share the clocks of the K051649 and ES5506 and
+/// are written by the same commands
+fn header_systems() -> impl Iterator<Item = System> {
+    System::ALL
+        .into_iter()
+        .filter(|system| !matches!(system, System::K052539 | System::Es5505))
+}
+
+/// Order of the chips in the header
+fn header_order(chip: &ChipId) -> (usize, u8) {
+    let index = System::ALL
+        .iter()
+        .position(|system| *system == chip.system)
+        .unwrap_or(System::ALL.len());
+    (index, chip.instance)
+}
+
+/// Chip written by a DAC stream, bit 7 of the chip type selects the second chip
+fn stream_chip(chip_type: u8) -> Option<ChipId> {
+    Some(ChipId {
+        system: System::from_chip_type(chip_type)?,
+        instance: chip_type >> 7,
+    })
+}
+
+impl VgmFile {
+    /// Before 1.10 the YM2612 and YM2151 use the clock of the YM2413
+    fn shares_ym2413_clock(&self, system: &System) -> bool {
+        self.header.version < 110 && matches!(system, System::Ym2612 | System::Ym2151)
+    }
+
+    /// Chip owning the header clock of a chip
+    fn clock_owner(&self, chip: &ChipId) -> ChipId {
+        if self.shares_ym2413_clock(&chip.system) {
+            ChipId {
+                system: System::Ym2413,
+                instance: chip.instance,
+            }
+        } else {
+            chip.clone()
+        }
+    }
+
+    /// Chips receiving commands, in the order of their clocks in the header
+    pub fn used_chips(&self) -> Vec<ChipUsage> {
+        let mut usage: HashMap<ChipId, ChipUsage> = HashMap::new();
+        for cmd in &self.commands {
+            let (chip, stream) = match *cmd {
+                Command::DACStreamSetupControl { chip_type, .. } => (stream_chip(chip_type), true),
+                _ => (cmd.chip(), false),
+            };
+            let Some(chip) = chip else {
+                continue;
+            };
+            let usage = usage.entry(chip.clone()).or_insert(ChipUsage {
+                chip,
+                writes: 0,
+                streams: 0,
+            });
+            if stream {
+                usage.streams += 1;
+            } else {
+                usage.writes += 1;
+            }
+        }
+
+        let mut usage: Vec<ChipUsage> = usage.into_values().collect();
+        usage.sort_by_key(|usage| header_order(&usage.chip));
+        usage
+    }
+
+    /// Whether the header gives a clock to a chip
+    pub fn has_clock(&self, chip: &ChipId) -> bool {
+        let clock = match chip.system {
+            System::Ym2612 => self.header.effective_ym2612_clock(),
+            System::Ym2151 => self.header.effective_ym2151_clock(),
+            _ => self.header.clock(&chip.system),
+        };
+        clock & CLOCK_MASK != 0 && (chip.instance == 0 || clock & DUAL_CHIP_FLAG != 0)
+    }
+
+    /// Compare the chips receiving commands to the clocks of the header. Before 1.10 the clock
+    /// of the YM2413 is only unused when the YM2612 and YM2151 are not used either.
+    pub fn chip_usage_diff(&self) -> ChipUsageDiff {
+        let used = self.used_chips();
+        let unused = header_systems()
+            .filter(|system| !self.shares_ym2413_clock(system))
+            .flat_map(|system| {
+                [0, 1].map(|instance| ChipId {
+                    system: system.clone(),
+                    instance,
+                })
+            })
+            .filter(|chip| {
+                self.has_clock(chip)
+                    && !used
+                        .iter()
+                        .any(|usage| self.clock_owner(&usage.chip) == *chip)
+            })
+            .collect();
+        let undeclared = used
+            .into_iter()
+            .filter(|usage| !self.has_clock(&usage.chip))
+            .collect();
+
+        ChipUsageDiff { unused, undeclared }
+    }
+
+    /// Remove the clocks of the chips receiving no command, a second chip is removed by clearing
+    /// the dual chip flag. The first chip is kept when only the second one is used.
+    /// The commands left writing to chips without a clock are reported.
+    pub fn strip_unused_chips(&mut self) -> ChipStripReport {
+        let mut report = ChipStripReport::default();
+        let diff = self.chip_usage_diff();
+        for system in header_systems() {
+            let unused = |instance| {
+                diff.unused.contains(&ChipId {
+                    system: system.clone(),
+                    instance,
+                })
+            };
+            // the first chip stays while the second one is used
+            let second = unused(1);
+            let first = unused(0) && (second || self.header.clock(&system) & DUAL_CHIP_FLAG == 0);
+            let clock = self.header.clock_mut(&system);
+            if first {
+                *clock = 0;
+            } else if second {
+                *clock &= !DUAL_CHIP_FLAG;
+            }
+
+            for instance in [(0, first), (1, second)]
+                .into_iter()
+                .filter_map(|(instance, removed)| removed.then_some(instance))
+            {
+                report.removed.push(ChipId {
+                    system: system.clone(),
+                    instance,
+                });
+            }
+        }
+
+        for (index, cmd) in self.commands.iter().enumerate() {
+            let chip = match *cmd {
+                Command::DACStreamSetupControl { chip_type, .. } => stream_chip(chip_type),
+                _ => cmd.chip(),
+            };
+            if chip.is_some_and(|chip| !self.has_clock(&chip)) {
+                report.orphan_commands.push(index);
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip_state::ChipId;
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::systems::System;
+    use crate::vgmfile::VgmFile;
+
+    #[test]
+    fn strip_unused_chips() {
+        let chip = |system, instance| ChipId { system, instance };
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 161,
+                sn76489_clock: 3579545,
+                ym2612_clock: 7670453,
+                nes_apu_clock: 1789772 | 0x40000000,
+                ..Default::default()
+            },
+            commands: vec![
+                Command::YM2612Port0Write {
+                    register: 0x28,
+                    value: 0xF0,
+                },
+                Command::YM2612Port0Write {
+                    register: 0x28,
+                    value: 0x00,
+                },
+                Command::NESAPUWrite {
+                    register: 0x15,
+                    value: 0x0F,
+                },
+                // second GameBoy, no clock
+                Command::GameBoyDMGWrite {
+                    register: 0x80 | 0x16,
+                    value: 0x80,
+                },
+                Command::DACStreamSetupControl {
+                    stream_id: 0,
+                    chip_type: 0x02,
+                    port: 0,
+                    command: 0x2A,
+                },
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let used = vgm.used_chips();
+        let used: Vec<_> = used
+            .iter()
+            .map(|usage| (usage.chip.clone(), usage.writes, usage.streams))
+            .collect();
+        assert_eq!(
+            used,
+            [
+                (chip(System::Ym2612, 0), 2, 1),
+                (chip(System::GameboyDmg, 1), 1, 0),
+                (chip(System::NesApu, 0), 1, 0),
+            ]
+        );
+
+        let diff = vgm.chip_usage_diff();
+        assert_eq!(
+            diff.unused,
+            [chip(System::Sn76489, 0), chip(System::NesApu, 1)]
+        );
+        assert_eq!(diff.undeclared.len(), 1);
+        assert_eq!(diff.undeclared[0].chip, chip(System::GameboyDmg, 1));
+
+        let report = vgm.strip_unused_chips();
+        assert_eq!(report.removed, diff.unused);
+        assert_eq!(report.orphan_commands, [3]);
+        assert_eq!(vgm.header.sn76489_clock, 0);
+        assert_eq!(vgm.header.ym2612_clock, 7670453);
+        assert_eq!(vgm.header.nes_apu_clock, 1789772);
+        assert!(vgm.chip_usage_diff().unused.is_empty());
+    }
+
+    #[test]
+    fn shared_clock() {
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 101,
+                ym2413_clock: 7670453,
+                ..Default::default()
+            },
+            commands: vec![Command::YM2612Port0Write {
+                register: 0x28,
+                value: 0xF0,
+            }],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        assert_eq!(vgm.chip_usage_diff(), Default::default());
+        assert!(vgm.strip_unused_chips().removed.is_empty());
+        assert_eq!(vgm.header.ym2413_clock, 7670453);
+
+        vgm.commands.clear();
+        let diff = vgm.chip_usage_diff();
+        assert_eq!(
+            diff.unused,
+            [ChipId {
+                system: System::Ym2413,
+                instance: 0,
+            }]
+        );
+        vgm.strip_unused_chips();
+        assert_eq!(vgm.header.ym2413_clock, 0);
+    }
+}
@@ -2,7 +2,11 @@
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
+use crate::chip_state::ChipId;
+use crate::systems::System;
+
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Command {
     AY8910StereoMask {
         value: u8,
@@ -82,11 +86,14 @@ pub enum Command {
     DataBlock {
         data_type: u8,
         data_size: u32,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_base64"))]
         data: Vec<u8>,
     },
     PCMRAMWrite {
-        offset: u32,
-        data: Vec<u8>,
+        chip_type: u8,
+        read_offset: u32,
+        write_offset: u32,
+        size: u32,
     },
     WaitNSamplesPlus1 {
         n: u8,
@@ -94,9 +101,35 @@ pub enum Command {
     YM2612Port0Address2AWriteWait {
         n: u8,
     },
-    DACStreamControlWrite {
-        register: u8,
-        value: u8,
+    DACStreamSetupControl {
+        stream_id: u8,
+        chip_type: u8,
+        port: u8,
+        command: u8,
+    },
+    DACStreamSetData {
+        stream_id: u8,
+        data_bank_id: u8,
+        step_size: u8,
+        step_base: u8,
+    },
+    DACStreamSetFrequency {
+        stream_id: u8,
+        frequency: u32,
+    },
+    DACStreamStart {
+        stream_id: u8,
+        data_start_offset: u32,
+        length_mode: u8,
+        data_length: u32,
+    },
+    DACStreamStop {
+        stream_id: u8,
+    },
+    DACStreamStartFast {
+        stream_id: u8,
+        block_id: u16,
+        flags: u8,
     },
     AY8910Write {
         register: u8,
@@ -342,24 +375,74 @@ impl Command {
                 data_size,
                 data,
             } => {
-                let mut out_data: Vec<u8> = vec![data_type];
+                let mut out_data: Vec<u8> = vec![0x67, 0x66, data_type];
                 out_data.extend(data_size.to_le_bytes());
                 out_data.extend(data);
                 out_data
             }
-            Command::PCMRAMWrite { offset: _, data: _ } => {
-                panic!("not implemented")
+            Command::PCMRAMWrite {
+                chip_type,
+                read_offset,
+                write_offset,
+                size,
+            } => {
+                let mut rslt = vec![0x68, 0x66, chip_type];
+                rslt.extend(&read_offset.to_le_bytes()[..3]);
+                rslt.extend(&write_offset.to_le_bytes()[..3]);
+                rslt.extend(&size.to_le_bytes()[..3]);
+                rslt
             }
 
             Command::WaitNSamplesPlus1 { n } => vec![0x70 + n],
 
             Command::YM2612Port0Address2AWriteWait { n } => vec![0x80 + n],
 
-            Command::DACStreamControlWrite {
-                register: _,
-                value: _,
+            Command::DACStreamSetupControl {
+                stream_id,
+                chip_type,
+                port,
+                command,
+            } => {
+                vec![0x90, stream_id, chip_type, port, command]
+            }
+            Command::DACStreamSetData {
+                stream_id,
+                data_bank_id,
+                step_size,
+                step_base,
             } => {
-                panic!("not implemented")
+                vec![0x91, stream_id, data_bank_id, step_size, step_base]
+            }
+            Command::DACStreamSetFrequency {
+                stream_id,
+                frequency,
+            } => {
+                let mut rslt = vec![0x92, stream_id];
+                rslt.extend(frequency.to_le_bytes());
+                rslt
+            }
+            Command::DACStreamStart {
+                stream_id,
+                data_start_offset,
+                length_mode,
+                data_length,
+            } => {
+                let mut rslt = vec![0x93, stream_id];
+                rslt.extend(data_start_offset.to_le_bytes());
+                rslt.push(length_mode);
+                rslt.extend(data_length.to_le_bytes());
+                rslt
+            }
+            Command::DACStreamStop { stream_id } => {
+                vec![0x94, stream_id]
+            }
+            Command::DACStreamStartFast {
+                stream_id,
+                block_id,
+                flags,
+            } => {
+                let temp = block_id.to_le_bytes();
+                vec![0x95, stream_id, temp[0], temp[1], flags]
             }
 
             Command::AY8910Write { register, value } => {
@@ -420,7 +503,7 @@ impl Command {
             }
             Command::MultiPCMSetBank { channel, offset } => {
                 let temp = offset.to_le_bytes();
-                vec![0xC3, temp[0], temp[1], channel]
+                vec![0xC3, channel, temp[0], temp[1]]
             }
 
             Command::QSoundWrite { register, value } => {
@@ -467,20 +550,20 @@ impl Command {
                 vec![0xD2, port, register, value]
             }
             Command::K054539Write { register, value } => {
-                let temp = register.to_le_bytes();
+                let temp = register.to_be_bytes();
                 vec![0xD3, temp[0], temp[1], value]
             }
             Command::C140Write { register, value } => {
-                let temp = register.to_le_bytes();
+                let temp = register.to_be_bytes();
                 vec![0xD4, temp[0], temp[1], value]
             }
 
             Command::ES5503Write { register, value } => {
-                let temp = register.to_le_bytes();
+                let temp = register.to_be_bytes();
                 vec![0xD5, temp[0], temp[1], value]
             }
             Command::ES5506Write16 { register, value } => {
-                let temp = value.to_le_bytes();
+                let temp = value.to_be_bytes();
                 vec![0xD6, register, temp[0], temp[1]]
             }
             Command::SeekPCM { offset } => {
@@ -490,8 +573,8 @@ impl Command {
             }
             Command::C352Write { register, value } => {
                 let mut rslt = vec![0xE1];
-                rslt.extend(register.to_le_bytes());
-                rslt.extend(value.to_le_bytes());
+                rslt.extend(register.to_be_bytes());
+                rslt.extend(value.to_be_bytes());
                 rslt
             }
 
@@ -503,7 +586,7 @@ impl Command {
                 rslt
             }
             Command::RF5C164WriteOffset { offset, value } => {
-                let mut rslt = vec![0xC1];
+                let mut rslt = vec![0xC2];
                 rslt.extend(offset.to_le_bytes());
                 rslt.extend(value.to_le_bytes());
                 rslt
@@ -662,18 +745,23 @@ impl Command {
                 bytes.get_u8();
                 let data_type = bytes.get_u8();
                 let data_size = bytes.get_u32_le();
+                // bit 31 of the size selects the second chip for the ROM dumps
+                let data_length = (data_size & 0x7FFFFFFF) as usize;
                 Command::DataBlock {
                     data_type,
                     data_size,
-                    data: (0..data_size as usize).map(|_| bytes.get_u8()).collect(),
+                    data: (0..data_length).map(|_| bytes.get_u8()).collect(),
                 }
             }
             0x68 => {
                 // handle PCM RAM write command
-                // TODO: not done
+                // skip compatibility arg (0x66)
+                bytes.get_u8();
                 Command::PCMRAMWrite {
-                    offset: 0,
-                    data: vec![],
+                    chip_type: bytes.get_u8(),
+                    read_offset: bytes.get_uint_le(3) as u32,
+                    write_offset: bytes.get_uint_le(3) as u32,
+                    size: bytes.get_uint_le(3) as u32,
                 }
             }
             cmd @ 0x70..=0x7F => {
@@ -684,14 +772,36 @@ impl Command {
                 // handle YM2612 port 0 address 2A write command
                 Command::YM2612Port0Address2AWriteWait { n: cmd - 0x80 }
             }
-            0x90..=0x95 => {
-                // handle DAC Stream Control Write command
-                // TODO: not done
-                Command::DACStreamControlWrite {
-                    register: 0,
-                    value: 0,
-                }
-            }
+            0x90 => Command::DACStreamSetupControl {
+                stream_id: bytes.get_u8(),
+                chip_type: bytes.get_u8(),
+                port: bytes.get_u8(),
+                command: bytes.get_u8(),
+            },
+            0x91 => Command::DACStreamSetData {
+                stream_id: bytes.get_u8(),
+                data_bank_id: bytes.get_u8(),
+                step_size: bytes.get_u8(),
+                step_base: bytes.get_u8(),
+            },
+            0x92 => Command::DACStreamSetFrequency {
+                stream_id: bytes.get_u8(),
+                frequency: bytes.get_u32_le(),
+            },
+            0x93 => Command::DACStreamStart {
+                stream_id: bytes.get_u8(),
+                data_start_offset: bytes.get_u32_le(),
+                length_mode: bytes.get_u8(),
+                data_length: bytes.get_u32_le(),
+            },
+            0x94 => Command::DACStreamStop {
+                stream_id: bytes.get_u8(),
+            },
+            0x95 => Command::DACStreamStartFast {
+                stream_id: bytes.get_u8(),
+                block_id: bytes.get_u16_le(),
+                flags: bytes.get_u8(),
+            },
             0xA0 => {
                 // handle AY8910 write command
                 Command::AY8910Write {
@@ -749,31 +859,31 @@ impl Command {
                     value: bytes.get_u8(),
                 }
             }
-            0xB7 => Command::HuC6280Write {
+            0xB7 => Command::OKIM6258Write {
                 register: bytes.get_u8(),
                 value: bytes.get_u8(),
             },
-            0xB8 => Command::K053260Write {
+            0xB8 => Command::OKIM6295Write {
                 register: bytes.get_u8(),
                 value: bytes.get_u8(),
             },
-            0xB9 => Command::PokeyWrite {
+            0xB9 => Command::HuC6280Write {
                 register: bytes.get_u8(),
                 value: bytes.get_u8(),
             },
-            0xBA => Command::WonderSwanWrite {
+            0xBA => Command::K053260Write {
                 register: bytes.get_u8(),
                 value: bytes.get_u8(),
             },
-            0xBB => Command::SAA1099Write {
+            0xBB => Command::PokeyWrite {
                 register: bytes.get_u8(),
                 value: bytes.get_u8(),
             },
-            0xBC => Command::ES5506Write {
+            0xBC => Command::WonderSwanWrite {
                 register: bytes.get_u8(),
                 value: bytes.get_u8(),
             },
-            0xBD => Command::GA20Write {
+            0xBD => Command::SAA1099Write {
                 register: bytes.get_u8(),
                 value: bytes.get_u8(),
             },
@@ -802,42 +912,30 @@ impl Command {
                 offset: bytes.get_u16_le(),
             },
             0xC4 => {
-                // TODO: weird stuff with the data
-                let value = bytes.get_u16_le();
+                // value is stored MSB first, register last
+                let value = bytes.get_u16();
                 Command::QSoundWrite {
                     register: bytes.get_u8(),
                     value,
                 }
             }
-            0xC5 => {
-                // TODO: weird stuff with the data
-                //let value = bytes.get_u16_le();
-                Command::SCSPWrite {
-                    offset: bytes.get_u16_le(),
-                    value: bytes.get_u8(),
-                }
-            }
-            0xC6 => {
-                // TODO: check
-                Command::WonderSwanWrite16 {
-                    offset: bytes.get_u16_le(),
-                    value: bytes.get_u8(),
-                }
-            }
-            0xC7 => {
-                // TODO: check
-                Command::VSUWrite {
-                    offset: bytes.get_u16_le(),
-                    value: bytes.get_u8(),
-                }
-            }
-            0xC8 => {
-                // TODO: check
-                Command::X1010Write {
-                    offset: bytes.get_u16_le(),
-                    value: bytes.get_u8(),
-                }
-            }
+            // offsets of 0xC5 - 0xC8 are stored MSB first
+            0xC5 => Command::SCSPWrite {
+                offset: bytes.get_u16(),
+                value: bytes.get_u8(),
+            },
+            0xC6 => Command::WonderSwanWrite16 {
+                offset: bytes.get_u16(),
+                value: bytes.get_u8(),
+            },
+            0xC7 => Command::VSUWrite {
+                offset: bytes.get_u16(),
+                value: bytes.get_u8(),
+            },
+            0xC8 => Command::X1010Write {
+                offset: bytes.get_u16(),
+                value: bytes.get_u8(),
+            },
             0xD0 => Command::YMF278BWrite {
                 port: bytes.get_u8(),
                 register: bytes.get_u8(),
@@ -853,30 +951,459 @@ impl Command {
                 register: bytes.get_u8(),
                 value: bytes.get_u8(),
             },
+            // registers of 0xD3 - 0xD5 are stored MSB first
             0xD3 => Command::K054539Write {
-                register: bytes.get_u16_le(),
+                register: bytes.get_u16(),
                 value: bytes.get_u8(),
             },
             0xD4 => Command::C140Write {
-                register: bytes.get_u16_le(),
+                register: bytes.get_u16(),
                 value: bytes.get_u8(),
             },
             0xD5 => Command::ES5503Write {
-                register: bytes.get_u16_le(),
+                register: bytes.get_u16(),
                 value: bytes.get_u8(),
             },
             0xD6 => Command::ES5506Write16 {
                 register: bytes.get_u8(),
-                value: bytes.get_u16_le(),
+                value: bytes.get_u16(),
             },
             0xE0 => Command::SeekPCM {
                 offset: bytes.get_u32_le(),
             },
             0xE1 => Command::C352Write {
-                register: bytes.get_u16_le(),
-                value: bytes.get_u16_le(),
+                register: bytes.get_u16(),
+                value: bytes.get_u16(),
             },
             cmd => Err(cmd)?,
         })
     }
+
+    /// Samples waited after the command, including the wait of the 0x8n YM2612 writes
+    pub fn wait_samples(&self) -> u32 {
+        match self {
+            Command::WaitNSamples { n } => *n as u32,
+            Command::Wait735Samples => 735,
+            Command::Wait882Samples => 882,
+            Command::WaitNSamplesPlus1 { n } => *n as u32 + 1,
+            Command::YM2612Port0Address2AWriteWait { n } => *n as u32,
+            _ => 0,
+        }
+    }
+
+    /// Number of bytes taken by the command once encoded
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Command::Wait735Samples
+            | Command::Wait882Samples
+            | Command::EndOfSoundData
+            | Command::WaitNSamplesPlus1 { .. }
+            | Command::YM2612Port0Address2AWriteWait { .. } => 1,
+
+            Command::AY8910StereoMask { .. }
+            | Command::GameGearPSGStereo { .. }
+            | Command::PSGWrite { .. }
+            | Command::DACStreamStop { .. } => 2,
+
+            Command::PWMWrite { .. }
+            | Command::SegaPCMWrite { .. }
+            | Command::MultiPCMSetBank { .. }
+            | Command::QSoundWrite { .. }
+            | Command::SCSPWrite { .. }
+            | Command::WonderSwanWrite16 { .. }
+            | Command::VSUWrite { .. }
+            | Command::X1010Write { .. }
+            | Command::YMF278BWrite { .. }
+            | Command::YMF271Write { .. }
+            | Command::SCC1Write { .. }
+            | Command::K054539Write { .. }
+            | Command::C140Write { .. }
+            | Command::ES5503Write { .. }
+            | Command::ES5506Write16 { .. }
+            | Command::RF5C68WriteOffset { .. }
+            | Command::RF5C164WriteOffset { .. } => 4,
+
+            Command::DACStreamSetupControl { .. }
+            | Command::DACStreamSetData { .. }
+            | Command::DACStreamStartFast { .. }
+            | Command::SeekPCM { .. }
+            | Command::C352Write { .. } => 5,
+
+            Command::DACStreamSetFrequency { .. } => 6,
+            Command::DACStreamStart { .. } => 11,
+            Command::PCMRAMWrite { .. } => 12,
+            Command::DataBlock { data, .. } => 7 + data.len(),
+
+            // every other command is an opcode followed by a register and a value
+            _ => 3,
+        }
+    }
+
+    /// Chip the command writes to, `None` for waits, data blocks and streams
+    pub fn system(&self) -> Option<System> {
+        Some(match self {
+            Command::PSGWrite { .. } | Command::GameGearPSGStereo { .. } => System::Sn76489,
+            Command::YM2413Write { .. } => System::Ym2413,
+            Command::YM2612Port0Write { .. }
+            | Command::YM2612Port1Write { .. }
+            | Command::YM2612Port0Address2AWriteWait { .. } => System::Ym2612,
+            Command::YM2151Write { .. } => System::Ym2151,
+            Command::SegaPCMWrite { .. } => System::SegaPcm,
+            Command::RF5C68Write { .. } | Command::RF5C68WriteOffset { .. } => System::Rf5c68,
+            Command::YM2203Write { .. } => System::Ym2203,
+            Command::YM2608Port0Write { .. } | Command::YM2608Port1Write { .. } => System::Ym2608,
+            Command::YM2610Port0Write { .. } | Command::YM2610Port1Write { .. } => System::Ym2610,
+            Command::YM3812Write { .. } => System::Ym3812,
+            Command::YM3526Write { .. } => System::Ym3526,
+            Command::Y8950Write { .. } => System::Y8950,
+            Command::YMF262Port0Write { .. } | Command::YMF262Port1Write { .. } => System::Ymf262,
+            Command::YMF278BWrite { .. } => System::Ymf278B,
+            Command::YMF271Write { .. } => System::Ymf271,
+            Command::YMZ280BWrite { .. } => System::Ymz280b,
+            Command::RF5C164Write { .. } | Command::RF5C164WriteOffset { .. } => System::Rf5c164,
+            Command::PWMWrite { .. } => System::Pwm,
+            Command::AY8910Write { .. } | Command::AY8910StereoMask { .. } => System::Ay8910,
+            Command::GameBoyDMGWrite { .. } => System::GameboyDmg,
+            Command::NESAPUWrite { .. } => System::NesApu,
+            Command::MultiPCMWrite { .. } | Command::MultiPCMSetBank { .. } => System::MultiPcm,
+            Command::uPD7759Write { .. } => System::Upd7759,
+            Command::OKIM6258Write { .. } => System::Okim6258,
+            Command::K054539Write { .. } => System::K054539,
+            Command::C140Write { .. } => System::C140,
+            Command::OKIM6295Write { .. } => System::Okim6295,
+            Command::SCC1Write { .. } => System::K051649,
+            Command::HuC6280Write { .. } => System::HuC6280,
+            Command::K053260Write { .. } => System::K053260,
+            Command::PokeyWrite { .. } => System::Pokey,
+            Command::QSoundWrite { .. } => System::QSound,
+            Command::SCSPWrite { .. } => System::Scsp,
+            Command::WonderSwanWrite { .. } | Command::WonderSwanWrite16 { .. } => {
+                System::WonderSwan
+            }
+            Command::VSUWrite { .. } => System::Vsu,
+            Command::SAA1099Write { .. } => System::Saa1099,
+            Command::ES5503Write { .. } => System::Es5503,
+            Command::ES5506Write { .. } | Command::ES5506Write16 { .. } => System::Es5506,
+            Command::C352Write { .. } => System::C352,
+            Command::X1010Write { .. } => System::X1_010,
+            Command::GA20Write { .. } => System::Ga20,
+            Command::WaitNSamples { .. }
+            | Command::Wait735Samples
+            | Command::Wait882Samples
+            | Command::WaitNSamplesPlus1 { .. }
+            | Command::EndOfSoundData
+            | Command::DataBlock { .. }
+            | Command::PCMRAMWrite { .. }
+            | Command::SeekPCM { .. }
+            | Command::DACStreamSetupControl { .. }
+            | Command::DACStreamSetData { .. }
+            | Command::DACStreamSetFrequency { .. }
+            | Command::DACStreamStart { .. }
+            | Command::DACStreamStop { .. }
+            | Command::DACStreamStartFast { .. } => return None,
+        })
+    }
+
+    /// Chip the command writes to, including the instance of a dual chip setup. The second chip
+    /// is selected by bit 7 of the first parameter byte, of the high byte of the offset for
+    /// the SegaPCM.
+    pub fn chip(&self) -> Option<ChipId> {
+        let system = self.system()?;
+        let bytes = self.clone().to_bytes();
+        let select = match bytes[0] {
+            0xC0 => bytes[2],
+            0xA0 | 0xB0..=0xBF | 0xC3 | 0xC5..=0xC8 | 0xD0..=0xD6 | 0xE1 => bytes[1],
+            _ => 0,
+        };
+
+        Some(ChipId {
+            system,
+            instance: select >> 7,
+        })
+    }
+
+    /// First VGM version defining the command, in the same format as `HeaderData::version`
+    pub fn min_version(&self) -> u32 {
+        match self {
+            Command::GameGearPSGStereo { .. }
+            | Command::PSGWrite { .. }
+            | Command::YM2413Write { .. }
+            | Command::YM2612Port0Write { .. }
+            | Command::YM2612Port1Write { .. }
+            | Command::YM2151Write { .. }
+            | Command::WaitNSamples { .. }
+            | Command::Wait735Samples
+            | Command::Wait882Samples
+            | Command::EndOfSoundData => 100,
+
+            Command::DataBlock { .. }
+            | Command::WaitNSamplesPlus1 { .. }
+            | Command::YM2612Port0Address2AWriteWait { .. }
+            | Command::SeekPCM { .. } => 150,
+
+            Command::PCMRAMWrite { .. }
+            | Command::DACStreamSetupControl { .. }
+            | Command::DACStreamSetData { .. }
+            | Command::DACStreamSetFrequency { .. }
+            | Command::DACStreamStart { .. }
+            | Command::DACStreamStop { .. }
+            | Command::DACStreamStartFast { .. } => 160,
+
+            Command::AY8910StereoMask { .. } => 171,
+
+            // every chip write is only valid once the chip has a clock in the header
+            _ => self
+                .system()
+                .map(|system| system.min_version())
+                .unwrap_or(100),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+
+    use crate::header::HeaderData;
+    use crate::vgmfile::VgmFile;
+
+    use super::Command;
+
+    /// Parse the bytes of a command and encode it back
+    fn round_trip(bytes: &[u8], expected: Command) {
+        let mut data = Bytes::copy_from_slice(bytes);
+        let parsed = Command::from_bytes(&mut data).unwrap();
+        assert!(
+            data.is_empty(),
+            "{:#04X} left {} bytes",
+            bytes[0],
+            data.len()
+        );
+        assert_eq!(parsed, expected);
+        assert_eq!(parsed.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn data_block() {
+        round_trip(
+            &[0x67, 0x66, 0x8B, 0x03, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03],
+            Command::DataBlock {
+                data_type: 0x8B,
+                data_size: 3,
+                data: vec![0x01, 0x02, 0x03],
+            },
+        );
+        // ROM dump of the second chip
+        round_trip(
+            &[0x67, 0x66, 0x8B, 0x01, 0x00, 0x00, 0x80, 0x01],
+            Command::DataBlock {
+                data_type: 0x8B,
+                data_size: 0x80000001,
+                data: vec![0x01],
+            },
+        );
+    }
+
+    #[test]
+    fn pcm_ram_write() {
+        round_trip(
+            &[
+                0x68, 0x66, 0x01, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09,
+            ],
+            Command::PCMRAMWrite {
+                chip_type: 0x01,
+                read_offset: 0x030201,
+                write_offset: 0x060504,
+                size: 0x090807,
+            },
+        );
+    }
+
+    #[test]
+    fn dac_stream_control() {
+        round_trip(
+            &[0x90, 0x00, 0x02, 0x00, 0x2A],
+            Command::DACStreamSetupControl {
+                stream_id: 0,
+                chip_type: 0x02,
+                port: 0x00,
+                command: 0x2A,
+            },
+        );
+        round_trip(
+            &[0x91, 0x00, 0x00, 0x01, 0x00],
+            Command::DACStreamSetData {
+                stream_id: 0,
+                data_bank_id: 0,
+                step_size: 1,
+                step_base: 0,
+            },
+        );
+        round_trip(
+            &[0x92, 0x00, 0x44, 0xAC, 0x00, 0x00],
+            Command::DACStreamSetFrequency {
+                stream_id: 0,
+                frequency: 44100,
+            },
+        );
+        round_trip(
+            &[
+                0x93, 0x00, 0x10, 0x00, 0x00, 0x00, 0x01, 0x20, 0x00, 0x00, 0x00,
+            ],
+            Command::DACStreamStart {
+                stream_id: 0,
+                data_start_offset: 0x10,
+                length_mode: 0x01,
+                data_length: 0x20,
+            },
+        );
+        round_trip(&[0x94, 0x00], Command::DACStreamStop { stream_id: 0 });
+        round_trip(
+            &[0x95, 0x00, 0x02, 0x01, 0x00],
+            Command::DACStreamStartFast {
+                stream_id: 0,
+                block_id: 0x0102,
+                flags: 0x00,
+            },
+        );
+    }
+
+    #[test]
+    fn chip_writes_b7_bf() {
+        let (register, value) = (0x12, 0x34);
+        let writes = [
+            Command::OKIM6258Write { register, value },
+            Command::OKIM6295Write { register, value },
+            Command::HuC6280Write { register, value },
+            Command::K053260Write { register, value },
+            Command::PokeyWrite { register, value },
+            Command::WonderSwanWrite { register, value },
+            Command::SAA1099Write { register, value },
+            Command::ES5506Write { register, value },
+            Command::GA20Write { register, value },
+        ];
+        for (opcode, command) in (0xB7..=0xBF).zip(writes) {
+            round_trip(&[opcode, register, value], command);
+        }
+    }
+
+    #[test]
+    fn offset_writes() {
+        round_trip(
+            &[0xC1, 0x34, 0x12, 0x56],
+            Command::RF5C68WriteOffset {
+                offset: 0x1234,
+                value: 0x56,
+            },
+        );
+        round_trip(
+            &[0xC2, 0x34, 0x12, 0x56],
+            Command::RF5C164WriteOffset {
+                offset: 0x1234,
+                value: 0x56,
+            },
+        );
+        round_trip(
+            &[0xC3, 0x05, 0x34, 0x12],
+            Command::MultiPCMSetBank {
+                channel: 0x05,
+                offset: 0x1234,
+            },
+        );
+    }
+
+    #[test]
+    fn big_endian_operands() {
+        round_trip(
+            &[0xC4, 0x12, 0x34, 0x56],
+            Command::QSoundWrite {
+                register: 0x56,
+                value: 0x1234,
+            },
+        );
+        round_trip(
+            &[0xC5, 0x12, 0x34, 0x56],
+            Command::SCSPWrite {
+                offset: 0x1234,
+                value: 0x56,
+            },
+        );
+        round_trip(
+            &[0xC6, 0x12, 0x34, 0x56],
+            Command::WonderSwanWrite16 {
+                offset: 0x1234,
+                value: 0x56,
+            },
+        );
+        round_trip(
+            &[0xC7, 0x12, 0x34, 0x56],
+            Command::VSUWrite {
+                offset: 0x1234,
+                value: 0x56,
+            },
+        );
+        round_trip(
+            &[0xC8, 0x12, 0x34, 0x56],
+            Command::X1010Write {
+                offset: 0x1234,
+                value: 0x56,
+            },
+        );
+        round_trip(
+            &[0xD3, 0x12, 0x34, 0x56],
+            Command::K054539Write {
+                register: 0x1234,
+                value: 0x56,
+            },
+        );
+        round_trip(
+            &[0xD4, 0x12, 0x34, 0x56],
+            Command::C140Write {
+                register: 0x1234,
+                value: 0x56,
+            },
+        );
+        round_trip(
+            &[0xD5, 0x12, 0x34, 0x56],
+            Command::ES5503Write {
+                register: 0x1234,
+                value: 0x56,
+            },
+        );
+        round_trip(
+            &[0xD6, 0x12, 0x34, 0x56],
+            Command::ES5506Write16 {
+                register: 0x12,
+                value: 0x3456,
+            },
+        );
+        round_trip(
+            &[0xE1, 0x12, 0x34, 0x56, 0x78],
+            Command::C352Write {
+                register: 0x1234,
+                value: 0x5678,
+            },
+        );
+    }
+
+    #[test]
+    fn file_without_gd3() {
+        let header = HeaderData {
+            version: 150,
+            vgm_data_offset: 0x0C,
+            ..Default::default()
+        };
+        let mut bytes = BytesMut::new();
+        header.to_bytes(&mut bytes);
+        bytes.put(&[0x62, 0x50, 0x9F, 0x66][..]);
+
+        let vgm = VgmFile::from_bytes(&mut Bytes::from(bytes.to_vec())).unwrap();
+        assert_eq!(
+            vgm.commands,
+            [Command::Wait735Samples, Command::PSGWrite { value: 0x9F }]
+        );
+        let mut written = BytesMut::new();
+        vgm.to_bytes(&mut written);
+        assert_eq!(written, bytes);
+    }
 }
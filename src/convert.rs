@@ -0,0 +1,545 @@
+use crate::command::Command;
+use crate::errors::LibError;
+use crate::header::{ExtraHeaderData, HeaderData};
+use crate::systems::System;
+use crate::vgmfile::VgmFile;
+
+/// Versions of the VGM specification that can be written
+pub const SUPPORTED_VERSIONS: [u32; 10] = [100, 101, 110, 150, 151, 160, 161, 170, 171, 172];
+
+/// Everything that changed or got lost while converting a file to another version
+#[derive(Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConversionReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// Chips whose clock was cleared, either because the target version has no field for them
+    /// or because they could not share the YM2413 clock of a pre-1.10 file
+    pub removed_chips: Vec<System>,
+    /// Header fields other than chip clocks that were cleared
+    pub removed_header_fields: Vec<String>,
+    /// Commands that do not exist in the target version, with their index in the original command list
+    pub removed_commands: Vec<(usize, Command)>,
+    /// Number of commands re-expressed with older commands without changing the playback
+    pub rewritten_commands: usize,
+}
+
+impl ConversionReport {
+    pub fn is_lossless(&self) -> bool {
+        self.removed_chips.is_empty()
+            && self.removed_header_fields.is_empty()
+            && self.removed_commands.is_empty()
+    }
+}
+
+/// Size of the main header for a version, without the extra header
+pub fn header_size(version: u32) -> usize {
+    match version {
+        0..=150 => 0x40,
+        151..=160 => 0x80,
+        161..=170 => 0xC0,
+        _ => 0x100,
+    }
+}
+
+fn clear_field<T: Default + PartialEq>(
+    field: &mut T,
+    name: &str,
+    min_version: u32,
+    target: u32,
+    report: &mut ConversionReport,
+) {
+    if target < min_version && *field != T::default() {
+        *field = T::default();
+        report.removed_header_fields.push(name.to_string());
+    }
+}
+
+/// Set the offsets of a non empty extra header written right after the main header, returns its size
+fn layout_extra_header(extra_header: &mut ExtraHeaderData) -> usize {
+    let clocks_size = if extra_header.chip_clock_entries.is_empty() {
+        0
+    } else {
+        1 + 5 * extra_header.chip_clock_entries.len()
+    };
+    let volumes_size = if extra_header.chip_volume_entries.is_empty() {
+        0
+    } else {
+        1 + 4 * extra_header.chip_volume_entries.len()
+    };
+
+    // offsets are relative to their own field, clocks go first
+    extra_header.header_size = 0x0C;
+    extra_header.chip_clock_offset = if clocks_size == 0 { 0 } else { 0x0C - 0x04 };
+    extra_header.chip_vol_offset = if volumes_size == 0 {
+        0
+    } else {
+        (0x0C + clocks_size - 0x08) as u32
+    };
+
+    0x0C + clocks_size + volumes_size
+}
+
+impl VgmFile {
+    /// Rewrite the file so that it follows the given version of the specification.
+    /// Upgrading is lossless, downgrading removes the chips, header fields and commands that
+    /// did not exist yet, everything that could not be kept is listed in the report.
+    pub fn convert_to_version(&mut self, target: u32) -> Result<ConversionReport, LibError> {
+        if !SUPPORTED_VERSIONS.contains(&target) {
+            return Err(LibError::UnsupportedVgmVersion { version: target });
+        }
+
+        // the loop follows its command, a loop offset inside a command can't be kept
+        if self.loop_index.is_none() {
+            self.loop_index = self.resolve_loop_index()?;
+        }
+
+        let mut report = ConversionReport {
+            from_version: self.header.version,
+            to_version: target,
+            ..Default::default()
+        };
+
+        // YM2612 and YM2151 move in and out of the YM2413 clock
+        if self.header.version < 110 && target >= 110 {
+            self.normalize_legacy_header();
+        } else if self.header.version >= 110 && target < 110 {
+            fold_legacy_clocks(&mut self.header, &mut report);
+        }
+
+        strip_header_fields(&mut self.header, target, &mut report);
+
+        let commands = std::mem::take(&mut self.commands);
+        let (commands, loop_index) =
+            convert_commands(commands, self.loop_index, target, &mut report);
+        self.commands = commands;
+        self.loop_index = loop_index;
+
+        self.header.version = target;
+        let header_end = header_size(target);
+        let extra_header = &mut self.header.extra_header;
+        if target >= 170
+            && !(extra_header.chip_clock_entries.is_empty()
+                && extra_header.chip_volume_entries.is_empty())
+        {
+            let extra_header_size = layout_extra_header(extra_header);
+            self.header.extra_header_offset = (header_end - 0xBC) as u32;
+            self.header.vgm_data_offset = (header_end + extra_header_size - 0x34) as u32;
+        } else {
+            self.header.extra_header = ExtraHeaderData::default();
+            self.header.extra_header_offset = 0;
+            self.header.vgm_data_offset = if target < 150 {
+                0
+            } else {
+                (header_end - 0x34) as u32
+            };
+        }
+
+        self.update_offsets();
+
+        Ok(report)
+    }
+}
+
+/// Before 1.10 the YM2413, YM2612 and YM2151 share a single clock, only one of them can be kept
+/// unless they run at the same frequency.
+fn fold_legacy_clocks(header: &mut HeaderData, report: &mut ConversionReport) {
+    let mut shared_clock = 0;
+    for system in [System::Ym2413, System::Ym2612, System::Ym2151] {
+        let clock = header.clock(&system);
+        if clock == 0 {
+            continue;
+        }
+
+        if shared_clock == 0 {
+            shared_clock = clock;
+        } else if clock != shared_clock {
+            report.removed_chips.push(system);
+        }
+    }
+
+    header.ym2413_clock = shared_clock;
+    header.ym2612_clock = 0;
+    header.ym2151_clock = 0;
+}
+
+fn strip_header_fields(header: &mut HeaderData, target: u32, report: &mut ConversionReport) {
+    for system in System::ALL {
+        // shared clock fields are reported once
+        if matches!(system, System::K052539 | System::Es5505) {
+            continue;
+        }
+
+        let clock = header.clock_mut(&system);
+        if target < system.min_version() && *clock != 0 {
+            *clock = 0;
+            report.removed_chips.push(system);
+        }
+    }
+
+    // the legacy PSG values don't need to be written
+    if target < 110
+        && (header.sn76489_feedback, header.sn76489_shift_register_width) == (0x0009, 16)
+    {
+        header.sn76489_feedback = 0;
+        header.sn76489_shift_register_width = 0;
+    }
+
+    clear_field(&mut header.rate, "rate", 101, target, report);
+    clear_field(
+        &mut header.sn76489_feedback,
+        "sn76489_feedback",
+        110,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.sn76489_shift_register_width,
+        "sn76489_shift_register_width",
+        110,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.sn76489_flags,
+        "sn76489_flags",
+        151,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.spcm_interface,
+        "spcm_interface",
+        151,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.ay8910_chip_type,
+        "ay8910_chip_type",
+        151,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.ay8910_flags,
+        "ay8910_flags",
+        151,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.ym2203_ay8910_flags,
+        "ym2203_ay8910_flags",
+        151,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.ym2608_ay8910_flags,
+        "ym2608_ay8910_flags",
+        151,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.loop_modifier,
+        "loop_modifier",
+        151,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.volume_modifier,
+        "volume_modifier",
+        160,
+        target,
+        report,
+    );
+    clear_field(&mut header.loop_base, "loop_base", 160, target, report);
+    clear_field(
+        &mut header.okim6258_flags,
+        "okim6258_flags",
+        161,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.k054539_flags,
+        "k054539_flags",
+        161,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.c140_chip_type,
+        "c140_chip_type",
+        161,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.es5503_nb_channels,
+        "es5503_nb_channels",
+        171,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.es5505_es5506_nb_channels,
+        "es5505_es5506_nb_channels",
+        171,
+        target,
+        report,
+    );
+    clear_field(
+        &mut header.c352_clock_divider,
+        "c352_clock_divider",
+        171,
+        target,
+        report,
+    );
+
+    let extra_header = &header.extra_header;
+    if target < 170
+        && !(extra_header.chip_clock_entries.is_empty()
+            && extra_header.chip_volume_entries.is_empty())
+    {
+        report
+            .removed_header_fields
+            .push("extra_header".to_string());
+    }
+}
+
+/// Drop or rewrite the commands that don't exist in the target version.
+/// Returns the new commands and the new index of the loop command.
+fn convert_commands(
+    commands: Vec<Command>,
+    loop_index: Option<usize>,
+    target: u32,
+    report: &mut ConversionReport,
+) -> (Vec<Command>, Option<usize>) {
+    let mut new_commands = Vec::with_capacity(commands.len());
+    let mut new_loop_index = None;
+
+    // YM2612 PCM bank, used to turn 0x8n into plain DAC writes before 1.50
+    let mut pcm_bank: Vec<u8> = vec![];
+    let mut pcm_pos = 0;
+
+    for (index, cmd) in commands.into_iter().enumerate() {
+        if Some(index) == loop_index {
+            new_loop_index = Some(new_commands.len());
+        }
+
+        let chip_removed = cmd
+            .system()
+            .is_some_and(|system| report.removed_chips.contains(&system));
+
+        match &cmd {
+            Command::DataBlock {
+                data_type: 0x00,
+                data,
+                ..
+            } => pcm_bank.extend(data),
+            Command::SeekPCM { offset } => pcm_pos = *offset as usize,
+            _ => {}
+        }
+
+        if !chip_removed && cmd.min_version() <= target {
+            if let Command::YM2612Port0Address2AWriteWait { .. } = cmd {
+                pcm_pos += 1;
+            }
+            new_commands.push(cmd);
+            continue;
+        }
+
+        match cmd {
+            // the wait of a 0x8n outlives its YM2612 write
+            Command::YM2612Port0Address2AWriteWait { n } if chip_removed => {
+                if n != 0 {
+                    new_commands.push(Command::WaitNSamples { n: n as u16 });
+                }
+                pcm_pos += 1;
+                report.removed_commands.push((index, cmd));
+            }
+            _ if chip_removed => report.removed_commands.push((index, cmd)),
+            Command::WaitNSamplesPlus1 { n } => {
+                new_commands.push(Command::WaitNSamples { n: n as u16 + 1 });
+                report.rewritten_commands += 1;
+            }
+            Command::YM2612Port0Address2AWriteWait { n } => {
+                new_commands.push(Command::YM2612Port0Write {
+                    register: 0x2A,
+                    value: pcm_bank.get(pcm_pos).copied().unwrap_or(0x80),
+                });
+                if n != 0 {
+                    new_commands.push(Command::WaitNSamples { n: n as u16 });
+                }
+                pcm_pos += 1;
+                report.rewritten_commands += 1;
+            }
+            // only used by the 0x8n commands, which were all rewritten
+            Command::SeekPCM { .. }
+            | Command::DataBlock {
+                data_type: 0x00, ..
+            } => report.rewritten_commands += 1,
+            cmd => report.removed_commands.push((index, cmd)),
+        }
+    }
+
+    (new_commands, new_loop_index)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+
+    use crate::command::Command;
+    use crate::errors::LibError;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::systems::System;
+    use crate::vgmfile::VgmFile;
+
+    fn vgm_171() -> VgmFile {
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 171,
+                vgm_data_offset: 0xCC,
+                sn76489_clock: 3579545,
+                sn76489_feedback: 0x0009,
+                sn76489_shift_register_width: 16,
+                ym2612_clock: 7670453,
+                ym2203_clock: 3993600,
+                ..Default::default()
+            },
+            commands: vec![
+                Command::DataBlock {
+                    data_type: 0x00,
+                    data_size: 4,
+                    data: vec![0x10, 0x20, 0x30, 0x40],
+                },
+                Command::SeekPCM { offset: 1 },
+                Command::YM2612Port0Write {
+                    register: 0x2B,
+                    value: 0x80,
+                },
+                Command::YM2203Write {
+                    register: 0x28,
+                    value: 0xF0,
+                },
+                Command::DACStreamStop { stream_id: 0 },
+                Command::YM2612Port0Address2AWriteWait { n: 2 },
+                Command::WaitNSamplesPlus1 { n: 4 },
+                Command::PSGWrite { value: 0x9F },
+                Command::Wait735Samples,
+            ],
+            metadata: VgmMetadata::default(),
+            // loop on the PSG write
+            loop_index: Some(7),
+        };
+        vgm.update_offsets();
+
+        vgm
+    }
+
+    #[test]
+    fn downgrade_101() {
+        let mut vgm = vgm_171();
+        let report = vgm.convert_to_version(101).unwrap();
+
+        assert_eq!(report.removed_chips, vec![System::Ym2203]);
+        assert!(report.removed_header_fields.is_empty());
+        assert_eq!(report.removed_commands.len(), 2);
+        assert_eq!(
+            report.removed_commands[1],
+            (4, Command::DACStreamStop { stream_id: 0 })
+        );
+
+        assert_eq!(vgm.header.ym2413_clock, 7670453);
+        assert_eq!(vgm.header.ym2612_clock, 0);
+        assert_eq!(
+            vgm.commands,
+            vec![
+                Command::YM2612Port0Write {
+                    register: 0x2B,
+                    value: 0x80,
+                },
+                Command::YM2612Port0Write {
+                    register: 0x2A,
+                    value: 0x20,
+                },
+                Command::WaitNSamples { n: 2 },
+                Command::WaitNSamples { n: 5 },
+                Command::PSGWrite { value: 0x9F },
+                Command::Wait735Samples,
+            ]
+        );
+
+        // data starts at 0x40, loop still on the PSG write
+        assert_eq!(vgm.header.loop_offset as usize + 0x1C, 0x40 + 3 * 4);
+
+        let mut buffer = BytesMut::new();
+        vgm.to_bytes(&mut buffer);
+        assert_eq!(buffer.len(), vgm.header.end_of_file_offset as usize + 4);
+
+        let parsed = VgmFile::from_bytes(&mut Bytes::from(buffer.to_vec())).unwrap();
+        assert_eq!(parsed.header.version, 101);
+        assert_eq!(parsed.commands, vgm.commands);
+    }
+
+    #[test]
+    fn upgrade_round_trip() {
+        let mut vgm = vgm_171();
+        vgm.convert_to_version(151).unwrap();
+        let report = vgm.convert_to_version(171).unwrap();
+        assert!(report.is_lossless());
+        assert_eq!(vgm.header.vgm_data_pos(), 0x100);
+        assert_eq!(vgm.header.ym2203_clock, 3993600);
+        assert_eq!(vgm.commands.len(), 8);
+    }
+
+    #[test]
+    fn removed_chip_keeps_waits() {
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 151,
+                ym2413_clock: 3579545,
+                ym2612_clock: 7670453,
+                ..Default::default()
+            },
+            commands: vec![
+                Command::YM2413Write {
+                    register: 0x0E,
+                    value: 0x20,
+                },
+                Command::YM2612Port0Address2AWriteWait { n: 3 },
+                Command::YM2612Port0Address2AWriteWait { n: 0 },
+                Command::Wait735Samples,
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+        vgm.update_offsets();
+        let samples = vgm.total_samples();
+
+        let report = vgm.convert_to_version(101).unwrap();
+        assert_eq!(report.removed_chips, vec![System::Ym2612]);
+        assert_eq!(report.removed_commands.len(), 2);
+        assert_eq!(vgm.commands[1], Command::WaitNSamples { n: 3 });
+        assert_eq!(vgm.commands.len(), 3);
+        assert_eq!(vgm.total_samples(), samples);
+    }
+
+    #[test]
+    fn loop_inside_command() {
+        let mut vgm = vgm_171();
+        vgm.loop_index = None;
+        // second byte of the PSG write
+        vgm.header.loop_offset += 1;
+        assert!(matches!(
+            vgm.convert_to_version(151),
+            Err(LibError::InvalidLoopOffset { .. })
+        ));
+    }
+}
@@ -0,0 +1,1017 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::chip_state::ChipState;
+use crate::command::Command;
+use crate::datablock::DATA_TYPE_YM2612_PCM;
+use crate::errors::LibError;
+use crate::optimize::compact_wait_commands;
+use crate::playback::VGM_SAMPLE_RATE;
+use crate::systems::System;
+use crate::vgmfile::VgmFile;
+
+/// Chip type of the YM2612 in the DAC stream setup command
+pub const STREAM_CHIP_YM2612: u8 = 0x02;
+/// YM2612 DAC data register
+pub const YM2612_DAC_REGISTER: u8 = 0x2A;
+
+/// Length mode of the DAC stream start command counting the writes
+const LENGTH_COMMANDS: u8 = 0x01;
+const LENGTH_MILLISECONDS: u8 = 0x02;
+const LENGTH_END_OF_DATA: u8 = 0x03;
+const LENGTH_REVERSE: u8 = 0x10;
+const LENGTH_LOOP: u8 = 0x80;
+/// Flags of the DAC stream fast start command
+const FAST_LOOP: u8 = 0x01;
+const FAST_REVERSE: u8 = 0x10;
+
+/// Settings of `VgmFile::dac_writes_to_streams`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DacStreamOptions {
+    /// Runs with fewer DAC writes are left alone
+    pub min_writes: usize,
+    /// Longest time between two writes of the same run, in samples
+    pub max_gap: u64,
+}
+
+impl Default for DacStreamOptions {
+    fn default() -> Self {
+        DacStreamOptions {
+            min_writes: 32,
+            max_gap: 64,
+        }
+    }
+}
+
+/// DAC writes replaced by a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DacRun {
+    pub start_sample: u64,
+    pub writes: usize,
+    pub frequency: u32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DacStreamReport {
+    pub stream_id: u8,
+    pub runs: Vec<DacRun>,
+}
+
+fn is_dac_write(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::YM2612Port0Write {
+            register: YM2612_DAC_REGISTER,
+            ..
+        } | Command::YM2612Port0Address2AWriteWait { .. }
+    )
+}
+
+/// Stream id of a DAC stream command
+fn stream_id(cmd: &Command) -> Option<u8> {
+    match *cmd {
+        Command::DACStreamSetupControl { stream_id, .. }
+        | Command::DACStreamSetData { stream_id, .. }
+        | Command::DACStreamSetFrequency { stream_id, .. }
+        | Command::DACStreamStart { stream_id, .. }
+        | Command::DACStreamStop { stream_id }
+        | Command::DACStreamStartFast { stream_id, .. } => Some(stream_id),
+        _ => None,
+    }
+}
+
+/// Lowest stream id not used by the commands, 0xFF stands for every stream in the stop command
+fn free_stream_id(commands: &[Command]) -> Option<u8> {
+    let used: HashSet<u8> = commands.iter().filter_map(stream_id).collect();
+    (0..0xFF).find(|id| !used.contains(id))
+}
+
+/// Settings of a DAC stream being played
+#[derive(Default, Clone, Copy)]
+struct Stream {
+    data_bank_id: u8,
+    frequency: u32,
+    step_size: u8,
+    step_base: u8,
+}
+
+/// Data played by a DAC stream start command
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StreamStart {
+    pub command_index: usize,
+    pub sample: u64,
+    pub stream_id: u8,
+    pub data_bank_id: u8,
+    /// Offset of the first byte in the data bank
+    pub offset: u32,
+    /// Number of bytes read, `step_size` apart
+    pub length: u32,
+    pub step_size: u8,
+    pub frequency: u32,
+}
+
+/// Commands of a file being rebuilt by `VgmFile::expand_dac_streams`
+enum Event {
+    Command(Command),
+    /// DAC write of a stream reading the YM2612 PCM data at an offset
+    Write(u32),
+    Loop,
+}
+
+/// DAC write of an expanded stream, reading a byte of the YM2612 PCM data
+struct StreamWrite {
+    sample: u64,
+    offset: u32,
+}
+
+impl VgmFile {
+    /// Index after the last YM2612 PCM data block, where the stream data is added
+    fn stream_data_index(&self) -> (usize, u32) {
+        let mut index = 0;
+        let mut length = 0;
+        for (position, cmd) in self.commands.iter().enumerate() {
+            if let Command::DataBlock {
+                data_type: DATA_TYPE_YM2612_PCM,
+                data,
+                ..
+            } = cmd
+            {
+                index = position + 1;
+                length += data.len() as u32;
+            }
+        }
+
+        (index, length)
+    }
+
+    /// Runs of YM2612 DAC writes, as `(command_index, sample, value)`, a run does not
+    /// go over the loop point since the stream would not be restarted when looping
+    fn dac_runs(&self, from: usize, options: &DacStreamOptions) -> Vec<Vec<(usize, u64, u8)>> {
+        let mut runs = vec![];
+        let mut run: Vec<(usize, u64, u8)> = vec![];
+        let mut state = ChipState::new();
+        for (index, (sample, _, cmd)) in self.timeline().enumerate() {
+            state.update(cmd);
+            if index < from || !is_dac_write(cmd) {
+                continue;
+            }
+            let Some(value) = state.register(System::Ym2612, YM2612_DAC_REGISTER as u32) else {
+                continue;
+            };
+
+            let split = run
+                .last()
+                .is_some_and(|&(_, last, _)| sample - last > options.max_gap)
+                || self.loop_index.is_some_and(|loop_index| {
+                    run.first()
+                        .is_some_and(|&(first, _, _)| first < loop_index && loop_index <= index)
+                });
+            if split {
+                runs.push(std::mem::take(&mut run));
+            }
+            run.push((index, sample, value as u8));
+        }
+        runs.push(run);
+
+        runs.retain(|run| {
+            run.len() >= options.min_writes.max(2) && run[run.len() - 1].1 > run[0].1
+        });
+        runs
+    }
+
+    /// Replace the runs of YM2612 DAC writes with a DAC stream playing the same bytes at the
+    /// average rate of the run. The bytes of every run go into a new YM2612 PCM data block and
+    /// the file is upgraded to 1.60 if needed.
+    /// The PCM data position is sought back after a run when the 0x8n commands left read from it.
+    pub fn dac_writes_to_streams(
+        &mut self,
+        options: &DacStreamOptions,
+    ) -> Result<DacStreamReport, LibError> {
+        let (data_index, bank_length) = self.stream_data_index();
+        let runs = self.dac_runs(data_index, options);
+        let stream_id = free_stream_id(&self.commands);
+        let mut report = DacStreamReport {
+            stream_id: stream_id.unwrap_or_default(),
+            runs: vec![],
+        };
+        if runs.is_empty() {
+            return Ok(report);
+        }
+        let stream_id = stream_id.ok_or(LibError::NoFreeStreamId)?;
+
+        let mut data = vec![];
+        let mut starts = HashMap::new();
+        let mut removed = HashSet::new();
+        let mut run_ends = HashSet::new();
+        for run in &runs {
+            let span = run[run.len() - 1].1 - run[0].1;
+            let writes = run.len() as u64;
+            let frequency = ((writes - 1) * VGM_SAMPLE_RATE as u64 + span / 2) / span;
+
+            starts.insert(
+                run[0].0,
+                (
+                    bank_length + data.len() as u32,
+                    run.len() as u32,
+                    frequency as u32,
+                ),
+            );
+            data.extend(run.iter().map(|&(_, _, value)| value));
+            removed.extend(run.iter().map(|&(index, _, _)| index));
+            run_ends.insert(run[run.len() - 1].0);
+            report.runs.push(DacRun {
+                start_sample: run[0].1,
+                writes: run.len(),
+                frequency: frequency as u32,
+            });
+        }
+
+        // whether a 0x8n command that is kept reads the PCM data position left by the commands
+        // before it, going back to the loop point at the end
+        let is_pcm_read = |index: usize| {
+            matches!(
+                self.commands[index],
+                Command::YM2612Port0Address2AWriteWait { .. }
+            ) && !removed.contains(&index)
+        };
+        let mut reads_position = vec![false; self.commands.len()];
+        let mut reads = self.loop_index.is_some_and(|loop_index| {
+            (loop_index..self.commands.len())
+                .find(|&index| {
+                    is_pcm_read(index) || matches!(self.commands[index], Command::SeekPCM { .. })
+                })
+                .is_some_and(is_pcm_read)
+        });
+        for index in (0..self.commands.len()).rev() {
+            reads_position[index] = reads;
+            match self.commands[index] {
+                Command::SeekPCM { .. } => reads = false,
+                _ if is_pcm_read(index) => reads = true,
+                _ => {}
+            }
+        }
+
+        let mut commands = Vec::with_capacity(self.commands.len());
+        let mut loop_index = None;
+        let mut frequency = 0;
+        // position in the YM2612 PCM data of the original commands
+        let mut pcm_offset = 0;
+        let mut pcm_skipped = false;
+        let setup = [
+            Command::DataBlock {
+                data_type: DATA_TYPE_YM2612_PCM,
+                data_size: data.len() as u32,
+                data,
+            },
+            Command::DACStreamSetupControl {
+                stream_id,
+                chip_type: STREAM_CHIP_YM2612,
+                port: 0x00,
+                command: YM2612_DAC_REGISTER,
+            },
+            Command::DACStreamSetData {
+                stream_id,
+                data_bank_id: DATA_TYPE_YM2612_PCM,
+                step_size: 1,
+                step_base: 0,
+            },
+        ];
+        let mut setup = Some(setup);
+        for (index, cmd) in self.commands.iter().enumerate() {
+            // the data block is not part of the loop
+            if index == data_index {
+                commands.extend(setup.take().into_iter().flatten());
+            }
+            if Some(index) == self.loop_index {
+                loop_index = Some(commands.len());
+                // the rate at the end of the file is in effect when jumping back
+                frequency = 0;
+            }
+
+            if let Some(&(offset, writes, run_frequency)) = starts.get(&index) {
+                if run_frequency != frequency {
+                    commands.push(Command::DACStreamSetFrequency {
+                        stream_id,
+                        frequency: run_frequency,
+                    });
+                    frequency = run_frequency;
+                }
+                commands.push(Command::DACStreamStart {
+                    stream_id,
+                    data_start_offset: offset,
+                    length_mode: LENGTH_COMMANDS,
+                    data_length: writes,
+                });
+            }
+
+            match cmd {
+                Command::YM2612Port0Address2AWriteWait { n } if removed.contains(&index) => {
+                    if *n > 0 {
+                        commands.push(Command::WaitNSamples { n: *n as u16 });
+                    }
+                    pcm_offset += 1;
+                    pcm_skipped = true;
+                }
+                _ if removed.contains(&index) => {}
+                Command::YM2612Port0Address2AWriteWait { .. } => {
+                    pcm_offset += 1;
+                    commands.push(cmd.clone());
+                }
+                Command::SeekPCM { offset } => {
+                    pcm_offset = *offset;
+                    pcm_skipped = false;
+                    commands.push(cmd.clone());
+                }
+                _ => commands.push(cmd.clone()),
+            }
+            if run_ends.contains(&index) && pcm_skipped && reads_position[index] {
+                commands.push(Command::SeekPCM { offset: pcm_offset });
+                pcm_skipped = false;
+            }
+        }
+        if self
+            .loop_index
+            .is_some_and(|index| index >= self.commands.len())
+        {
+            loop_index = Some(commands.len());
+        }
+
+        self.commands = commands;
+        self.loop_index = loop_index;
+        if self.header.version < 160 {
+            self.convert_to_version(160)?;
+        }
+        self.update_offsets();
+
+        Ok(report)
+    }
+
+    /// Streams that only write the YM2612 DAC from the YM2612 PCM data, they can be played
+    /// with 0x8n commands
+    fn expandable_streams(&self) -> HashSet<u8> {
+        let mut streams = HashSet::new();
+        let mut other_streams = HashSet::new();
+        for cmd in &self.commands {
+            match *cmd {
+                Command::DACStreamSetupControl {
+                    stream_id,
+                    chip_type: STREAM_CHIP_YM2612,
+                    port: 0x00,
+                    command: YM2612_DAC_REGISTER,
+                } => {
+                    streams.insert(stream_id);
+                }
+                Command::DACStreamSetData {
+                    data_bank_id: DATA_TYPE_YM2612_PCM,
+                    ..
+                } => {}
+                Command::DACStreamSetupControl { stream_id, .. }
+                | Command::DACStreamSetData { stream_id, .. } => {
+                    other_streams.insert(stream_id);
+                }
+                _ => {}
+            }
+        }
+
+        &streams - &other_streams
+    }
+
+    /// Every DAC stream start command with the part of its data bank it plays.
+    /// Starts that only move the data position are left out.
+    pub fn dac_stream_starts(&self) -> Vec<StreamStart> {
+        let mut starts = vec![];
+        let mut settings: HashMap<u8, Stream> = HashMap::new();
+        // (offset, length) of the blocks of every bank
+        let mut banks: HashMap<u8, Vec<(u32, u32)>> = HashMap::new();
+        for (command_index, (sample, _, cmd)) in self.timeline().enumerate() {
+            match *cmd {
+                Command::DataBlock {
+                    data_type: data_type @ ..=0x3F,
+                    ref data,
+                    ..
+                } => {
+                    let blocks = banks.entry(data_type).or_default();
+                    let offset = blocks.last().map_or(0, |&(offset, length)| offset + length);
+                    blocks.push((offset, data.len() as u32));
+                }
+                Command::DACStreamSetData {
+                    stream_id,
+                    data_bank_id,
+                    step_size,
+                    step_base,
+                } => {
+                    let stream = settings.entry(stream_id).or_default();
+                    stream.data_bank_id = data_bank_id;
+                    stream.step_size = step_size;
+                    stream.step_base = step_base;
+                }
+                Command::DACStreamSetFrequency {
+                    stream_id,
+                    frequency,
+                } => {
+                    settings.entry(stream_id).or_default().frequency = frequency;
+                }
+                Command::DACStreamStart {
+                    stream_id,
+                    data_start_offset,
+                    length_mode,
+                    data_length,
+                } if data_start_offset != u32::MAX => {
+                    let stream = settings.get(&stream_id).copied().unwrap_or_default();
+                    let step = stream.step_size.max(1) as u32;
+                    let offset = data_start_offset.saturating_add(stream.step_base as u32);
+                    let bank_length = banks
+                        .get(&stream.data_bank_id)
+                        .and_then(|blocks| blocks.last())
+                        .map_or(0, |&(offset, length)| offset + length);
+                    let length = match length_mode & 0x0F {
+                        LENGTH_COMMANDS => data_length,
+                        LENGTH_MILLISECONDS => {
+                            (data_length as u64 * stream.frequency as u64 / 1000) as u32
+                        }
+                        LENGTH_END_OF_DATA => bank_length.saturating_sub(offset).div_ceil(step),
+                        _ => continue,
+                    };
+                    starts.push(StreamStart {
+                        command_index,
+                        sample,
+                        stream_id,
+                        data_bank_id: stream.data_bank_id,
+                        offset,
+                        length,
+                        step_size: step as u8,
+                        frequency: stream.frequency,
+                    });
+                }
+                Command::DACStreamStartFast {
+                    stream_id,
+                    block_id,
+                    ..
+                } => {
+                    let stream = settings.get(&stream_id).copied().unwrap_or_default();
+                    let block = banks
+                        .get(&stream.data_bank_id)
+                        .and_then(|blocks| blocks.get(block_id as usize));
+                    if let Some(&(offset, length)) = block {
+                        starts.push(StreamStart {
+                            command_index,
+                            sample,
+                            stream_id,
+                            data_bank_id: stream.data_bank_id,
+                            offset,
+                            length,
+                            step_size: 1,
+                            frequency: stream.frequency,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        starts
+    }
+
+    /// Offsets read by the streams with their sample, until they are stopped,
+    /// restarted or the file ends. A frequency change applies from the offset the stream reached.
+    fn stream_writes(&self, streams: &HashSet<u8>) -> Vec<StreamWrite> {
+        let mut writes = vec![];
+        let mut settings: HashMap<u8, Stream> = HashMap::new();
+        // sample of the first write and offsets read by each playing stream
+        let mut playing: HashMap<u8, (u64, Vec<u32>, bool)> = HashMap::new();
+        let mut blocks = vec![];
+        let mut bank_length = 0;
+        let total_samples = self.total_samples();
+
+        // writes of a stream before `until`, returns the offsets it has left to read
+        let flush = |writes: &mut Vec<StreamWrite>,
+                     stream_id: u8,
+                     until: u64,
+                     settings: &HashMap<u8, Stream>,
+                     playing: &mut HashMap<u8, (u64, Vec<u32>, bool)>|
+         -> Option<(Vec<u32>, bool)> {
+            let (start, mut offsets, looped) = playing.remove(&stream_id)?;
+            if offsets.is_empty() {
+                return None;
+            }
+            let frequency = settings.get(&stream_id).map_or(0, |s| s.frequency) as u64;
+            let count = match (frequency, looped) {
+                (0, _) => 0,
+                (_, true) => u64::MAX,
+                (_, false) => offsets.len() as u64,
+            };
+            let mut written = 0;
+            while written < count {
+                let sample = start + written * VGM_SAMPLE_RATE as u64 / frequency;
+                if sample >= until {
+                    break;
+                }
+                writes.push(StreamWrite {
+                    sample,
+                    offset: offsets[(written % offsets.len() as u64) as usize],
+                });
+                written += 1;
+            }
+
+            if looped {
+                let position = (written % offsets.len() as u64) as usize;
+                offsets.rotate_left(position);
+            } else {
+                offsets.drain(..written as usize);
+            }
+            (!offsets.is_empty()).then_some((offsets, looped))
+        };
+
+        for (sample, _, cmd) in self.timeline() {
+            let stop_all = *cmd == Command::DACStreamStop { stream_id: 0xFF };
+            if !stop_all && stream_id(cmd).is_some_and(|id| !streams.contains(&id)) {
+                continue;
+            }
+
+            match *cmd {
+                Command::DataBlock {
+                    data_type: DATA_TYPE_YM2612_PCM,
+                    ref data,
+                    ..
+                } => {
+                    blocks.push((bank_length, data.len() as u32));
+                    bank_length += data.len() as u32;
+                }
+                Command::DACStreamSetData {
+                    stream_id,
+                    step_size,
+                    step_base,
+                    ..
+                } => {
+                    let stream = settings.entry(stream_id).or_default();
+                    stream.step_size = step_size;
+                    stream.step_base = step_base;
+                }
+                Command::DACStreamSetFrequency {
+                    stream_id,
+                    frequency,
+                } => {
+                    let left = flush(&mut writes, stream_id, sample, &settings, &mut playing);
+                    settings.entry(stream_id).or_default().frequency = frequency;
+                    if let Some((offsets, looped)) = left {
+                        playing.insert(stream_id, (sample, offsets, looped));
+                    }
+                }
+                Command::DACStreamStop { stream_id: 0xFF } => {
+                    for stream_id in playing.keys().copied().collect::<Vec<_>>() {
+                        flush(&mut writes, stream_id, sample, &settings, &mut playing);
+                    }
+                }
+                Command::DACStreamStop { stream_id } => {
+                    flush(&mut writes, stream_id, sample, &settings, &mut playing);
+                }
+                Command::DACStreamStart {
+                    stream_id,
+                    data_start_offset,
+                    length_mode,
+                    data_length,
+                } => {
+                    let stream = settings.get(&stream_id).copied().unwrap_or_default();
+                    let step = stream.step_size.max(1) as u32;
+                    let start = data_start_offset.saturating_add(stream.step_base as u32);
+                    let length = match length_mode & 0x0F {
+                        LENGTH_COMMANDS => data_length,
+                        LENGTH_MILLISECONDS => {
+                            (data_length as u64 * stream.frequency as u64 / 1000) as u32
+                        }
+                        LENGTH_END_OF_DATA => bank_length.saturating_sub(start).div_ceil(step),
+                        // only moves the data position
+                        _ => continue,
+                    };
+                    let mut offsets: Vec<u32> = (0..length).map(|k| start + k * step).collect();
+                    if length_mode & LENGTH_REVERSE != 0 {
+                        offsets.reverse();
+                    }
+
+                    flush(&mut writes, stream_id, sample, &settings, &mut playing);
+                    playing.insert(stream_id, (sample, offsets, length_mode & LENGTH_LOOP != 0));
+                }
+                Command::DACStreamStartFast {
+                    stream_id,
+                    block_id,
+                    flags,
+                } => {
+                    let Some(&(start, length)) = blocks.get(block_id as usize) else {
+                        continue;
+                    };
+                    let mut offsets: Vec<u32> = (start..start + length).collect();
+                    if flags & FAST_REVERSE != 0 {
+                        offsets.reverse();
+                    }
+
+                    flush(&mut writes, stream_id, sample, &settings, &mut playing);
+                    playing.insert(stream_id, (sample, offsets, flags & FAST_LOOP != 0));
+                }
+                _ => {}
+            }
+        }
+        for stream_id in playing.keys().copied().collect::<Vec<_>>() {
+            flush(
+                &mut writes,
+                stream_id,
+                total_samples,
+                &settings,
+                &mut playing,
+            );
+        }
+
+        // streams started at the same sample keep the order of their start commands
+        writes.sort_by_key(|write| write.sample);
+        writes
+    }
+
+    /// Replace the DAC streams writing the YM2612 DAC from the YM2612 PCM data with
+    /// SeekPCM and 0x8n commands, for players that do not support streams.
+    /// Returns the number of DAC writes added.
+    pub fn expand_dac_streams(&mut self) -> usize {
+        let streams = self.expandable_streams();
+        if streams.is_empty() {
+            return 0;
+        }
+        let stream_writes = self.stream_writes(&streams);
+
+        // writes of the streams go after the commands of the same sample
+        let mut events: Vec<(u64, Event)> = vec![];
+        let mut writes = stream_writes.iter().peekable();
+        for (index, (sample, _, cmd)) in self.timeline().enumerate() {
+            while let Some(write) = writes.next_if(|write| write.sample < sample) {
+                events.push((write.sample, Event::Write(write.offset)));
+            }
+            if Some(index) == self.loop_index {
+                events.push((sample, Event::Loop));
+            }
+
+            let is_wait = cmd.wait_samples() > 0 && cmd.system().is_none();
+            if !is_wait && stream_id(cmd).is_none_or(|id| !streams.contains(&id)) {
+                events.push((sample, Event::Command(cmd.clone())));
+            }
+        }
+        let total_samples = self.total_samples();
+        events.extend(writes.map(|write| (write.sample, Event::Write(write.offset))));
+        if self
+            .loop_index
+            .is_some_and(|index| index >= self.commands.len())
+        {
+            events.push((total_samples, Event::Loop));
+        }
+
+        let mut commands = vec![];
+        let mut loop_index = None;
+        // position read by the next 0x8n of the original file, and the one of the chip
+        let mut expected_offset = 0;
+        let mut pcm_offset = Some(0);
+        for (position, (sample, event)) in events.iter().enumerate() {
+            match event {
+                Event::Loop => {
+                    loop_index = Some(commands.len());
+                    // the chip can be anywhere when jumping back
+                    pcm_offset = None;
+                }
+                Event::Command(Command::SeekPCM { offset }) => {
+                    expected_offset = *offset;
+                    pcm_offset = Some(*offset);
+                    commands.push(Command::SeekPCM { offset: *offset });
+                }
+                Event::Command(Command::YM2612Port0Address2AWriteWait { .. }) => {
+                    if pcm_offset != Some(expected_offset) {
+                        commands.push(Command::SeekPCM {
+                            offset: expected_offset,
+                        });
+                    }
+                    commands.push(Command::YM2612Port0Address2AWriteWait { n: 0 });
+                    expected_offset += 1;
+                    pcm_offset = Some(expected_offset);
+                }
+                Event::Command(cmd) => commands.push(cmd.clone()),
+                Event::Write(offset) => {
+                    if pcm_offset != Some(*offset) {
+                        commands.push(Command::SeekPCM { offset: *offset });
+                    }
+                    commands.push(Command::YM2612Port0Address2AWriteWait { n: 0 });
+                    pcm_offset = Some(offset + 1);
+                }
+            }
+
+            let next_sample = events
+                .get(position + 1)
+                .map_or(total_samples, |(next, _)| *next);
+            let mut wait = next_sample - sample;
+            if !matches!(event, Event::Loop) {
+                if let Some(Command::YM2612Port0Address2AWriteWait { n }) = commands.last_mut() {
+                    *n = wait.min(15) as u8;
+                    wait -= *n as u64;
+                }
+            }
+            commands.extend(compact_wait_commands(wait));
+        }
+
+        self.commands = commands;
+        self.loop_index = loop_index;
+        self.update_offsets();
+
+        stream_writes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::errors::LibError;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::systems::System;
+    use crate::vgmfile::VgmFile;
+
+    use super::DacStreamOptions;
+
+    #[test]
+    fn streams_round_trip() {
+        let key_on = Command::YM2612Port0Write {
+            register: 0x28,
+            value: 0xF0,
+        };
+        let mut commands = vec![];
+        for value in 0..40 {
+            commands.extend([
+                Command::YM2612Port0Write {
+                    register: 0x2A,
+                    value,
+                },
+                Command::WaitNSamples { n: 5 },
+            ]);
+        }
+        commands.insert(20, key_on.clone());
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 150,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+        let total_samples = vgm.total_samples();
+
+        let report = vgm
+            .dac_writes_to_streams(&DacStreamOptions::default())
+            .unwrap();
+        assert_eq!(report.runs.len(), 1);
+        assert_eq!(report.runs[0].writes, 40);
+        assert_eq!(report.runs[0].frequency, 8820);
+        assert_eq!(vgm.header.version, 160);
+        assert_eq!(vgm.total_samples(), total_samples);
+        assert_eq!(
+            vgm.commands[..5],
+            [
+                Command::DataBlock {
+                    data_type: 0x00,
+                    data_size: 40,
+                    data: (0..40).collect(),
+                },
+                Command::DACStreamSetupControl {
+                    stream_id: 0,
+                    chip_type: 0x02,
+                    port: 0x00,
+                    command: 0x2A,
+                },
+                Command::DACStreamSetData {
+                    stream_id: 0,
+                    data_bank_id: 0x00,
+                    step_size: 1,
+                    step_base: 0,
+                },
+                Command::DACStreamSetFrequency {
+                    stream_id: 0,
+                    frequency: 8820,
+                },
+                Command::DACStreamStart {
+                    stream_id: 0,
+                    data_start_offset: 0,
+                    length_mode: 0x01,
+                    data_length: 40,
+                },
+            ]
+        );
+        assert!(vgm.commands.contains(&key_on));
+
+        assert_eq!(vgm.expand_dac_streams(), 40);
+        assert_eq!(vgm.total_samples(), total_samples);
+        let dac_writes = vgm
+            .commands
+            .iter()
+            .filter(|cmd| matches!(cmd, Command::YM2612Port0Address2AWriteWait { n: 5 }))
+            .count();
+        assert_eq!(dac_writes, 40);
+        assert_eq!(vgm.commands[11], key_on);
+        let state = vgm.chip_state_at(vgm.commands.len());
+        assert_eq!(state.register(System::Ym2612, 0x2A), Some(39));
+    }
+
+    #[test]
+    fn pcm_position_after_run() {
+        let pcm_write = Command::YM2612Port0Address2AWriteWait { n: 5 };
+        let mut commands = vec![
+            Command::DataBlock {
+                data_type: 0x00,
+                data_size: 60,
+                data: (0..60).collect(),
+            },
+            Command::SeekPCM { offset: 0 },
+        ];
+        // a run that is converted, then one that is too short
+        commands.extend(vec![pcm_write.clone(); 40]);
+        commands.push(Command::WaitNSamples { n: 1000 });
+        commands.extend(vec![pcm_write.clone(); 10]);
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 150,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+        let total_samples = vgm.total_samples();
+
+        let report = vgm
+            .dac_writes_to_streams(&DacStreamOptions::default())
+            .unwrap();
+        assert_eq!(report.runs.len(), 1);
+        assert_eq!(vgm.total_samples(), total_samples);
+        let seek = vgm
+            .commands
+            .iter()
+            .rposition(|cmd| *cmd == Command::SeekPCM { offset: 40 })
+            .unwrap();
+        assert_eq!(vgm.commands[seek + 2], pcm_write);
+        let state = vgm.chip_state_at(vgm.commands.len());
+        assert_eq!(state.register(System::Ym2612, 0x2A), Some(49));
+    }
+
+    #[test]
+    fn no_free_stream_id() {
+        let mut commands: Vec<Command> = (0..0xFF)
+            .map(|stream_id| Command::DACStreamStop { stream_id })
+            .collect();
+        for value in 0..40 {
+            commands.extend([
+                Command::YM2612Port0Write {
+                    register: 0x2A,
+                    value,
+                },
+                Command::WaitNSamples { n: 5 },
+            ]);
+        }
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 160,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        assert_eq!(
+            vgm.dac_writes_to_streams(&DacStreamOptions::default()),
+            Err(LibError::NoFreeStreamId)
+        );
+    }
+
+    #[test]
+    fn stop_all_and_frequency_change() {
+        let stream = |length_mode, data_length, after: Vec<Command>| {
+            let mut commands = vec![
+                Command::DataBlock {
+                    data_type: 0x00,
+                    data_size: 16,
+                    data: (0..16).collect(),
+                },
+                Command::DACStreamSetupControl {
+                    stream_id: 0,
+                    chip_type: 0x02,
+                    port: 0x00,
+                    command: 0x2A,
+                },
+                Command::DACStreamSetData {
+                    stream_id: 0,
+                    data_bank_id: 0x00,
+                    step_size: 1,
+                    step_base: 0,
+                },
+                // a write every 10 samples
+                Command::DACStreamSetFrequency {
+                    stream_id: 0,
+                    frequency: 4410,
+                },
+                Command::DACStreamStart {
+                    stream_id: 0,
+                    data_start_offset: 0,
+                    length_mode,
+                    data_length,
+                },
+                Command::WaitNSamples { n: 20 },
+            ];
+            commands.extend(after);
+            VgmFile {
+                header: HeaderData {
+                    version: 160,
+                    vgm_data_offset: 0x0C,
+                    ..Default::default()
+                },
+                commands,
+                metadata: VgmMetadata::default(),
+                loop_index: None,
+            }
+        };
+        let dac_write_samples = |vgm: &VgmFile| -> Vec<u64> {
+            vgm.timeline()
+                .filter(|(_, _, cmd)| matches!(cmd, Command::YM2612Port0Address2AWriteWait { .. }))
+                .map(|(sample, _, _)| sample)
+                .collect()
+        };
+
+        // looping stream stopped with every other stream
+        let mut vgm = stream(
+            0x81,
+            4,
+            vec![
+                Command::DACStreamStop { stream_id: 0xFF },
+                Command::WaitNSamples { n: 100 },
+            ],
+        );
+        assert_eq!(vgm.expand_dac_streams(), 2);
+
+        // half the rate after the first 2 writes
+        let mut vgm = stream(
+            0x01,
+            10,
+            vec![
+                Command::DACStreamSetFrequency {
+                    stream_id: 0,
+                    frequency: 2205,
+                },
+                Command::WaitNSamples { n: 200 },
+            ],
+        );
+        assert_eq!(vgm.expand_dac_streams(), 10);
+        let samples = dac_write_samples(&vgm);
+        assert_eq!(samples[..3], [0, 10, 20]);
+        assert_eq!(samples[9], 160);
+    }
+
+    #[test]
+    fn frequency_at_loop_point() {
+        let run = |wait| {
+            let mut commands = vec![];
+            for value in 0..40 {
+                commands.extend([
+                    Command::YM2612Port0Write {
+                        register: 0x2A,
+                        value,
+                    },
+                    Command::WaitNSamples { n: wait },
+                ]);
+            }
+            commands.push(Command::WaitNSamples { n: 1000 });
+            commands
+        };
+        let mut commands = run(10);
+        let loop_index = commands.len();
+        commands.extend(run(10));
+        commands.extend(run(20));
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 160,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: Some(loop_index),
+        };
+
+        let report = vgm
+            .dac_writes_to_streams(&DacStreamOptions::default())
+            .unwrap();
+        assert_eq!(report.runs.len(), 3);
+        // the rate of the last run is in effect when jumping back
+        assert_eq!(
+            vgm.commands[vgm.loop_index.unwrap()],
+            Command::DACStreamSetFrequency {
+                stream_id: 0,
+                frequency: 4410,
+            }
+        );
+    }
+}
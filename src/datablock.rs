@@ -0,0 +1,712 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::command::Command;
+use crate::vgmfile::VgmFile;
+
+/// Uncompressed YM2612 PCM data, read by 0x8n and DAC streams
+pub const DATA_TYPE_YM2612_PCM: u8 = 0x00;
+/// SegaPCM ROM dump
+pub const DATA_TYPE_SEGA_PCM_ROM: u8 = 0x80;
+
+/// Last type of the uncompressed stream data, the compressed versions of the same banks follow
+const LAST_STREAM_TYPE: u8 = 0x3F;
+/// Last type of the compressed stream data
+const LAST_COMPRESSED_TYPE: u8 = 0x7E;
+/// `data_start_offset` of a DAC stream start continuing from the current position
+const STREAM_CURRENT_OFFSET: u32 = 0xFFFFFFFF;
+
+/// Set in `data_size` when a ROM block is for the second chip
+const SECOND_CHIP_FLAG: u32 = 0x80000000;
+
+/// ROM image of a chip, rebuilt from its ROM dump data blocks (types 0x80 - 0xBF).
+/// Each block holds the total size of the ROM, a start address and part of the data.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RomImage {
+    pub size: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_base64"))]
+    pub data: Vec<u8>,
+}
+
+impl RomImage {
+    /// Merge every ROM block of the given type written for the first chip, `None` if there is none.
+    /// Blocks going past the size of the ROM are skipped.
+    pub fn from_commands(commands: &[Command], data_type: u8) -> Option<RomImage> {
+        let mut rom: Option<RomImage> = None;
+        for cmd in commands {
+            if let Command::DataBlock {
+                data_type: block_type,
+                data_size,
+                data,
+            } = cmd
+            {
+                if *block_type != data_type || data_size & SECOND_CHIP_FLAG != 0 || data.len() < 8 {
+                    continue;
+                }
+
+                let size = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                let start = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+                let end = start + data.len() - 8;
+                if end > size as usize {
+                    continue;
+                }
+                let rom = rom.get_or_insert_with(RomImage::default);
+                rom.size = size;
+
+                if rom.data.len() < end {
+                    rom.data.resize(end, 0);
+                }
+                rom.data[start..end].copy_from_slice(&data[8..]);
+            }
+        }
+
+        rom
+    }
+
+    /// Byte at an address, unwritten parts of the ROM read as 0
+    pub fn read(&self, address: u32) -> u8 {
+        self.data.get(address as usize).copied().unwrap_or(0)
+    }
+
+    /// Bytes between two addresses, clamped to the loaded data
+    pub fn slice(&self, start: u32, end: u32) -> &[u8] {
+        let end = (end as usize).min(self.data.len());
+        let start = (start as usize).min(end);
+        &self.data[start..end]
+    }
+}
+
+/// Blocks removed or merged by `VgmFile::consolidate_data_blocks`
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DataBlockReport {
+    /// Blocks dropped because an earlier block holds the same data
+    pub removed_duplicates: usize,
+    /// Blocks appended to the previous block of the same type
+    pub merged_blocks: usize,
+    pub saved_bytes: usize,
+}
+
+/// Stream data bank, the concatenation of the blocks of one type
+#[derive(Default)]
+struct Bank {
+    /// `(command_index, offset, length)` of every block
+    blocks: Vec<(usize, u32, u32)>,
+    /// Some blocks are compressed, the offsets of the following ones are not known
+    compressed: bool,
+    /// Played with 0x95 commands, which count the blocks
+    fast_started: bool,
+}
+
+impl Bank {
+    fn len(&self) -> u32 {
+        self.blocks
+            .last()
+            .map_or(0, |&(_, offset, length)| offset + length)
+    }
+
+    /// Index of the block holding an offset
+    fn block_at(&self, offset: u32) -> Option<usize> {
+        let index = self
+            .blocks
+            .partition_point(|&(_, start, _)| start <= offset)
+            .checked_sub(1)?;
+        let (_, start, length) = self.blocks[index];
+        (offset < start + length).then_some(index)
+    }
+}
+
+/// Settings of a DAC stream deciding the bytes it reads
+#[derive(Default, Clone, Copy)]
+struct StreamRead {
+    bank: Option<u8>,
+    step_size: u8,
+    frequency: u32,
+}
+
+/// Offset and number of bytes read from the stream banks by a command, given the settings
+/// of every DAC stream
+fn bank_read(
+    cmd: &Command,
+    streams: &HashMap<u8, StreamRead>,
+    banks: &HashMap<u8, Bank>,
+) -> Option<(u8, u32, u32)> {
+    match *cmd {
+        Command::PCMRAMWrite {
+            chip_type,
+            read_offset,
+            size,
+            ..
+        } => {
+            // a size of 0 stands for 16MB
+            let size = if size == 0 { 0x1000000 } else { size };
+            Some((chip_type, read_offset, size))
+        }
+        Command::DACStreamStart {
+            stream_id,
+            data_start_offset,
+            length_mode,
+            data_length,
+        } if data_start_offset != STREAM_CURRENT_OFFSET => {
+            let stream = streams.get(&stream_id)?;
+            let bank = stream.bank?;
+            let step = stream.step_size.max(1) as u64;
+            // number of commands for the first two modes, the stream reads a byte every step
+            let length = match length_mode & 0x03 {
+                1 => data_length as u64 * step,
+                2 => data_length as u64 * stream.frequency as u64 / 1000 * step,
+                3 => banks.get(&bank).map_or(0, |bank| {
+                    bank.len().saturating_sub(data_start_offset) as u64
+                }),
+                _ => 0,
+            };
+            Some((bank, data_start_offset, length.min(u32::MAX as u64) as u32))
+        }
+        _ => None,
+    }
+}
+
+/// Addresses written by a ROM block, the whole ROM when the block has no start address
+fn rom_range(data: &[u8]) -> (u64, u64) {
+    if data.len() < 8 {
+        return (0, u64::MAX);
+    }
+    let start = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as u64;
+    (start, start + data.len() as u64 - 8)
+}
+
+/// Whether two ROM blocks of the same type can be written as one
+fn contiguous_rom_blocks(first: &[u8], first_size: u32, second: &[u8], second_size: u32) -> bool {
+    first.len() >= 8
+        && second.len() >= 8
+        && first_size & SECOND_CHIP_FLAG == second_size & SECOND_CHIP_FLAG
+        && first[..4] == second[..4]
+        && u32::from_le_bytes([first[4], first[5], first[6], first[7]]) as usize + first.len() - 8
+            == u32::from_le_bytes([second[4], second[5], second[6], second[7]]) as usize
+}
+
+impl VgmFile {
+    fn stream_banks(&self) -> HashMap<u8, Bank> {
+        let mut banks: HashMap<u8, Bank> = HashMap::new();
+        for (index, cmd) in self.commands.iter().enumerate() {
+            match *cmd {
+                Command::DataBlock {
+                    data_type,
+                    ref data,
+                    ..
+                } if data_type <= LAST_STREAM_TYPE => {
+                    let bank = banks.entry(data_type).or_default();
+                    let offset = bank.len();
+                    bank.blocks.push((index, offset, data.len() as u32));
+                }
+                Command::DataBlock { data_type, .. } if data_type <= LAST_COMPRESSED_TYPE => {
+                    banks
+                        .entry(data_type & LAST_STREAM_TYPE)
+                        .or_default()
+                        .compressed = true;
+                }
+                _ => {}
+            }
+        }
+
+        let mut stream_banks = HashMap::new();
+        for cmd in &self.commands {
+            match *cmd {
+                Command::DACStreamSetData {
+                    stream_id,
+                    data_bank_id,
+                    ..
+                } => {
+                    stream_banks.insert(stream_id, data_bank_id);
+                }
+                Command::DACStreamStartFast { stream_id, .. } => {
+                    if let Some(bank) = stream_banks
+                        .get(&stream_id)
+                        .and_then(|bank| banks.get_mut(bank))
+                    {
+                        bank.fast_started = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        banks
+    }
+
+    /// Ranges of the stream banks read by the commands, as `(bank, offset, length)`
+    fn bank_reads(&self, banks: &HashMap<u8, Bank>) -> Vec<(u8, u32, u32)> {
+        let mut reads = vec![];
+        let mut streams: HashMap<u8, StreamRead> = HashMap::new();
+        // the 0x8n commands read from the position of the last seek
+        let mut pcm_read = (0, 0);
+        for cmd in &self.commands {
+            match *cmd {
+                Command::DACStreamSetData {
+                    stream_id,
+                    data_bank_id,
+                    step_size,
+                    ..
+                } => {
+                    let stream = streams.entry(stream_id).or_default();
+                    stream.bank = Some(data_bank_id);
+                    stream.step_size = step_size;
+                }
+                Command::DACStreamSetFrequency {
+                    stream_id,
+                    frequency,
+                } => {
+                    streams.entry(stream_id).or_default().frequency = frequency;
+                }
+                Command::SeekPCM { offset } => {
+                    reads.push((DATA_TYPE_YM2612_PCM, pcm_read.0, pcm_read.1));
+                    pcm_read = (offset, 0);
+                }
+                Command::YM2612Port0Address2AWriteWait { .. } => pcm_read.1 += 1,
+                _ => reads.extend(bank_read(cmd, &streams, banks)),
+            }
+        }
+        reads.push((DATA_TYPE_YM2612_PCM, pcm_read.0, pcm_read.1));
+
+        reads
+    }
+
+    /// Remove the data blocks holding the same data as an earlier block of the same type, then
+    /// merge the blocks of the same type written one after the other.
+    /// Offsets of SeekPCM, DAC stream and PCM RAM write commands and the block ids of
+    /// DAC stream fast starts are rewritten for the new layout of the data banks.
+    pub fn consolidate_data_blocks(&mut self) -> DataBlockReport {
+        let mut report = DataBlockReport::default();
+        let bytes_before: usize = self.commands.iter().map(Command::encoded_len).sum();
+        let banks = self.stream_banks();
+
+        // blocks read over their boundaries have to stay where they are
+        let mut pinned = HashSet::new();
+        for (bank_type, offset, length) in self.bank_reads(&banks) {
+            let Some(bank) = banks.get(&bank_type) else {
+                continue;
+            };
+            let end = offset.saturating_add(length).min(bank.len());
+            let first = bank.block_at(offset);
+            let last = bank.block_at(end.saturating_sub(1));
+            if let (Some(first), Some(last)) = (first, last) {
+                if first != last {
+                    pinned.extend((first..=last).map(|index| bank.blocks[index].0));
+                }
+            }
+        }
+
+        // duplicated command index -> command index of the first copy
+        let mut duplicates: HashMap<usize, usize> = HashMap::new();
+        for bank in banks.values().filter(|bank| !bank.compressed) {
+            let mut first_copies: HashMap<&[u8], usize> = HashMap::new();
+            for &(index, _, _) in &bank.blocks {
+                let Command::DataBlock { data, .. } = &self.commands[index] else {
+                    continue;
+                };
+                match first_copies.get(data.as_slice()) {
+                    Some(&first) if !pinned.contains(&index) => {
+                        duplicates.insert(index, first);
+                    }
+                    Some(_) => {}
+                    None => {
+                        first_copies.insert(data, index);
+                    }
+                }
+            }
+        }
+        // a ROM block written again is only dropped if nothing overwrote it in between
+        let mut roms = HashMap::new();
+        let mut rom_writes: HashMap<_, Vec<_>> = HashMap::new();
+        for (index, cmd) in self.commands.iter().enumerate() {
+            if let Command::DataBlock {
+                data_type: data_type @ 0x80..=0xBF,
+                data_size,
+                data,
+            } = cmd
+            {
+                let (start, end) = rom_range(data);
+                let writes = rom_writes
+                    .entry((*data_type, data_size & SECOND_CHIP_FLAG))
+                    .or_default();
+                let key = (*data_type, *data_size, data.as_slice());
+                let overwritten = |first: usize| {
+                    writes.iter().any(|&(write, write_start, write_end)| {
+                        write > first && write_start < end && start < write_end
+                    })
+                };
+                match roms.get(&key) {
+                    Some(&first) if !overwritten(first) => {
+                        duplicates.insert(index, index);
+                    }
+                    _ => {
+                        roms.insert(key, index);
+                        writes.push((index, start, end));
+                    }
+                }
+            }
+        }
+
+        // new offset and block id of the blocks of every bank
+        let mut layouts: HashMap<u8, (Vec<(u32, u16)>, u32)> = HashMap::new();
+        for (&bank_type, bank) in banks.iter().filter(|(_, bank)| !bank.compressed) {
+            let mut layout: Vec<(u32, u16)> = vec![];
+            let mut kept: HashMap<usize, usize> = HashMap::new();
+            let (mut offset, mut block_id) = (0, 0);
+            for (position, &(index, _, length)) in bank.blocks.iter().enumerate() {
+                match duplicates.get(&index) {
+                    Some(first) => layout.push(layout[kept[first]]),
+                    None => {
+                        kept.insert(index, position);
+                        layout.push((offset, block_id));
+                        offset += length;
+                        block_id += 1;
+                    }
+                }
+            }
+            layouts.insert(bank_type, (layout, offset));
+        }
+        let remap = |bank_type: u8, offset: u32| -> u32 {
+            let (Some(bank), Some((layout, new_length))) =
+                (banks.get(&bank_type), layouts.get(&bank_type))
+            else {
+                return offset;
+            };
+            match bank.block_at(offset) {
+                Some(block) => layout[block].0 + offset - bank.blocks[block].1,
+                None => offset.saturating_sub(bank.len()) + new_length,
+            }
+        };
+
+        let mut commands: Vec<Command> = Vec::with_capacity(self.commands.len());
+        let mut loop_index = None;
+        let mut stream_banks = HashMap::new();
+        // types of the blocks written since the last command that is not a data block
+        let mut run: HashMap<u8, usize> = HashMap::new();
+        for (index, cmd) in self.commands.iter().enumerate() {
+            if Some(index) == self.loop_index {
+                loop_index = Some(commands.len());
+                run.clear();
+            }
+
+            let mut cmd = cmd.clone();
+            match &mut cmd {
+                Command::DataBlock { .. } if duplicates.contains_key(&index) => {
+                    report.removed_duplicates += 1;
+                    continue;
+                }
+                Command::DataBlock {
+                    data_type,
+                    data_size,
+                    data,
+                } => {
+                    let mergeable = match *data_type {
+                        ..=LAST_STREAM_TYPE => banks
+                            .get(data_type)
+                            .is_some_and(|bank| !bank.compressed && !bank.fast_started),
+                        0x80..=0xBF => true,
+                        _ => false,
+                    };
+                    let previous = run.get(data_type).map(|&previous| &mut commands[previous]);
+                    match previous {
+                        Some(Command::DataBlock {
+                            data_size: previous_size,
+                            data: previous_data,
+                            ..
+                        }) if mergeable => {
+                            if *data_type <= LAST_STREAM_TYPE {
+                                previous_data.extend_from_slice(data);
+                                *previous_size = previous_data.len() as u32;
+                            } else if contiguous_rom_blocks(
+                                previous_data,
+                                *previous_size,
+                                data,
+                                *data_size,
+                            ) {
+                                previous_data.extend_from_slice(&data[8..]);
+                                *previous_size =
+                                    previous_data.len() as u32 | *data_size & SECOND_CHIP_FLAG;
+                            } else {
+                                run.insert(*data_type, commands.len());
+                                commands.push(cmd);
+                                continue;
+                            }
+                            report.merged_blocks += 1;
+                            continue;
+                        }
+                        _ => {
+                            run.insert(*data_type, commands.len());
+                        }
+                    }
+                }
+                Command::SeekPCM { offset } => {
+                    *offset = remap(DATA_TYPE_YM2612_PCM, *offset);
+                }
+                Command::PCMRAMWrite {
+                    chip_type,
+                    read_offset,
+                    ..
+                } => {
+                    *read_offset = remap(*chip_type, *read_offset);
+                }
+                Command::DACStreamSetData {
+                    stream_id,
+                    data_bank_id,
+                    ..
+                } => {
+                    stream_banks.insert(*stream_id, *data_bank_id);
+                }
+                Command::DACStreamStart {
+                    stream_id,
+                    data_start_offset,
+                    ..
+                } if *data_start_offset != STREAM_CURRENT_OFFSET => {
+                    if let Some(&bank) = stream_banks.get(stream_id) {
+                        *data_start_offset = remap(bank, *data_start_offset);
+                    }
+                }
+                Command::DACStreamStartFast {
+                    stream_id,
+                    block_id,
+                    ..
+                } => {
+                    let layout = stream_banks
+                        .get(stream_id)
+                        .and_then(|bank| layouts.get(bank));
+                    if let Some((layout, _)) = layout {
+                        *block_id = layout
+                            .get(*block_id as usize)
+                            .map_or(*block_id, |block| block.1);
+                    }
+                }
+                _ => {}
+            }
+            if !matches!(cmd, Command::DataBlock { .. }) {
+                run.clear();
+            }
+            commands.push(cmd);
+        }
+        if self
+            .loop_index
+            .is_some_and(|index| index >= self.commands.len())
+        {
+            loop_index = Some(commands.len());
+        }
+
+        self.commands = commands;
+        self.loop_index = loop_index;
+        self.update_offsets();
+
+        let bytes_after: usize = self.commands.iter().map(Command::encoded_len).sum();
+        report.saved_bytes = bytes_before - bytes_after;
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    use super::RomImage;
+
+    fn block(data_type: u8, data: &[u8]) -> Command {
+        Command::DataBlock {
+            data_type,
+            data_size: data.len() as u32,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn rom_image() {
+        let rom = |size: u32, start: u32, data: &[u8], second_chip: bool| {
+            let mut block = [size.to_le_bytes(), start.to_le_bytes()].concat();
+            block.extend_from_slice(data);
+            Command::DataBlock {
+                data_type: 0x80,
+                data_size: block.len() as u32 | (second_chip as u32) << 31,
+                data: block,
+            }
+        };
+        let commands = [
+            rom(8, 2, &[1, 2], false),
+            rom(8, 0, &[9], true),
+            // past the end of the ROM
+            rom(8, 0xFFFFFF00, &[5], false),
+            rom(8, 7, &[5, 5], false),
+        ];
+
+        let image = RomImage::from_commands(&commands, 0x80).unwrap();
+        assert_eq!(image.size, 8);
+        assert_eq!(image.data, [0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn consolidate() {
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 160,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands: vec![
+                block(0x00, &[1, 2, 3]),
+                block(0x00, &[4, 5]),
+                block(0x00, &[1, 2, 3]),
+                block(0x01, &[9, 9]),
+                block(0x01, &[9, 9]),
+                Command::SeekPCM { offset: 6 },
+                Command::YM2612Port0Address2AWriteWait { n: 1 },
+                Command::PCMRAMWrite {
+                    chip_type: 0x01,
+                    read_offset: 3,
+                    write_offset: 0,
+                    size: 1,
+                },
+                Command::DACStreamSetData {
+                    stream_id: 0,
+                    data_bank_id: 0x00,
+                    step_size: 1,
+                    step_base: 0,
+                },
+                Command::DACStreamStart {
+                    stream_id: 0,
+                    data_start_offset: 4,
+                    length_mode: 0x01,
+                    data_length: 1,
+                },
+                block(0x00, &[6]),
+                Command::SeekPCM { offset: 8 },
+                Command::EndOfSoundData,
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: Some(12),
+        };
+
+        let report = vgm.consolidate_data_blocks();
+        assert_eq!(report.removed_duplicates, 2);
+        assert_eq!(report.merged_blocks, 1);
+        assert_eq!(report.saved_bytes, (7 + 3) + (7 + 2) + 7);
+        assert_eq!(
+            vgm.commands,
+            [
+                block(0x00, &[1, 2, 3, 4, 5]),
+                block(0x01, &[9, 9]),
+                Command::SeekPCM { offset: 1 },
+                Command::YM2612Port0Address2AWriteWait { n: 1 },
+                Command::PCMRAMWrite {
+                    chip_type: 0x01,
+                    read_offset: 1,
+                    write_offset: 0,
+                    size: 1,
+                },
+                Command::DACStreamSetData {
+                    stream_id: 0,
+                    data_bank_id: 0x00,
+                    step_size: 1,
+                    step_base: 0,
+                },
+                Command::DACStreamStart {
+                    stream_id: 0,
+                    data_start_offset: 4,
+                    length_mode: 0x01,
+                    data_length: 1,
+                },
+                block(0x00, &[6]),
+                Command::SeekPCM { offset: 5 },
+                Command::EndOfSoundData,
+            ]
+        );
+        assert_eq!(vgm.loop_index, Some(9));
+    }
+
+    #[test]
+    fn stream_read_lengths() {
+        let stream = |stream_id, step_size, data_start_offset, length_mode, data_length| {
+            [
+                Command::DACStreamSetData {
+                    stream_id,
+                    data_bank_id: 0x00,
+                    step_size,
+                    step_base: 0,
+                },
+                Command::DACStreamSetFrequency {
+                    stream_id,
+                    frequency: 1000,
+                },
+                Command::DACStreamStart {
+                    stream_id,
+                    data_start_offset,
+                    length_mode,
+                    data_length,
+                },
+            ]
+        };
+        let mut commands = vec![];
+        for _ in 0..3 {
+            commands.extend([block(0x00, &[1, 2]), block(0x00, &[3, 4])]);
+        }
+        // 2 commands with a step of 2 bytes, then 4 commands in 4 ms at 1000 Hz
+        commands.extend(stream(0, 2, 4, 0x01, 2));
+        commands.extend(stream(1, 1, 8, 0x02, 4));
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 160,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        // the copies are read over their boundaries
+        let report = vgm.consolidate_data_blocks();
+        assert_eq!(report.removed_duplicates, 0);
+    }
+
+    #[test]
+    fn overwritten_rom_blocks() {
+        let rom = |start: u32, data: &[u8]| {
+            let mut block = [8u32.to_le_bytes(), start.to_le_bytes()].concat();
+            block.extend_from_slice(data);
+            Command::DataBlock {
+                data_type: 0x80,
+                data_size: block.len() as u32,
+                data: block,
+            }
+        };
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 160,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands: vec![
+                rom(0, &[1, 2]),
+                rom(4, &[3]),
+                // the same data again, nothing was written over it
+                rom(0, &[1, 2]),
+                rom(1, &[4]),
+                // written back after the overlapping block
+                rom(0, &[1, 2]),
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let report = vgm.consolidate_data_blocks();
+        assert_eq!(report.removed_duplicates, 1);
+        assert_eq!(
+            vgm.commands,
+            [rom(0, &[1, 2]), rom(4, &[3]), rom(1, &[4]), rom(0, &[1, 2])]
+        );
+        assert_eq!(
+            RomImage::from_commands(&vgm.commands, 0x80).unwrap().data,
+            [1, 2, 0, 0, 3]
+        );
+    }
+}
@@ -0,0 +1,541 @@
+//! Text form of a VGM file, one line per command:
+//!
+//! ```text
+//! .header 0000 56 67 6D 20 ...
+//! 00000100 @0          YM2612.P0 B4 C0 ; ch1 stereo/LFO sensitivity
+//! .loop
+//! 00000103 @0          WAIT 735
+//! .gd3 track_en "Green Hill Zone"
+//! ```
+//!
+//! Each command line starts with its byte offset and its absolute sample time, both ignored by
+//! the assembler, followed by a mnemonic and the bytes of the command after the opcode.
+//! Waits, data blocks and PCM RAM writes use a symbolic form instead.
+//! Register writes are followed by their meaning as a `;` comment, see `Command::describe`.
+
+use bytes::{Bytes, BytesMut};
+
+use crate::chip_state::ChipState;
+use crate::command::Command;
+use crate::errors::LibError;
+use crate::header::HeaderData;
+use crate::metadata::VgmMetadata;
+use crate::vgmfile::VgmFile;
+
+/// Mnemonics of the commands written as an opcode followed by raw bytes
+const MNEMONICS: [(u8, &str); 67] = [
+    (0x31, "AY8910.STEREO"),
+    (0x4F, "GG.STEREO"),
+    (0x50, "SN76489"),
+    (0x51, "YM2413"),
+    (0x52, "YM2612.P0"),
+    (0x53, "YM2612.P1"),
+    (0x54, "YM2151"),
+    (0x55, "YM2203"),
+    (0x56, "YM2608.P0"),
+    (0x57, "YM2608.P1"),
+    (0x58, "YM2610.P0"),
+    (0x59, "YM2610.P1"),
+    (0x5A, "YM3812"),
+    (0x5B, "YM3526"),
+    (0x5C, "Y8950"),
+    (0x5D, "YMZ280B"),
+    (0x5E, "YMF262.P0"),
+    (0x5F, "YMF262.P1"),
+    (0x62, "WAIT.NTSC"),
+    (0x63, "WAIT.PAL"),
+    // skipped by the assembler, the end of the commands is always written
+    (0x66, "END"),
+    (0x90, "DAC.SETUP"),
+    (0x91, "DAC.DATA"),
+    (0x92, "DAC.FREQ"),
+    (0x93, "DAC.START"),
+    (0x94, "DAC.STOP"),
+    (0x95, "DAC.FAST"),
+    (0xA0, "AY8910"),
+    (0xB0, "RF5C68"),
+    (0xB1, "RF5C164"),
+    (0xB2, "PWM"),
+    (0xB3, "GB.DMG"),
+    (0xB4, "NES.APU"),
+    (0xB5, "MULTIPCM"),
+    (0xB6, "UPD7759"),
+    (0xB7, "OKIM6258"),
+    (0xB8, "OKIM6295"),
+    (0xB9, "HUC6280"),
+    (0xBA, "K053260"),
+    (0xBB, "POKEY"),
+    (0xBC, "WONDERSWAN"),
+    (0xBD, "SAA1099"),
+    (0xBE, "ES5506"),
+    (0xBF, "GA20"),
+    (0xC0, "SEGAPCM"),
+    (0xC1, "RF5C68.MEM"),
+    (0xC2, "RF5C164.MEM"),
+    (0xC3, "MULTIPCM.BANK"),
+    (0xC4, "QSOUND"),
+    (0xC5, "SCSP"),
+    (0xC6, "WONDERSWAN.MEM"),
+    (0xC7, "VSU"),
+    (0xC8, "X1010"),
+    (0xD0, "YMF278B"),
+    (0xD1, "YMF271"),
+    (0xD2, "SCC1"),
+    (0xD3, "K054539"),
+    (0xD4, "C140"),
+    (0xD5, "ES5503"),
+    (0xD6, "ES5506.16"),
+    (0xE0, "SEEKPCM"),
+    (0xE1, "C352"),
+    // symbolic forms, see `format_command`
+    (0x61, "WAIT"),
+    (0x67, "DATA"),
+    (0x68, "PCMRAM"),
+    (0x70, "WAIT.SHORT"),
+    (0x80, "YM2612.DAC"),
+];
+
+/// Names of the GD3 fields, in the order they are written
+const GD3_FIELDS: [&str; 11] = [
+    "track_en",
+    "track_jp",
+    "game_en",
+    "game_jp",
+    "system_en",
+    "system_jp",
+    "author_en",
+    "author_jp",
+    "date",
+    "creator",
+    "notes",
+];
+
+pub fn mnemonic(opcode: u8) -> Option<&'static str> {
+    let opcode = match opcode {
+        0x70..=0x7F => 0x70,
+        0x80..=0x8F => 0x80,
+        opcode => opcode,
+    };
+    MNEMONICS
+        .iter()
+        .find(|(op, _)| *op == opcode)
+        .map(|(_, name)| *name)
+}
+
+fn opcode(mnemonic: &str) -> Option<u8> {
+    MNEMONICS
+        .iter()
+        .find(|(_, name)| name.eq_ignore_ascii_case(mnemonic))
+        .map(|(op, _)| *op)
+}
+
+/// Mnemonic and operands of a command
+pub fn format_command(cmd: &Command) -> String {
+    match cmd {
+        Command::WaitNSamples { n } => format!("WAIT {n}"),
+        Command::WaitNSamplesPlus1 { n } => format!("WAIT.SHORT {}", n + 1),
+        Command::YM2612Port0Address2AWriteWait { n } => format!("YM2612.DAC {n}"),
+        Command::DataBlock {
+            data_type,
+            data_size,
+            data,
+        } => format!("DATA {data_type:02X} {data_size:08X} {}", hex_string(data)),
+        _ => {
+            let bytes = cmd.clone().to_bytes();
+            // PCM RAM writes repeat the 0x66 compatibility byte of data blocks
+            let operands = if bytes[0] == 0x68 {
+                &bytes[2..]
+            } else {
+                &bytes[1..]
+            };
+
+            let mut line = mnemonic(bytes[0]).unwrap().to_string();
+            for byte in operands {
+                line.push_str(&format!(" {byte:02X}"));
+            }
+            line
+        }
+    }
+}
+
+fn hex_string(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+fn parse_hex_string(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn unquote(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut unquoted = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => unquoted.push('\n'),
+            'r' => unquoted.push('\r'),
+            c => unquoted.push(c),
+        }
+    }
+    Some(unquoted)
+}
+
+fn gd3_fields(metadata: &VgmMetadata) -> [&String; 11] {
+    [
+        &metadata.english_data.track,
+        &metadata.japanese_data.track,
+        &metadata.english_data.game,
+        &metadata.japanese_data.game,
+        &metadata.english_data.system,
+        &metadata.japanese_data.system,
+        &metadata.english_data.author,
+        &metadata.japanese_data.author,
+        &metadata.date_release,
+        &metadata.name_vgm_creator,
+        &metadata.notes,
+    ]
+}
+
+fn gd3_field_mut<'a>(metadata: &'a mut VgmMetadata, name: &str) -> Option<&'a mut String> {
+    Some(match name {
+        "track_en" => &mut metadata.english_data.track,
+        "track_jp" => &mut metadata.japanese_data.track,
+        "game_en" => &mut metadata.english_data.game,
+        "game_jp" => &mut metadata.japanese_data.game,
+        "system_en" => &mut metadata.english_data.system,
+        "system_jp" => &mut metadata.japanese_data.system,
+        "author_en" => &mut metadata.english_data.author,
+        "author_jp" => &mut metadata.japanese_data.author,
+        "date" => &mut metadata.date_release,
+        "creator" => &mut metadata.name_vgm_creator,
+        "notes" => &mut metadata.notes,
+        _ => return None,
+    })
+}
+
+/// Command line without the offset and sample time columns
+fn strip_columns(line: &str) -> &str {
+    let mut rest = line;
+    if let Some((offset, tail)) = rest.split_once(char::is_whitespace) {
+        if offset.len() == 8 && offset.chars().all(|c| c.is_ascii_hexdigit()) {
+            rest = tail.trim_start();
+        }
+    }
+    if rest.starts_with('@') {
+        rest = rest
+            .split_once(char::is_whitespace)
+            .map(|(_, tail)| tail.trim_start())
+            .unwrap_or("");
+    }
+    rest
+}
+
+fn parse_command(text: &str) -> Result<Command, String> {
+    let mut tokens = text.split_whitespace();
+    let name = tokens.next().ok_or("empty command".to_string())?;
+    let operands: Vec<&str> = tokens.collect();
+    let op = opcode(name).ok_or(format!("unknown mnemonic {name}"))?;
+
+    let number = |index: usize| -> Result<u32, String> {
+        let token = operands
+            .get(index)
+            .ok_or(format!("missing operand for {name}"))?;
+        token
+            .parse::<u32>()
+            .map_err(|_| format!("invalid number {token}"))
+    };
+
+    let bytes = match op {
+        0x61 => {
+            let n = number(0)?;
+            let n = u16::try_from(n).map_err(|_| format!("wait of {n} samples is too long"))?;
+            return Ok(Command::WaitNSamples { n });
+        }
+        0x70 => match number(0)? {
+            n @ 1..=16 => vec![0x70 + n as u8 - 1],
+            n => return Err(format!("short wait of {n} samples")),
+        },
+        0x80 => match number(0)? {
+            n @ 0..=15 => vec![0x80 + n as u8],
+            n => return Err(format!("YM2612.DAC wait of {n} samples")),
+        },
+        0x67 => {
+            let [data_type, data_size, data] = operands[..] else {
+                return Err("DATA takes a type, a size and the hex payload".to_string());
+            };
+            let data_type = u8::from_str_radix(data_type, 16)
+                .map_err(|_| format!("invalid type {data_type}"))?;
+            let data_size = u32::from_str_radix(data_size, 16)
+                .map_err(|_| format!("invalid size {data_size}"))?;
+            let data = parse_hex_string(data).ok_or("invalid hex payload".to_string())?;
+            // bit 31 of the size marks the ROM dumps of the second chip
+            if data_size & 0x7FFFFFFF != data.len() as u32 {
+                return Err(format!(
+                    "DATA size {data_size:08X} does not match the {} bytes of payload",
+                    data.len()
+                ));
+            }
+            return Ok(Command::DataBlock {
+                data_type,
+                data_size,
+                data,
+            });
+        }
+        op => {
+            let mut bytes = vec![op];
+            if op == 0x68 {
+                bytes.push(0x66);
+            }
+            for token in &operands {
+                bytes.push(
+                    u8::from_str_radix(token, 16).map_err(|_| format!("invalid byte {token}"))?,
+                );
+            }
+            bytes
+        }
+    };
+
+    // pad so that a missing operand is reported instead of panicking the decoder
+    let len = bytes.len();
+    let cmd = Command::from_bytes(&mut Bytes::from([&bytes[..], &[0; 16][..]].concat()))
+        .map_err(|op| format!("unknown opcode {op:02X}"))?;
+    if cmd.encoded_len() != len {
+        return Err(format!(
+            "{name} takes {} operand bytes, got {}",
+            cmd.encoded_len() - (len - operands.len()),
+            operands.len()
+        ));
+    }
+
+    Ok(cmd)
+}
+
+impl VgmFile {
+    /// Text listing of the file, see the module documentation for the format
+    pub fn disassemble(&self) -> String {
+        let mut text = String::new();
+
+        let mut header = BytesMut::new();
+        self.header.to_bytes(&mut header);
+        for (i, chunk) in header.chunks(16).enumerate() {
+            text.push_str(&format!(".header {:04X}", i * 16));
+            for byte in chunk {
+                text.push_str(&format!(" {byte:02X}"));
+            }
+            text.push('\n');
+        }
+
+        let mut chip_state = ChipState::new();
+        let mut timeline = self.timeline();
+        for (index, (sample, pos, cmd)) in timeline.by_ref().enumerate() {
+            if Some(index) == self.loop_index {
+                text.push_str(".loop\n");
+            }
+            text.push_str(&format!("{pos:08X} @{sample:<10} {}", format_command(cmd)));
+            if let Some(description) = cmd.describe(&chip_state) {
+                text.push_str(&format!(" ; {description}"));
+            }
+            text.push('\n');
+            chip_state.update(cmd);
+        }
+        text.push_str(&format!(
+            "{:08X} @{:<10} END\n",
+            timeline.byte_offset(),
+            timeline.sample_position()
+        ));
+
+        if self.header.gd3_offset != 0 {
+            for (name, value) in GD3_FIELDS.iter().zip(gd3_fields(&self.metadata)) {
+                text.push_str(&format!(".gd3 {name} {}\n", quote(value)));
+            }
+        }
+
+        text
+    }
+
+    /// Parse a listing made by `disassemble`, possibly edited.
+    /// The offset and sample time columns are ignored, the loop starts at the `.loop` line
+    /// (no loop without one) and the loop, GD3 and end of file offsets are recomputed.
+    pub fn assemble(text: &str) -> Result<VgmFile, LibError> {
+        let mut header_bytes = vec![];
+        let mut commands = vec![];
+        let mut loop_index = None;
+        let mut metadata = VgmMetadata::default();
+        let mut has_gd3 = false;
+
+        for (line_index, line) in text.lines().enumerate() {
+            let error = |message: String| LibError::AssemblerError {
+                line: line_index + 1,
+                message,
+            };
+
+            // GD3 strings may contain semicolons
+            let line = line.trim();
+            let line = if line.starts_with(".gd3") {
+                line
+            } else {
+                line.split(';').next().unwrap().trim_end()
+            };
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix(".header") {
+                // the first token is the offset of the row
+                for token in rest.split_whitespace().skip(1) {
+                    header_bytes.push(
+                        u8::from_str_radix(token, 16)
+                            .map_err(|_| error(format!("invalid byte {token}")))?,
+                    );
+                }
+            } else if line == ".loop" {
+                loop_index = Some(commands.len());
+            } else if let Some(rest) = line.strip_prefix(".gd3") {
+                let (name, value) = rest
+                    .trim_start()
+                    .split_once(char::is_whitespace)
+                    .ok_or(error("missing GD3 value".to_string()))?;
+                let field = gd3_field_mut(&mut metadata, name)
+                    .ok_or(error(format!("unknown GD3 field {name}")))?;
+                *field = unquote(value.trim()).ok_or(error("invalid GD3 string".to_string()))?;
+                has_gd3 = true;
+            } else {
+                let cmd = strip_columns(line);
+                if cmd.eq_ignore_ascii_case("END") {
+                    continue;
+                }
+                commands.push(parse_command(cmd).map_err(error)?);
+            }
+        }
+
+        if header_bytes.len() < 0x40 {
+            return Err(LibError::AssemblerError {
+                line: 0,
+                message: "header is shorter than 0x40 bytes".to_string(),
+            });
+        }
+
+        let mut vgm = VgmFile {
+            header: HeaderData::from_bytes(&mut Bytes::from(header_bytes)),
+            commands,
+            metadata,
+            loop_index,
+        };
+        vgm.header.gd3_offset = has_gd3 as u32;
+        vgm.update_offsets();
+
+        Ok(vgm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    #[test]
+    fn round_trip() {
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 171,
+                ym2612_clock: 7670453,
+                sn76489_clock: 3579545,
+                vgm_data_offset: 0x100 - 0x34,
+                ..Default::default()
+            },
+            commands: vec![
+                Command::DataBlock {
+                    data_type: 0x00,
+                    data_size: 3,
+                    data: vec![0x80, 0x90, 0xA0],
+                },
+                Command::YM2612Port0Write {
+                    register: 0xB4,
+                    value: 0xC0,
+                },
+                Command::SeekPCM { offset: 0 },
+                Command::YM2612Port0Address2AWriteWait { n: 2 },
+                Command::PSGWrite { value: 0x9F },
+                Command::Wait735Samples,
+                Command::WaitNSamplesPlus1 { n: 15 },
+                Command::PCMRAMWrite {
+                    chip_type: 0x01,
+                    read_offset: 0x10,
+                    write_offset: 0x2000,
+                    size: 0x100,
+                },
+                Command::WaitNSamples { n: 1000 },
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+        vgm.metadata.english_data.track = "Title \"1\"; remix".to_string();
+        vgm.header.gd3_offset = 1;
+        vgm.loop_index = Some(4);
+        vgm.update_offsets();
+
+        let text = vgm.disassemble();
+        assert!(text.contains("YM2612.P0 B4 C0 ; ch1 stereo/LFO sensitivity\n"));
+        assert!(text.contains("@2          SN76489 9F ; tone 0 volume 0x0F\n"));
+        assert!(text.contains(".loop\n"));
+        assert!(text.contains("WAIT.SHORT 16\n"));
+        assert!(text.contains(".gd3 track_en \"Title \\\"1\\\"; remix\"\n"));
+
+        let mut original = BytesMut::new();
+        vgm.to_bytes(&mut original);
+        let mut assembled = BytesMut::new();
+        VgmFile::assemble(&text).unwrap().to_bytes(&mut assembled);
+        assert_eq!(assembled, original);
+
+        // hand edit without the columns
+        let edited = text.replace("WAIT 1000", "WAIT 500 ; shorter\nYM2612.P1 28 F0");
+        let edited = VgmFile::assemble(&edited).unwrap();
+        assert_eq!(
+            edited.commands[9],
+            Command::YM2612Port1Write {
+                register: 0x28,
+                value: 0xF0
+            }
+        );
+        assert!(VgmFile::assemble(&text.replace("SN76489 9F", "SN76489")).is_err());
+        // columns without a command, payload longer than the size
+        assert!(VgmFile::assemble(&format!("{text}00000100 @0\n")).is_err());
+        assert!(VgmFile::assemble(&text.replace("00000003 8090A0", "00000002 8090A0")).is_err());
+
+        // the end of sound data command is listed, and written once
+        vgm.commands.push(Command::EndOfSoundData);
+        let text = vgm.disassemble();
+        let mut assembled = BytesMut::new();
+        VgmFile::assemble(&text).unwrap().to_bytes(&mut assembled);
+        assert_eq!(assembled, original);
+    }
+}
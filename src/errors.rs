@@ -13,4 +13,23 @@ pub enum LibError {
 
     #[error("Failed to parse GD3 data")]
     FailedParseGd3,
+
+    #[error("Unsupported VGM version - {version}")]
+    UnsupportedVgmVersion { version: u32 },
+
+    #[error("Loop offset does not point to the start of a command - {offset:#X}")]
+    InvalidLoopOffset { offset: usize },
+
+    #[error("Invalid trim range - start {start}, loop start {loop_start:?}, end {end}")]
+    InvalidTrimRange {
+        start: u64,
+        loop_start: Option<u64>,
+        end: u64,
+    },
+
+    #[error("Invalid VGM listing at line {line} - {message}")]
+    AssemblerError { line: usize, message: String },
+
+    #[error("Every DAC stream id is already used")]
+    NoFreeStreamId,
 }
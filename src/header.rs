@@ -1,21 +1,25 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 use crate::bcd::{bcd_from_bytes, decimal_to_bcd};
+use crate::systems::System;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChipClockEntry {
     pub chip_id: u8,
     pub clock: u32,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChipVolumeEntry {
     pub chip_id: u8,
     pub flags: u8,
     pub volume: u16,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExtraHeaderData {
     pub header_size: u32,
     pub chip_clock_offset: u32,
@@ -24,7 +28,8 @@ pub struct ExtraHeaderData {
     pub chip_volume_entries: Vec<ChipVolumeEntry>,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HeaderData {
     pub end_of_file_offset: u32,
     pub version: u32,
@@ -269,6 +274,110 @@ impl HeaderData {
         }
     }
 
+    /// Absolute position of the first VGM command
+    /// Before 1.50 the data offset field did not exist and the data always starts at 0x40
+    pub fn vgm_data_pos(&self) -> usize {
+        if self.version < 150 || self.vgm_data_offset == 0 {
+            0x40
+        } else {
+            (self.vgm_data_offset + 0x34) as usize
+        }
+    }
+
+    /// Clock field of a chip, including the dual chip (bit 30) and variant (bit 31) flags.
+    /// Chips sharing a field (K051649/K052539, ES5505/ES5506) return the same one.
+    pub fn clock_mut(&mut self, system: &System) -> &mut u32 {
+        match system {
+            System::Sn76489 => &mut self.sn76489_clock,
+            System::Ym2413 => &mut self.ym2413_clock,
+            System::Ym2612 => &mut self.ym2612_clock,
+            System::Ym2151 => &mut self.ym2151_clock,
+            System::SegaPcm => &mut self.sega_pcm_clock,
+            System::Rf5c68 => &mut self.rf5c68_clock,
+            System::Ym2203 => &mut self.ym2203_clock,
+            System::Ym2608 => &mut self.ym2608_clock,
+            System::Ym2610 => &mut self.ym2610b_clock,
+            System::Ym3812 => &mut self.ym3812_clock,
+            System::Ym3526 => &mut self.ym3526_clock,
+            System::Y8950 => &mut self.y8950_clock,
+            System::Ymf262 => &mut self.ymf262_clock,
+            System::Ymf278B => &mut self.ymf278b_clock,
+            System::Ymf271 => &mut self.ymf271_clock,
+            System::Ymz280b => &mut self.ymz280b_clock,
+            System::Rf5c164 => &mut self.rf5c164_clock,
+            System::Pwm => &mut self.pwm_clock,
+            System::Ay8910 => &mut self.ay8910_clock,
+            System::GameboyDmg => &mut self.gb_dmg_clock,
+            System::NesApu => &mut self.nes_apu_clock,
+            System::MultiPcm => &mut self.multi_pcm_clock,
+            System::Upd7759 => &mut self.upd7759_clock,
+            System::Okim6258 => &mut self.okim6258_clock,
+            System::K054539 => &mut self.k054539_clock,
+            System::C140 => &mut self.c140_clock,
+            System::Okim6295 => &mut self.okim6295_clock,
+            System::K051649 | System::K052539 => &mut self.k051649_k052539_clock,
+            System::HuC6280 => &mut self.huc6280_clock,
+            System::K053260 => &mut self.k053260_clock,
+            System::Pokey => &mut self.pokey_clock,
+            System::QSound => &mut self.qsound_clock,
+            System::Scsp => &mut self.scsp_clock,
+            System::WonderSwan => &mut self.wonderswan_clock,
+            System::Vsu => &mut self.vsu_clock,
+            System::Saa1099 => &mut self.saa1099_clock,
+            System::Es5503 => &mut self.es5503_clock,
+            System::Es5505 | System::Es5506 => &mut self.es5506_clock,
+            System::C352 => &mut self.c352_clock,
+            System::X1_010 => &mut self.x1010_clock,
+            System::Ga20 => &mut self.ga20_clock,
+        }
+    }
+
+    pub fn clock(&self, system: &System) -> u32 {
+        match system {
+            System::Sn76489 => self.sn76489_clock,
+            System::Ym2413 => self.ym2413_clock,
+            System::Ym2612 => self.ym2612_clock,
+            System::Ym2151 => self.ym2151_clock,
+            System::SegaPcm => self.sega_pcm_clock,
+            System::Rf5c68 => self.rf5c68_clock,
+            System::Ym2203 => self.ym2203_clock,
+            System::Ym2608 => self.ym2608_clock,
+            System::Ym2610 => self.ym2610b_clock,
+            System::Ym3812 => self.ym3812_clock,
+            System::Ym3526 => self.ym3526_clock,
+            System::Y8950 => self.y8950_clock,
+            System::Ymf262 => self.ymf262_clock,
+            System::Ymf278B => self.ymf278b_clock,
+            System::Ymf271 => self.ymf271_clock,
+            System::Ymz280b => self.ymz280b_clock,
+            System::Rf5c164 => self.rf5c164_clock,
+            System::Pwm => self.pwm_clock,
+            System::Ay8910 => self.ay8910_clock,
+            System::GameboyDmg => self.gb_dmg_clock,
+            System::NesApu => self.nes_apu_clock,
+            System::MultiPcm => self.multi_pcm_clock,
+            System::Upd7759 => self.upd7759_clock,
+            System::Okim6258 => self.okim6258_clock,
+            System::K054539 => self.k054539_clock,
+            System::C140 => self.c140_clock,
+            System::Okim6295 => self.okim6295_clock,
+            System::K051649 | System::K052539 => self.k051649_k052539_clock,
+            System::HuC6280 => self.huc6280_clock,
+            System::K053260 => self.k053260_clock,
+            System::Pokey => self.pokey_clock,
+            System::QSound => self.qsound_clock,
+            System::Scsp => self.scsp_clock,
+            System::WonderSwan => self.wonderswan_clock,
+            System::Vsu => self.vsu_clock,
+            System::Saa1099 => self.saa1099_clock,
+            System::Es5503 => self.es5503_clock,
+            System::Es5505 | System::Es5506 => self.es5506_clock,
+            System::C352 => self.c352_clock,
+            System::X1_010 => self.x1010_clock,
+            System::Ga20 => self.ga20_clock,
+        }
+    }
+
     /// Read header data
     /// From 1.5 onwards, any length of header is valid as long as it is at least 64 bytes long
     pub fn from_bytes(data: &mut Bytes) -> Self {
@@ -304,191 +413,191 @@ impl HeaderData {
         header.sega_pcm_clock = data.get_u32_le();
         header.spcm_interface = data.get_u32_le();
 
-        let pos_start_vgm = header.vgm_data_offset + 0x34;
+        let pos_start_vgm = header.vgm_data_pos();
 
         // 0x40
         // From here, need to check if is still header, or start of vgm data
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.rf5c68_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ym2203_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ym2608_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ym2610b_clock = data.get_u32_le();
 
         // 0x50
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ym3812_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ym3526_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.y8950_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ymf262_clock = data.get_u32_le();
 
         // 0x60
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ymf278b_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ymf271_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ymz280b_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.rf5c164_clock = data.get_u32_le();
 
         // 0x70
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.pwm_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ay8910_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ay8910_chip_type = data.get_u8();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ay8910_flags = data.get_u8();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ym2203_ay8910_flags = data.get_u8();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.ym2608_ay8910_flags = data.get_u8();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.volume_modifier = data.get_u8();
 
         // skip reserved
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         data.get_u8();
 
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.loop_base = data.get_u8();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.loop_modifier = data.get_u8();
 
         // 0x80
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.gb_dmg_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.nes_apu_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.multi_pcm_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.upd7759_clock = data.get_u32_le();
 
         // 0x90
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.okim6258_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.okim6258_flags = data.get_u8();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.k054539_flags = data.get_u8();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.c140_chip_type = data.get_u8();
 
         // skip reserved
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         data.get_u8();
 
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.okim6295_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.k051649_k052539_clock = data.get_u32_le();
 
         // 0xA0
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.k054539_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.huc6280_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.c140_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.k053260_clock = data.get_u32_le();
 
         // 0xB0
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.pokey_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.qsound_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.scsp_clock = data.get_u32_le();
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         }
         header.extra_header_offset = data.get_u32_le();
@@ -502,7 +611,7 @@ impl HeaderData {
         // 0xC0
         // from here need to also check for extra header data
         // can assume that after extra header is vgm data?
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -512,7 +621,7 @@ impl HeaderData {
         }
         header.wonderswan_clock = data.get_u32_le();
 
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -522,7 +631,7 @@ impl HeaderData {
         }
         header.vsu_clock = data.get_u32_le();
 
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -532,7 +641,7 @@ impl HeaderData {
         }
         header.saa1099_clock = data.get_u32_le();
 
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -543,7 +652,7 @@ impl HeaderData {
         header.es5503_clock = data.get_u32_le();
 
         // 0xD0
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -553,7 +662,7 @@ impl HeaderData {
         }
         header.es5506_clock = data.get_u32_le();
 
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -563,7 +672,7 @@ impl HeaderData {
         }
         header.es5503_nb_channels = data.get_u8();
 
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -573,7 +682,7 @@ impl HeaderData {
         }
         header.es5505_es5506_nb_channels = data.get_u8();
 
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -584,7 +693,7 @@ impl HeaderData {
         header.c352_clock_divider = data.get_u8();
 
         // skip reserved
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -594,7 +703,7 @@ impl HeaderData {
         }
         data.get_u8();
 
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -604,7 +713,7 @@ impl HeaderData {
         }
         header.x1010_clock = data.get_u32_le();
 
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -615,7 +724,7 @@ impl HeaderData {
         header.c352_clock = data.get_u32_le();
 
         // 0xE0
-        if (len_data - data.remaining()) == pos_start_vgm as usize {
+        if (len_data - data.remaining()) == pos_start_vgm {
             return header;
         } else if let Some(pos_extra_header) = pos_extra_header {
             if (len_data - data.remaining()) == pos_extra_header {
@@ -625,11 +734,20 @@ impl HeaderData {
         }
         header.ga20_clock = data.get_u32_le();
 
+        // extra header placed after the end of the known fields
+        if let Some(pos_extra_header) = pos_extra_header {
+            let curr_pos = len_data - data.remaining();
+            if curr_pos <= pos_extra_header && pos_extra_header < pos_start_vgm {
+                data.advance(pos_extra_header - curr_pos);
+                header.parse_extra_header(data, pos_extra_header);
+            }
+        }
+
         header
     }
 
     pub fn to_bytes(&self, buffer: &mut BytesMut) {
-        let vgm_data_pos = (self.vgm_data_offset + 0x34) as usize;
+        let vgm_data_pos = self.vgm_data_pos();
         let extra_header_pos = if self.extra_header_offset == 0 {
             None
         } else {
@@ -1000,23 +1118,36 @@ impl HeaderData {
             }
         }
         buffer.put(&self.ga20_clock.to_le_bytes()[..]);
+
+        // extra header placed after the end of the known fields
+        if let Some(extra_header_pos) = extra_header_pos {
+            if buffer.len() <= extra_header_pos && extra_header_pos < vgm_data_pos {
+                buffer.put_bytes(0x00, extra_header_pos - buffer.len());
+                self.write_extra_header(buffer, vgm_data_pos);
+                return;
+            }
+        }
+
+        // pad until start of VGM
+        if buffer.len() < vgm_data_pos {
+            buffer.put_bytes(0x00, vgm_data_pos - buffer.len());
+        }
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "serde"))]
 mod tests {
     use std::fs;
 
-    use bytes::{Buf, Bytes, BytesMut};
-
-    use crate::traits::{VgmParser, VgmWriter};
+    use bytes::{Bytes, BytesMut};
 
     use super::HeaderData;
 
     #[test]
+    #[ignore = "needs ./vgm_files/Into Battle.vgm, which is not in the repository"]
     fn header_170() {
-        let FILENAME = "./vgm_files/Into Battle.vgm";
-        let data = fs::read(FILENAME).unwrap();
+        let filename = "./vgm_files/Into Battle.vgm";
+        let data = fs::read(filename).unwrap();
         let mut mem = Bytes::from(data.clone());
 
         let header = HeaderData::from_bytes(&mut mem);
@@ -1025,10 +1156,12 @@ mod tests {
         let mut out_buffer = BytesMut::new();
         header.to_bytes(&mut out_buffer);
 
-        fs::write("./generated/Into Battle.bin", out_buffer);
+        fs::create_dir_all("./generated").unwrap();
+        fs::write("./generated/Into Battle.bin", out_buffer).unwrap();
         fs::write(
             "./generated/Into Battle.json",
             serde_json::to_string(&header).unwrap(),
-        );
+        )
+        .unwrap();
     }
 }
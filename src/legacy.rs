@@ -0,0 +1,192 @@
+use crate::command::Command;
+use crate::header::HeaderData;
+use crate::vgmfile::VgmFile;
+
+/// Feedback pattern of the SN76489 for files before 1.10 (Sega Master System / Mega Drive PSG)
+pub const LEGACY_SN76489_FEEDBACK: u16 = 0x0009;
+
+/// Noise shift register width of the SN76489 for files before 1.10
+pub const LEGACY_SN76489_SHIFT_REGISTER_WIDTH: u8 = 16;
+
+/// Values of the header fields that older VGM versions leave implicit.
+/// Everything here is what a player should use, whatever the version of the file.
+#[derive(Default, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EffectiveHeader {
+    pub ym2413_clock: u32,
+    pub ym2612_clock: u32,
+    pub ym2151_clock: u32,
+    pub sn76489_feedback: u16,
+    pub sn76489_shift_register_width: u8,
+    pub rate: u32,
+}
+
+impl HeaderData {
+    /// Before 1.10 the YM2413 clock field was shared by the YM2413, YM2612 and YM2151
+    pub fn effective_ym2612_clock(&self) -> u32 {
+        if self.version < 110 {
+            self.ym2413_clock
+        } else {
+            self.ym2612_clock
+        }
+    }
+
+    pub fn effective_ym2151_clock(&self) -> u32 {
+        if self.version < 110 {
+            self.ym2413_clock
+        } else {
+            self.ym2151_clock
+        }
+    }
+
+    /// Before 1.10 the feedback field did not exist, 0x0009 is assumed when the PSG is used
+    pub fn effective_sn76489_feedback(&self) -> u16 {
+        if self.version >= 110 {
+            self.sn76489_feedback
+        } else if self.sn76489_clock != 0 {
+            LEGACY_SN76489_FEEDBACK
+        } else {
+            0
+        }
+    }
+
+    /// Before 1.10 the shift register width field did not exist, 16 is assumed when the PSG is used
+    pub fn effective_sn76489_shift_register_width(&self) -> u8 {
+        if self.version >= 110 {
+            self.sn76489_shift_register_width
+        } else if self.sn76489_clock != 0 {
+            LEGACY_SN76489_SHIFT_REGISTER_WIDTH
+        } else {
+            0
+        }
+    }
+
+    /// The rate field was added in 1.01, anything stored there in a 1.00 file is meaningless.
+    /// 0 means that the recording rate is unknown.
+    pub fn effective_rate(&self) -> u32 {
+        if self.version < 101 {
+            0
+        } else {
+            self.rate
+        }
+    }
+
+    pub fn effective(&self) -> EffectiveHeader {
+        EffectiveHeader {
+            ym2413_clock: self.ym2413_clock,
+            ym2612_clock: self.effective_ym2612_clock(),
+            ym2151_clock: self.effective_ym2151_clock(),
+            sn76489_feedback: self.effective_sn76489_feedback(),
+            sn76489_shift_register_width: self.effective_sn76489_shift_register_width(),
+            rate: self.effective_rate(),
+        }
+    }
+
+    /// Write the implicit values of a pre-1.10 header into their explicit fields and bump the version to 1.10.
+    /// Without looking at the commands we can't know which of the YM2413, YM2612 and YM2151 the shared clock
+    /// belongs to, so it is copied to all of them. Use `VgmFile::normalize_legacy_header` to only keep the used ones.
+    pub fn normalize_legacy(&mut self) {
+        if self.version >= 110 {
+            return;
+        }
+
+        let effective = self.effective();
+        self.ym2612_clock = effective.ym2612_clock;
+        self.ym2151_clock = effective.ym2151_clock;
+        self.sn76489_feedback = effective.sn76489_feedback;
+        self.sn76489_shift_register_width = effective.sn76489_shift_register_width;
+        self.rate = effective.rate;
+        self.version = 110;
+    }
+}
+
+impl VgmFile {
+    /// Same as `HeaderData::normalize_legacy`, but the shared YM2413 clock is only kept for the chips
+    /// that actually receive writes in the command stream.
+    pub fn normalize_legacy_header(&mut self) {
+        if self.header.version >= 110 {
+            return;
+        }
+
+        let uses_ym2413 = self
+            .commands
+            .iter()
+            .any(|cmd| matches!(cmd, Command::YM2413Write { .. }));
+        let uses_ym2612 = self.commands.iter().any(|cmd| {
+            matches!(
+                cmd,
+                Command::YM2612Port0Write { .. }
+                    | Command::YM2612Port1Write { .. }
+                    | Command::YM2612Port0Address2AWriteWait { .. }
+            )
+        });
+        let uses_ym2151 = self
+            .commands
+            .iter()
+            .any(|cmd| matches!(cmd, Command::YM2151Write { .. }));
+
+        self.header.normalize_legacy();
+
+        if !uses_ym2413 {
+            self.header.ym2413_clock = 0;
+        }
+        if !uses_ym2612 {
+            self.header.ym2612_clock = 0;
+        }
+        if !uses_ym2151 {
+            self.header.ym2151_clock = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+
+    use crate::header::HeaderData;
+
+    use super::{LEGACY_SN76489_FEEDBACK, LEGACY_SN76489_SHIFT_REGISTER_WIDTH};
+
+    fn header_101() -> Bytes {
+        let mut buffer = BytesMut::new();
+        buffer.put(&b"Vgm "[..]);
+        buffer.put_u32_le(0);
+        buffer.put(&[0x01, 0x01, 0x00, 0x00][..]);
+        buffer.put_u32_le(3579545); // SN76489
+        buffer.put_u32_le(7670453); // YM2413, used by the YM2612 here
+        buffer.put_bytes(0, 0x10);
+        buffer.put_u32_le(60); // rate
+        buffer.put_bytes(0, 0x18);
+        // first command, must not be read as header
+        buffer.put(&[0x52, 0x2B, 0x80][..]);
+        buffer.freeze()
+    }
+
+    #[test]
+    fn legacy_header_101() {
+        let mut data = header_101();
+        let mut header = HeaderData::from_bytes(&mut data);
+        assert_eq!(header.version, 101);
+        assert_eq!(header.vgm_data_pos(), 0x40);
+        assert_eq!(data.len(), 3);
+
+        assert_eq!(header.effective_ym2612_clock(), 7670453);
+        assert_eq!(header.effective_sn76489_feedback(), LEGACY_SN76489_FEEDBACK);
+        assert_eq!(
+            header.effective_sn76489_shift_register_width(),
+            LEGACY_SN76489_SHIFT_REGISTER_WIDTH
+        );
+        assert_eq!(header.effective_rate(), 60);
+
+        header.normalize_legacy();
+        assert_eq!(header.version, 110);
+        assert_eq!(header.ym2612_clock, 7670453);
+        assert_eq!(header.ym2151_clock, 7670453);
+        assert_eq!(header.sn76489_feedback, LEGACY_SN76489_FEEDBACK);
+
+        // still a 0x40 bytes header once upgraded
+        let mut out_buffer = BytesMut::new();
+        header.to_bytes(&mut out_buffer);
+        assert_eq!(out_buffer.len(), 0x40);
+    }
+}
@@ -2,8 +2,35 @@ pub mod bcd;
 pub mod errors;
 
 pub mod command;
+pub mod convert;
+pub mod dac_stream;
+pub mod datablock;
+pub mod disasm;
 pub mod systems;
 
+pub mod adpcm;
+pub mod chip_flags;
+pub mod chip_state;
+pub mod chip_usage;
 pub mod header;
+pub mod legacy;
+pub mod loop_finder;
 pub mod metadata;
+pub mod oki;
+pub mod optimize;
+pub mod pcm_export;
+pub mod playback;
+pub mod registers;
+pub mod seek;
+pub mod segapcm;
+pub mod stats;
+pub mod timeline;
+pub mod trim;
 pub mod vgmfile;
+pub mod wav;
+pub mod ym2608;
+pub mod ym2610;
+pub mod ym2612_pcm;
+
+#[cfg(feature = "serde")]
+mod serde_base64;
@@ -0,0 +1,265 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::chip_state::ChipState;
+use crate::command::Command;
+use crate::playback::VGM_SAMPLE_RATE;
+use crate::vgmfile::VgmFile;
+
+/// Settings of `VgmFile::find_loop`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopFinderOptions {
+    /// Shortest loop accepted, in samples
+    pub min_loop_samples: u64,
+    /// Compare consecutive waits by their total length, so that a wait split differently
+    /// in the two repetitions still matches
+    pub merge_waits: bool,
+    /// Only accept a loop start where the chips are in the same state as at the start
+    /// of the repetition, so that jumping back does not change the sound
+    pub match_chip_state: bool,
+}
+
+impl Default for LoopFinderOptions {
+    fn default() -> Self {
+        LoopFinderOptions {
+            min_loop_samples: VGM_SAMPLE_RATE as u64,
+            merge_waits: true,
+            match_chip_state: false,
+        }
+    }
+}
+
+/// Loop found in the commands, the loop body is `commands[start_index..end_index]`
+/// and `commands[end_index..]` repeats it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundLoop {
+    pub start_index: usize,
+    pub end_index: usize,
+    pub start_sample: u64,
+    pub loop_samples: u64,
+}
+
+/// Commands grouped into the units compared by the loop finder
+struct Token {
+    hash: u64,
+    is_wait: bool,
+    command_index: usize,
+    sample: u64,
+}
+
+/// Whether two tokens are the same, the merged waits hash to their length and the other tokens
+/// are compared by their command once the hashes match
+fn same_token(commands: &[Command], a: &Token, b: &Token, merge_waits: bool) -> bool {
+    a.hash == b.hash
+        && a.is_wait == b.is_wait
+        && ((a.is_wait && merge_waits) || commands[a.command_index] == commands[b.command_index])
+}
+
+fn tokenize(commands: &[Command], merge_waits: bool) -> Vec<Token> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut sample = 0;
+    for (command_index, cmd) in commands.iter().enumerate() {
+        // the 0x8n writes keep their wait, they are compared as a whole
+        let is_wait = cmd.wait_samples() != 0 && cmd.system().is_none();
+        let wait = cmd.wait_samples() as u64;
+
+        match tokens.last_mut() {
+            Some(last) if is_wait && merge_waits && last.is_wait => {
+                last.hash += wait;
+            }
+            _ => {
+                let hash = if is_wait && merge_waits {
+                    wait
+                } else {
+                    let mut hasher = DefaultHasher::new();
+                    cmd.hash(&mut hasher);
+                    hasher.finish()
+                };
+                tokens.push(Token {
+                    hash,
+                    is_wait,
+                    command_index,
+                    sample,
+                });
+            }
+        }
+        sample += wait;
+    }
+
+    tokens
+}
+
+/// Feed the chip state with the commands from `from` up to `index`
+fn state_at(commands: &[Command], state: &mut ChipState, from: &mut usize, index: usize) {
+    for cmd in &commands[*from..index] {
+        state.update(cmd);
+    }
+    *from = index;
+}
+
+impl VgmFile {
+    /// Look for the longest tail of the file made of repetitions of a loop, ignoring the
+    /// loop information of the header. The earliest loop start wins, then the shortest loop.
+    pub fn find_loop(&self, options: &LoopFinderOptions) -> Option<FoundLoop> {
+        let tokens = tokenize(&self.commands, options.merge_waits);
+        let total_samples = self.total_samples();
+
+        // a final wait is usually cut short, it does not take part in the comparison
+        let n = match tokens.last() {
+            Some(last) if last.is_wait => tokens.len() - 1,
+            _ => tokens.len(),
+        };
+        let sample_at = |index: usize| tokens.get(index).map_or(total_samples, |t| t.sample);
+        let same = |a: usize, b: usize| {
+            same_token(&self.commands, &tokens[a], &tokens[b], options.merge_waits)
+        };
+
+        let mut best: Option<(usize, usize)> = None;
+        for length in 1..=n / 2 {
+            // earliest start from which every token matches the one a loop later
+            let mut start = n - length;
+            while start > 0 && same(start - 1, start - 1 + length) {
+                start -= 1;
+            }
+
+            // chip states at the start of the loop and of its repetition, carried forward as
+            // `start` grows
+            let mut loop_start = (ChipState::new(), 0);
+            let mut repetition = (ChipState::new(), 0);
+            // at least one full repetition after the loop
+            while start + 2 * length <= n {
+                if sample_at(start + length) - sample_at(start) < options.min_loop_samples {
+                    break;
+                }
+                if best.is_some_and(|(best_start, _)| best_start <= start) {
+                    break;
+                }
+                if options.match_chip_state {
+                    let (state, from) = &mut loop_start;
+                    state_at(&self.commands, state, from, tokens[start].command_index);
+                    let (state, from) = &mut repetition;
+                    let index = tokens[start + length].command_index;
+                    state_at(&self.commands, state, from, index);
+                }
+                if !options.match_chip_state || loop_start.0 == repetition.0 {
+                    best = Some((start, length));
+                    break;
+                }
+                start += 1;
+            }
+        }
+
+        best.map(|(start, length)| FoundLoop {
+            start_index: tokens[start].command_index,
+            end_index: tokens[start + length].command_index,
+            start_sample: tokens[start].sample,
+            loop_samples: tokens[start + length].sample - tokens[start].sample,
+        })
+    }
+
+    /// Truncate the file after the first repetition of a loop and set the loop in the header
+    pub fn apply_loop(&mut self, found: &FoundLoop) {
+        self.commands.truncate(found.end_index);
+        self.loop_index = Some(found.start_index);
+        self.header.total_nb_samples = (found.start_sample + found.loop_samples) as u32;
+        self.header.loop_nb_samples = found.loop_samples as u32;
+        self.update_offsets();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    use super::{FoundLoop, LoopFinderOptions};
+
+    fn note(value: u8) -> Command {
+        Command::PSGWrite { value }
+    }
+
+    #[test]
+    fn split_waits() {
+        let mut commands = vec![note(0x9F), Command::WaitNSamples { n: 100 }];
+        for _ in 0..2 {
+            commands.extend([note(0x90), Command::Wait735Samples, note(0x91)]);
+            commands.extend([Command::WaitNSamples { n: 500 }]);
+        }
+        // the second repetition splits the last wait differently and is cut short
+        commands.truncate(commands.len() - 1);
+        commands.extend([
+            Command::WaitNSamples { n: 400 },
+            Command::WaitNSamples { n: 100 },
+            note(0x90),
+            Command::WaitNSamples { n: 10 },
+        ]);
+
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 150,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let options = LoopFinderOptions {
+            min_loop_samples: 1000,
+            ..Default::default()
+        };
+        let found = vgm.find_loop(&options).unwrap();
+        assert_eq!(
+            found,
+            FoundLoop {
+                start_index: 2,
+                end_index: 6,
+                start_sample: 100,
+                loop_samples: 1235,
+            }
+        );
+
+        let exact = LoopFinderOptions {
+            merge_waits: false,
+            ..options.clone()
+        };
+        assert_eq!(vgm.find_loop(&exact), None);
+
+        vgm.apply_loop(&found);
+        assert_eq!(vgm.commands.len(), 6);
+        assert_eq!(vgm.loop_index, Some(2));
+        assert_eq!(vgm.header.loop_offset as usize + 0x1C, 0x40 + 5);
+        assert_eq!(vgm.header.total_nb_samples, 1335);
+        assert_eq!(vgm.header.loop_nb_samples, 1235);
+    }
+
+    #[test]
+    fn chip_state() {
+        let mut commands = vec![];
+        for _ in 0..3 {
+            commands.extend([note(0x9F), Command::WaitNSamples { n: 100 }]);
+            commands.extend([note(0x90), Command::WaitNSamples { n: 100 }]);
+        }
+        let vgm = VgmFile {
+            header: HeaderData::default(),
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let options = LoopFinderOptions {
+            min_loop_samples: 200,
+            ..Default::default()
+        };
+        assert_eq!(vgm.find_loop(&options).unwrap().start_index, 0);
+        // nothing is written at the start of the file
+        let options = LoopFinderOptions {
+            match_chip_state: true,
+            ..options
+        };
+        assert_eq!(vgm.find_loop(&options).unwrap().start_index, 1);
+    }
+}
@@ -1,12 +1,14 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LanguageData {
     English(Gd3LocaleData),
     Japanese(Gd3LocaleData),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Default, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Gd3LocaleData {
     //pub Language: Language,
     pub track: String,
@@ -15,7 +17,8 @@ pub struct Gd3LocaleData {
     pub author: String,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Default, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VgmMetadata {
     pub english_data: Gd3LocaleData,
     pub japanese_data: Gd3LocaleData,
@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+
+use crate::adpcm::{decode_oki_adpcm, DecodedSample, OkiAdpcm};
+use crate::command::Command;
+use crate::datablock::RomImage;
+use crate::vgmfile::VgmFile;
+
+/// OKIM6258 ADPCM data, read by DAC streams
+pub const DATA_TYPE_OKIM6258_ADPCM: u8 = 0x04;
+/// OKIM6295 ROM dump
+pub const DATA_TYPE_OKIM6295_ROM: u8 = 0x8B;
+
+/// Bit of the OKIM6295 clock field telling the state of pin 7
+const OKIM6295_PIN7_FLAG: u32 = 0x80000000;
+/// Bits of the clock fields used by the flags
+const CLOCK_FLAGS: u32 = 0xC0000000;
+/// Bit of the NMK112 mode banking the phrase table separately from the samples
+const NMK112_TABLE_PAGING: u8 = 0x80;
+/// Size of the phrase table when the NMK112 banks it
+const NMK112_TABLE_SIZE: u32 = 0x400;
+/// OKIM6258 clock dividers, selected by `okim6258_flags` and register 0x0C
+const OKIM6258_DIVIDERS: [u32; 4] = [1024, 768, 512, 512];
+/// Bit of `okim6258_flags` selecting the 3 bits ADPCM
+const OKIM6258_3BIT_FLAG: u8 = 0x04;
+/// Bit of `okim6258_flags` selecting the 12 bits output
+const OKIM6258_12BIT_FLAG: u8 = 0x08;
+
+/// Sample of the OKIM6295 phrase table, `start` and `end` of the decoded sample are the
+/// addresses seen by the chip, `end` is exclusive
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OkiPhrase {
+    pub phrase: u8,
+    pub sample: DecodedSample,
+}
+
+/// Phrase started on a channel, `phrase_index` is its index in the list of phrases
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OkiTrigger {
+    pub command_index: usize,
+    pub sample_position: u64,
+    pub channel: u8,
+    pub attenuation: u8,
+    pub phrase_index: usize,
+}
+
+/// Phrases decoded from the ROM of an OKIM6295 and the commands playing them
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Okim6295Phrases {
+    pub phrases: Vec<OkiPhrase>,
+    pub triggers: Vec<OkiTrigger>,
+}
+
+/// Banking of the ROM of an OKIM6295, either a single bank of 256 KB or the four 64 KB banks
+/// of the NMK112
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Okim6295Banks {
+    bank_base: u32,
+    nmk_mode: u8,
+    nmk_banks: [u8; 4],
+}
+
+impl Okim6295Banks {
+    /// Address in the ROM image of an address of the chip
+    fn rom_address(&self, address: u32) -> u32 {
+        if self.nmk_mode == 0 {
+            return self.bank_base | address;
+        }
+
+        let (bank, offset) =
+            if address < NMK112_TABLE_SIZE && self.nmk_mode & NMK112_TABLE_PAGING != 0 {
+                (address >> 8, address & 0xFF)
+            } else {
+                (address >> 16, address & 0xFFFF)
+            };
+        (self.nmk_banks[bank as usize & 0x03] as u32) << 16 | offset
+    }
+
+    fn read(&self, rom: &RomImage, address: u32) -> u8 {
+        rom.read(self.rom_address(address))
+    }
+}
+
+/// Set one byte of a clock from the clock registers 0x08 - 0x0B
+fn set_clock_byte(clock: &mut u32, register: u8, value: u8) {
+    let shift = (register - 0x08) * 8;
+    *clock = *clock & !(0xFF << shift) | (value as u32) << shift;
+}
+
+impl VgmFile {
+    /// Decode the phrases of the OKIM6295 started by the commands of the file, reading the
+    /// phrase table through the bank in use at each start
+    pub fn okim6295_phrases(&self) -> Okim6295Phrases {
+        let rom =
+            RomImage::from_commands(&self.commands, DATA_TYPE_OKIM6295_ROM).unwrap_or_default();
+        let mut clock = self.header.okim6295_clock & !CLOCK_FLAGS;
+        let mut pin7 = self.header.okim6295_clock & OKIM6295_PIN7_FLAG != 0;
+        let mut banks = Okim6295Banks::default();
+        // phrase waiting for the byte selecting its channels
+        let mut phrase = None;
+
+        let mut result = Okim6295Phrases::default();
+        let mut indexes = HashMap::new();
+        for (command_index, (sample_position, _, cmd)) in self.timeline().enumerate() {
+            let Command::OKIM6295Write { register, value } = *cmd else {
+                continue;
+            };
+            match register {
+                0x00 => {
+                    let Some(number) = phrase.take() else {
+                        if value & 0x80 != 0 {
+                            phrase = Some(value & 0x7F);
+                        }
+                        continue;
+                    };
+                    let channels = value >> 4;
+                    if channels == 0 {
+                        continue;
+                    }
+
+                    let rate = clock / if pin7 { 132 } else { 165 };
+                    let phrase_index = *indexes.entry((number, rate, banks)).or_insert_with(|| {
+                        let entry = number as u32 * 8;
+                        let address = |offset: u32| {
+                            ((banks.read(&rom, entry + offset) as u32) << 16
+                                | (banks.read(&rom, entry + offset + 1) as u32) << 8
+                                | banks.read(&rom, entry + offset + 2) as u32)
+                                & 0x3FFFF
+                        };
+                        let start = address(0);
+                        let end = address(3) + 1;
+                        let data: Vec<u8> = (start..end.max(start))
+                            .map(|address| banks.read(&rom, address))
+                            .collect();
+                        result.phrases.push(OkiPhrase {
+                            phrase: number,
+                            sample: DecodedSample {
+                                command_index,
+                                start,
+                                end,
+                                rate,
+                                samples: decode_oki_adpcm(&data),
+                            },
+                        });
+                        result.phrases.len() - 1
+                    });
+                    for channel in (0..4).filter(|channel| channels & 1 << channel != 0) {
+                        result.triggers.push(OkiTrigger {
+                            command_index,
+                            sample_position,
+                            channel,
+                            attenuation: value & 0x0F,
+                            phrase_index,
+                        });
+                    }
+                }
+                0x08..=0x0B => set_clock_byte(&mut clock, register, value),
+                0x0C => pin7 = value != 0,
+                0x0E => banks.nmk_mode = value,
+                0x0F => banks.bank_base = (value as u32) << 18,
+                0x10..=0x13 => banks.nmk_banks[register as usize - 0x10] = value,
+                _ => {}
+            }
+        }
+
+        result
+    }
+
+    /// Decode the ADPCM data played by the OKIM6258, written to its data register between a
+    /// play and a stop command or read from the data bank by DAC streams. `start` and `end` of
+    /// the samples are offsets in the data bank for the streams, and count the bytes written
+    /// for the others.
+    /// The divider and output depth are taken from `okim6258_flags`, files using the 3 bits
+    /// ADPCM give no samples.
+    pub fn okim6258_samples(&self) -> Vec<DecodedSample> {
+        let flags = self.header.okim6258_flags;
+        if flags & OKIM6258_3BIT_FLAG != 0 {
+            return vec![];
+        }
+        // low bits dropped by the 10 bits output
+        let output_mask = if flags & OKIM6258_12BIT_FLAG != 0 {
+            !0
+        } else {
+            !0x03
+        };
+        let decode = |data: &[u8]| -> Vec<i16> {
+            let mut decoder = OkiAdpcm::default();
+            data.iter()
+                .flat_map(|byte| [byte & 0x0F, byte >> 4])
+                .map(|nibble| ((decoder.decode(nibble) & output_mask) << 4) as i16)
+                .collect()
+        };
+
+        let bank: Vec<u8> = self
+            .commands
+            .iter()
+            .filter_map(|cmd| match cmd {
+                Command::DataBlock {
+                    data_type: DATA_TYPE_OKIM6258_ADPCM,
+                    data,
+                    ..
+                } => Some(&data[..]),
+                _ => None,
+            })
+            .flatten()
+            .copied()
+            .collect();
+        let stream_starts: HashMap<usize, _> = self
+            .dac_stream_starts()
+            .into_iter()
+            .filter(|start| start.data_bank_id == DATA_TYPE_OKIM6258_ADPCM)
+            .map(|start| (start.command_index, start))
+            .collect();
+
+        let mut clock = self.header.okim6258_clock & !CLOCK_FLAGS;
+        let mut divider = OKIM6258_DIVIDERS[(flags & 0x03) as usize];
+        // first command and data written since the last play command
+        let mut playing: Option<(usize, Vec<u8>)> = None;
+
+        let finish = |playing: &mut Option<(usize, Vec<u8>)>, rate| {
+            playing
+                .take()
+                .filter(|(_, data)| !data.is_empty())
+                .map(|(command_index, data)| DecodedSample {
+                    command_index,
+                    start: 0,
+                    end: data.len() as u32,
+                    rate,
+                    samples: decode(&data),
+                })
+        };
+        let mut samples = vec![];
+        for (command_index, cmd) in self.commands.iter().enumerate() {
+            if let Some(start) = stream_starts.get(&command_index) {
+                let data: Vec<u8> = (0..start.length)
+                    .filter_map(|index| {
+                        bank.get((start.offset + index * start.step_size.max(1) as u32) as usize)
+                    })
+                    .copied()
+                    .collect();
+                samples.push(DecodedSample {
+                    command_index,
+                    start: start.offset,
+                    end: start.offset + data.len() as u32,
+                    rate: clock / divider,
+                    samples: decode(&data),
+                });
+                continue;
+            }
+
+            let Command::OKIM6258Write { register, value } = *cmd else {
+                continue;
+            };
+            match register {
+                // stop, then play
+                0x00 if value & 0x01 != 0 || value & 0x02 == 0 => {
+                    samples.extend(finish(&mut playing, clock / divider))
+                }
+                0x00 => {
+                    playing.get_or_insert_with(|| (command_index, vec![]));
+                }
+                0x01 => {
+                    if let Some((_, data)) = &mut playing {
+                        data.push(value);
+                    }
+                }
+                0x08..=0x0B => set_clock_byte(&mut clock, register, value),
+                0x0C => divider = OKIM6258_DIVIDERS[(value & 0x03) as usize],
+                _ => {}
+            }
+        }
+        samples.extend(finish(&mut playing, clock / divider));
+
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adpcm::decode_oki_adpcm;
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    use super::DATA_TYPE_OKIM6295_ROM;
+
+    fn rom_block(data: &[u8]) -> Command {
+        let mut block = vec![];
+        block.extend((data.len() as u32).to_le_bytes());
+        block.extend(0u32.to_le_bytes());
+        block.extend(data);
+        Command::DataBlock {
+            data_type: DATA_TYPE_OKIM6295_ROM,
+            data_size: block.len() as u32,
+            data: block,
+        }
+    }
+
+    #[test]
+    fn phrases() {
+        // phrase 1 plays 0x400 - 0x40F, from NMK112 bank 2 0x20400 - 0x2040F
+        let mut rom: Vec<u8> = (0..0x30000).map(|i| i as u8).collect();
+        rom[8..14].copy_from_slice(&[0x00, 0x04, 0x00, 0x00, 0x04, 0x0F]);
+        rom[0x20008..0x2000E].copy_from_slice(&[0x00, 0x04, 0x00, 0x00, 0x04, 0x0F]);
+        let write = |register, value| Command::OKIM6295Write { register, value };
+        let vgm = VgmFile {
+            header: HeaderData {
+                version: 161,
+                okim6295_clock: 1000000 | 0x80000000,
+                ..Default::default()
+            },
+            commands: vec![
+                rom_block(&rom),
+                write(0x00, 0x81),
+                write(0x00, 0x30),
+                Command::Wait735Samples,
+                write(0x00, 0x81),
+                write(0x00, 0x42),
+                // the table and the samples of the first bank are read from bank 2
+                write(0x0E, 0x80),
+                write(0x10, 0x02),
+                write(0x00, 0x81),
+                write(0x00, 0x10),
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let phrases = vgm.okim6295_phrases();
+        assert_eq!(phrases.phrases.len(), 2);
+        let first = &phrases.phrases[0];
+        assert_eq!(
+            (first.phrase, first.sample.start, first.sample.end),
+            (1, 0x400, 0x410)
+        );
+        assert_eq!(first.sample.rate, 7575);
+        assert_eq!(first.sample.samples, decode_oki_adpcm(&rom[0x400..0x410]));
+        assert_eq!(
+            phrases.phrases[1].sample.samples,
+            decode_oki_adpcm(&rom[0x20400..0x20410])
+        );
+
+        let triggers: Vec<_> = phrases
+            .triggers
+            .iter()
+            .map(|trigger| {
+                (
+                    trigger.sample_position,
+                    trigger.channel,
+                    trigger.attenuation,
+                    trigger.phrase_index,
+                )
+            })
+            .collect();
+        assert_eq!(
+            triggers,
+            [(0, 0, 0, 0), (0, 1, 0, 0), (735, 2, 2, 0), (735, 0, 0, 1)]
+        );
+    }
+
+    #[test]
+    fn okim6258_flags() {
+        let write = |register, value| Command::OKIM6258Write { register, value };
+        let commands = vec![
+            write(0x00, 0x02),
+            write(0x01, 0x07),
+            write(0x01, 0x77),
+            write(0x00, 0x01),
+            // ignored while stopped
+            write(0x01, 0x77),
+        ];
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 161,
+                okim6258_clock: 4000000,
+                okim6258_flags: 0x02,
+                ..Default::default()
+            },
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let samples = vgm.okim6258_samples();
+        assert_eq!(samples.len(), 1);
+        assert_eq!((samples[0].command_index, samples[0].end), (0, 2));
+        assert_eq!(samples[0].rate, 7812);
+        // low nibble first, on 10 bits
+        assert_eq!(samples[0].samples[..2], [28 << 4, 32 << 4]);
+
+        vgm.header.okim6258_flags = 0x08;
+        let samples = vgm.okim6258_samples();
+        assert_eq!(samples[0].rate, 3906);
+        assert_eq!(samples[0].samples[..2], [30 << 4, 34 << 4]);
+    }
+}
@@ -0,0 +1,496 @@
+use std::collections::HashMap;
+
+use crate::chip_state::{register_write, ChipState};
+use crate::command::Command;
+use crate::systems::System;
+use crate::vgmfile::VgmFile;
+
+/// Byte counts before and after `VgmFile::compact_waits`
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WaitCompactionReport {
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+    pub waits_before: usize,
+    pub waits_after: usize,
+}
+
+impl WaitCompactionReport {
+    pub fn saved_bytes(&self) -> usize {
+        self.bytes_before - self.bytes_after
+    }
+}
+
+/// Single byte wait commands
+fn short_wait(samples: u64) -> Option<Command> {
+    match samples {
+        735 => Some(Command::Wait735Samples),
+        882 => Some(Command::Wait882Samples),
+        1..=16 => Some(Command::WaitNSamplesPlus1 {
+            n: samples as u8 - 1,
+        }),
+        _ => None,
+    }
+}
+
+/// Wait of at most 65535 samples in as few bytes as possible, then as few commands as possible
+fn shortest_wait(samples: u64) -> Vec<Command> {
+    if samples == 0 {
+        return vec![];
+    }
+    if let Some(cmd) = short_wait(samples) {
+        return vec![cmd];
+    }
+    for first in [735, 882].into_iter().chain((1..=16).rev()) {
+        if let (Some(a), Some(b)) = (
+            short_wait(first),
+            samples.checked_sub(first).and_then(short_wait),
+        ) {
+            return vec![a, b];
+        }
+    }
+
+    vec![Command::WaitNSamples { n: samples as u16 }]
+}
+
+/// Wait commands for a number of samples using the shortest encodings, the 0x61 commands
+/// are split above 65535 samples
+pub fn compact_wait_commands(samples: u64) -> Vec<Command> {
+    let mut commands = vec![];
+    let mut samples = samples;
+    while samples > u16::MAX as u64 {
+        commands.push(Command::WaitNSamples { n: u16::MAX });
+        samples -= u16::MAX as u64;
+    }
+    commands.extend(shortest_wait(samples));
+
+    commands
+}
+
+fn encoded_len(commands: &[Command]) -> usize {
+    commands.iter().map(Command::encoded_len).sum()
+}
+
+fn is_wait(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::WaitNSamples { .. }
+            | Command::Wait735Samples
+            | Command::Wait882Samples
+            | Command::WaitNSamplesPlus1 { .. }
+    )
+}
+
+/// Append a merged wait, part of it goes into the last command if it is a 0x8n and `can_fold`
+fn push_wait(commands: &mut Vec<Command>, samples: u64, can_fold: bool) {
+    if let Some(Command::YM2612Port0Address2AWriteWait { n }) = commands.last_mut() {
+        if can_fold {
+            let room = (15 - (*n as u64).min(15)).min(samples);
+            let folded = (0..=room)
+                .min_by_key(|folded| {
+                    let remaining = compact_wait_commands(samples - folded);
+                    (encoded_len(&remaining), remaining.len())
+                })
+                .unwrap_or(0);
+            *n += folded as u8;
+            commands.extend(compact_wait_commands(samples - folded));
+            return;
+        }
+    }
+
+    commands.extend(compact_wait_commands(samples));
+}
+
+impl VgmFile {
+    /// Merge consecutive waits and write them with the shortest commands, part of a wait following
+    /// a 0x8n YM2612 write is moved into it when that saves bytes.
+    /// Every command keeps its sample position and the loop point stays on the same command.
+    pub fn compact_waits(&mut self) -> WaitCompactionReport {
+        let mut report = WaitCompactionReport {
+            bytes_before: encoded_len(&self.commands),
+            waits_before: self.commands.iter().filter(|cmd| is_wait(cmd)).count(),
+            ..Default::default()
+        };
+
+        let mut commands: Vec<Command> = Vec::with_capacity(self.commands.len());
+        let mut loop_index = None;
+        let mut pending: u64 = 0;
+        for (index, cmd) in self.commands.iter().enumerate() {
+            // waits are not merged over the loop point
+            if Some(index) == self.loop_index {
+                push_wait(&mut commands, pending, true);
+                pending = 0;
+                loop_index = Some(commands.len());
+            }
+
+            if is_wait(cmd) {
+                pending += cmd.wait_samples() as u64;
+            } else {
+                let can_fold = loop_index != Some(commands.len());
+                push_wait(&mut commands, pending, can_fold);
+                pending = 0;
+                commands.push(cmd.clone());
+            }
+        }
+        let can_fold = loop_index != Some(commands.len());
+        push_wait(&mut commands, pending, can_fold);
+        if self
+            .loop_index
+            .is_some_and(|index| index >= self.commands.len())
+        {
+            loop_index = Some(commands.len());
+        }
+
+        self.commands = commands;
+        self.loop_index = loop_index;
+        self.update_offsets();
+
+        report.bytes_after = encoded_len(&self.commands);
+        report.waits_after = self.commands.iter().filter(|cmd| is_wait(cmd)).count();
+        report
+    }
+}
+
+/// Commands removed by `VgmFile::remove_redundant_writes`, by chip
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RedundantWriteReport {
+    pub removed: HashMap<System, usize>,
+}
+
+impl RedundantWriteReport {
+    pub fn total(&self) -> usize {
+        self.removed.values().sum()
+    }
+}
+
+/// Registers where writing the value they already hold still does something: key-on, retrigger
+/// and timer registers, FIFO and data ports, and registers applying to a channel selected
+/// by another register, which the shadow registers do not follow. The addresses are the ones
+/// of the register files, without the bit selecting the second chip.
+fn has_side_effect(system: &System, address: u32, value: u16) -> bool {
+    // the frequency writes go through a latch shared by the channels, 0xA4 to 0xAE set it
+    // and 0xA0 to 0xAA apply it, on both ports
+    let opn_frequency = (0xA0..=0xAE).contains(&(address & 0xFF));
+    match system {
+        // key on, timers, DAC data
+        System::Ym2612 => opn_frequency || matches!(address, 0x27 | 0x28 | 0x2A),
+        // SSG envelope shape, key on, timers
+        System::Ym2203 => opn_frequency || matches!(address, 0x0D | 0x27 | 0x28 | 0x2A),
+        // SSG envelope shape, rhythm key on, ADPCM-B control and data
+        System::Ym2608 => {
+            opn_frequency || matches!(address, 0x0D | 0x10 | 0x27 | 0x28 | 0x100 | 0x108)
+        }
+        // SSG envelope shape, ADPCM-B control, ADPCM-A key on
+        System::Ym2610 => {
+            opn_frequency || matches!(address, 0x0D | 0x10 | 0x1C | 0x27 | 0x28 | 0x100)
+        }
+        System::Ym2151 => matches!(address, 0x08 | 0x14),
+        // rhythm and channel key on bits
+        System::Ym2413 => matches!(address, 0x0E | 0x20..=0x28),
+        // ADPCM control and data
+        System::Y8950 => matches!(address, 0x07 | 0x0F),
+        // envelope shape restarts the envelope
+        System::Ay8910 => address == 13,
+        // trigger bit of NR14, NR24, NR34 and NR44
+        System::GameboyDmg => matches!(address, 0x04 | 0x09 | 0x0E | 0x13) && value & 0x80 != 0,
+        // length counter reload, DMC direct load and channel enable
+        System::NesApu => matches!(address, 0x03 | 0x07 | 0x0B | 0x0F | 0x11 | 0x15),
+        // channel registers of the selected channel
+        System::Rf5c68 | System::Rf5c164 => address <= 0x06,
+        System::HuC6280 => (0x02..=0x09).contains(&address),
+        // left, right and mono FIFO samples
+        System::Pwm => (0x02..=0x04).contains(&address),
+        // key on bit of the voice mode register
+        System::C140 => address < 0x180 && address & 0x0F == 0x05 && value & 0x80 != 0,
+        // key on and key off strobes
+        System::K054539 => matches!(address, 0x214 | 0x215),
+        System::K053260 => address == 0x28,
+        System::Ga20 => address < 0x20 && address & 0x07 == 0x06,
+        // the chip clears the key on bit at the end of a sample
+        System::X1_010 => address < 0x80 && address & 0x07 == 0x00,
+        // command ports and register banks
+        System::Okim6258
+        | System::Okim6295
+        | System::Upd7759
+        | System::MultiPcm
+        | System::Es5503
+        | System::Es5505
+        | System::Es5506
+        | System::Ymz280b
+        | System::QSound => true,
+        _ => false,
+    }
+}
+
+/// Whether a command only writes values the chip already holds
+fn is_redundant(state: &ChipState, cmd: &Command) -> bool {
+    match cmd {
+        Command::PSGWrite { .. } => {
+            let mut after = state.clone();
+            after.update(cmd);
+            let address = ((after.sn76489_latch() >> 4) & 0x07) as u32;
+            // writing the noise register resets the shift register
+            address != 6
+                && after.sn76489_latch() == state.sn76489_latch()
+                && after.register(System::Sn76489, address)
+                    == state.register(System::Sn76489, address)
+        }
+        _ => match register_write(cmd) {
+            Some((chip, address, value)) => {
+                !has_side_effect(&chip.system, address, value)
+                    && state
+                        .registers(&chip)
+                        .and_then(|registers| registers.get(address))
+                        == Some(value)
+            }
+            None => false,
+        },
+    }
+}
+
+impl VgmFile {
+    /// Remove the register writes that set a register to the value it already holds.
+    /// Inside the loop a write is only removed if it is also redundant when the file
+    /// jumps back to the loop point.
+    pub fn remove_redundant_writes(&mut self) -> RedundantWriteReport {
+        let mut report = RedundantWriteReport::default();
+
+        let mut state = ChipState::new();
+        let mut loop_state = None;
+        let mut commands = Vec::with_capacity(self.commands.len());
+        let mut loop_index = None;
+        for (index, cmd) in self.commands.iter().enumerate() {
+            if Some(index) == self.loop_index {
+                loop_index = Some(commands.len());
+                loop_state = Some(self.chip_state_at(self.commands.len()));
+            }
+
+            let redundant = is_redundant(&state, cmd)
+                && loop_state
+                    .as_ref()
+                    .is_none_or(|loop_state| is_redundant(loop_state, cmd));
+            state.update(cmd);
+            if let Some(loop_state) = loop_state.as_mut() {
+                loop_state.update(cmd);
+            }
+
+            match cmd.system() {
+                Some(system) if redundant => *report.removed.entry(system).or_default() += 1,
+                _ => commands.push(cmd.clone()),
+            }
+        }
+        if self
+            .loop_index
+            .is_some_and(|index| index >= self.commands.len())
+        {
+            loop_index = Some(commands.len());
+        }
+
+        self.commands = commands;
+        self.loop_index = loop_index;
+        self.update_offsets();
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    use crate::systems::System;
+
+    use super::compact_wait_commands;
+
+    #[test]
+    fn shortest_encodings() {
+        assert_eq!(compact_wait_commands(735), [Command::Wait735Samples]);
+        assert_eq!(
+            compact_wait_commands(1617),
+            [Command::Wait735Samples, Command::Wait882Samples]
+        );
+        assert_eq!(
+            compact_wait_commands(20),
+            [
+                Command::WaitNSamplesPlus1 { n: 15 },
+                Command::WaitNSamplesPlus1 { n: 3 }
+            ]
+        );
+        assert_eq!(
+            compact_wait_commands(1000),
+            [Command::WaitNSamples { n: 1000 }]
+        );
+        assert_eq!(
+            compact_wait_commands(65535 + 882),
+            [Command::WaitNSamples { n: 65535 }, Command::Wait882Samples]
+        );
+        assert_eq!(compact_wait_commands(0), []);
+    }
+
+    #[test]
+    fn compact_waits() {
+        let psg = Command::PSGWrite { value: 0x9F };
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 150,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands: vec![
+                psg.clone(),
+                Command::WaitNSamples { n: 700 },
+                Command::WaitNSamples { n: 35 },
+                psg.clone(),
+                Command::YM2612Port0Address2AWriteWait { n: 1 },
+                Command::WaitNSamples { n: 4 },
+                Command::WaitNSamples { n: 10 },
+                // loop point
+                Command::WaitNSamples { n: 2 },
+                psg.clone(),
+                Command::WaitNSamples { n: 0 },
+                Command::EndOfSoundData,
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: Some(7),
+        };
+        let total_samples = vgm.total_samples();
+
+        let report = vgm.compact_waits();
+        assert_eq!(
+            vgm.commands,
+            [
+                psg.clone(),
+                Command::Wait735Samples,
+                psg.clone(),
+                Command::YM2612Port0Address2AWriteWait { n: 15 },
+                Command::WaitNSamplesPlus1 { n: 1 },
+                psg.clone(),
+                Command::EndOfSoundData,
+            ]
+        );
+        assert_eq!(vgm.loop_index, Some(4));
+        assert_eq!(vgm.total_samples(), total_samples);
+        assert_eq!(report.bytes_before, 26);
+        assert_eq!(report.bytes_after, 10);
+        assert_eq!(report.saved_bytes(), 16);
+        assert_eq!(report.waits_before, 6);
+        assert_eq!(report.waits_after, 2);
+    }
+
+    #[test]
+    fn redundant_writes() {
+        let ym2612 = |register, value| Command::YM2612Port0Write { register, value };
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 150,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands: vec![
+                ym2612(0xB0, 0x32),
+                ym2612(0xB0, 0x32),
+                ym2612(0x28, 0xF0),
+                ym2612(0x28, 0xF0),
+                Command::PSGWrite { value: 0x9F },
+                Command::PSGWrite { value: 0x9F },
+                Command::PSGWrite { value: 0xE4 },
+                Command::PSGWrite { value: 0xE4 },
+                Command::AY8910Write {
+                    register: 13,
+                    value: 0x0E,
+                },
+                Command::AY8910Write {
+                    register: 13,
+                    value: 0x0E,
+                },
+                // loop point
+                ym2612(0xB4, 0x22),
+                ym2612(0xB0, 0x32),
+                ym2612(0xB4, 0x22),
+                ym2612(0xB4, 0x23),
+                Command::EndOfSoundData,
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: Some(10),
+        };
+
+        let report = vgm.remove_redundant_writes();
+        assert_eq!(report.removed.get(&System::Ym2612), Some(&3));
+        assert_eq!(report.removed.get(&System::Sn76489), Some(&1));
+        assert_eq!(report.total(), 4);
+        // the first 0xB4 write is needed when jumping back to the loop point
+        assert_eq!(vgm.loop_index, Some(8));
+        assert_eq!(vgm.commands[8], ym2612(0xB4, 0x22));
+        assert_eq!(vgm.commands.len(), 11);
+    }
+
+    #[test]
+    fn side_effects() {
+        let ym2612 = |register, value| Command::YM2612Port0Write { register, value };
+        let game_boy = |register, value| Command::GameBoyDMGWrite { register, value };
+        let c140 = |register, value| Command::C140Write { register, value };
+        let mut commands = vec![
+            // the last 0xA0 write applies the latch written by 0xA4, not 0xA5
+            ym2612(0xA4, 0x22),
+            ym2612(0xA0, 0x11),
+            ym2612(0xA5, 0x33),
+            ym2612(0xA1, 0x44),
+            ym2612(0xA4, 0x55),
+            ym2612(0xA0, 0x11),
+            // trigger of NR14 on the second chip
+            game_boy(0x80 | 0x04, 0x87),
+            game_boy(0x80 | 0x04, 0x87),
+            c140(0x15, 0x80),
+            c140(0x15, 0x80),
+            Command::K053260Write {
+                register: 0x28,
+                value: 0x01,
+            },
+            Command::K053260Write {
+                register: 0x28,
+                value: 0x01,
+            },
+            Command::YM2203Write {
+                register: 0x0D,
+                value: 0x00,
+            },
+            Command::WaitNSamples { n: 1000 },
+            Command::YM2203Write {
+                register: 0x0D,
+                value: 0x00,
+            },
+            Command::PWMWrite {
+                register: 0x04,
+                value: 0x100,
+            },
+            Command::PWMWrite {
+                register: 0x04,
+                value: 0x100,
+            },
+        ];
+        let end = commands.len();
+        // without trigger or key on
+        commands.extend([game_boy(0x80 | 0x12, 0xF3), game_boy(0x80 | 0x12, 0xF3)]);
+        commands.extend([c140(0x15, 0x00), c140(0x15, 0x00)]);
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 161,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let report = vgm.remove_redundant_writes();
+        assert_eq!(report.removed.get(&System::GameboyDmg), Some(&1));
+        assert_eq!(report.removed.get(&System::C140), Some(&1));
+        assert_eq!(report.total(), 2);
+        assert_eq!(vgm.commands.len(), end + 2);
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::HashSet;
+
+use crate::command::Command;
+use crate::datablock::{RomImage, DATA_TYPE_YM2612_PCM};
+use crate::playback::VGM_SAMPLE_RATE;
+use crate::segapcm::SegaPcm;
+use crate::systems::System;
+use crate::vgmfile::VgmFile;
+use crate::wav::WavFile;
+use crate::ym2612_pcm::DEFAULT_SEGMENT_MAX_GAP;
+
+/// RF5C68 PCM data, copied to its RAM by the PCM RAM write commands
+pub const DATA_TYPE_RF5C68_PCM: u8 = 0x01;
+/// RF5C164 PCM data, copied to its RAM by the PCM RAM write commands
+pub const DATA_TYPE_RF5C164_PCM: u8 = 0x02;
+/// C140 ROM dump
+pub const DATA_TYPE_C140_ROM: u8 = 0x8D;
+/// RF5C68 RAM write
+pub const DATA_TYPE_RF5C68_RAM: u8 = 0xC0;
+/// RF5C164 RAM write
+pub const DATA_TYPE_RF5C164_RAM: u8 = 0xC1;
+
+/// Byte ending the samples in the RAM of the RF5C68 and RF5C164
+const RF5C_LOOP_MARKER: u8 = 0xFF;
+
+/// Chip a sample was taken from
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SampleSource {
+    Ym2612,
+    Rf5c68,
+    Rf5c164,
+    SegaPcm,
+    C140,
+    Pwm,
+}
+
+/// Sample found in a file, `start` is its address in the data of the chip, or the sample
+/// position of its first write for the PWM which has no sample memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExtractedSample {
+    pub source: SampleSource,
+    pub start: u32,
+    pub wav: WavFile,
+}
+
+/// Unsigned 8 bits sample of a RF5C68 sign and magnitude byte
+fn rf5c_to_u8(value: u8) -> u8 {
+    if value & 0x80 != 0 {
+        0x80 + (value & 0x7F)
+    } else {
+        0x80 - (value & 0x7F)
+    }
+}
+
+/// 16 bits sample of a C140 byte, either linear or in its compressed format
+fn c140_to_i16(value: u8, compressed: bool) -> i16 {
+    if !compressed {
+        return (value as i8 as i16) << 8;
+    }
+
+    // start of each of the 8 segments of the compressed format
+    let segment_base = |segment: i32| (0..segment).map(|i| 16 << i).sum::<i32>();
+    let value = value as i8 as i32;
+    let exponent = value & 0x07;
+    let mantissa = value >> 3;
+    let sample = if mantissa < 0 {
+        (mantissa << exponent) - segment_base(exponent)
+    } else {
+        (mantissa << exponent) + segment_base(exponent)
+    };
+    sample.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+}
+
+/// Collects the samples and drops the ones already found
+#[derive(Default)]
+struct SampleSet {
+    samples: Vec<ExtractedSample>,
+    seen: HashSet<(SampleSource, u32, Vec<u8>)>,
+}
+
+impl SampleSet {
+    fn add(&mut self, source: SampleSource, start: u32, mut wav: WavFile) {
+        if wav.is_empty() {
+            return;
+        }
+        // no clock in the header or a single write, the rate is unknown
+        if wav.sample_rate == 0 {
+            wav.sample_rate = VGM_SAMPLE_RATE;
+        }
+        if self.seen.insert((source, start, wav.data.clone())) {
+            self.samples.push(ExtractedSample { source, start, wav });
+        }
+    }
+}
+
+/// RAM and registers of a RF5C68 or RF5C164
+struct Rf5c {
+    ram: Vec<u8>,
+    /// 4KB window of the RAM written by the memory write commands
+    bank: u16,
+    channel: u8,
+    /// Start address and frequency step of every channel
+    start: [u8; 8],
+    step: [u16; 8],
+    /// Channels turned off, a bit at 1 is off
+    off: u8,
+}
+
+impl Rf5c {
+    fn new() -> Self {
+        Rf5c {
+            ram: vec![0; 0x10000],
+            bank: 0,
+            channel: 0,
+            start: [0; 8],
+            step: [0; 8],
+            off: 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u32, data: &[u8]) {
+        for (i, &value) in data.iter().enumerate() {
+            self.ram[(address as usize + i) & 0xFFFF] = value;
+        }
+    }
+
+    /// Apply a register write, returns the channels it keys on
+    fn write(&mut self, register: u8, value: u8) -> Vec<u8> {
+        let channel = self.channel as usize;
+        match register {
+            0x02 => self.step[channel] = self.step[channel] & 0xFF00 | value as u16,
+            0x03 => self.step[channel] = self.step[channel] & 0x00FF | (value as u16) << 8,
+            0x06 => self.start[channel] = value,
+            0x07 if value & 0x40 != 0 => self.channel = value & 0x07,
+            0x07 => self.bank = (value & 0x0F) as u16,
+            0x08 => {
+                let keyed_on = self.off & !value;
+                self.off = value;
+                return (0..8).filter(|bit| keyed_on & 1 << bit != 0).collect();
+            }
+            _ => {}
+        }
+
+        vec![]
+    }
+
+    /// Bytes of the sample of a channel up to the end marker
+    fn sample(&self, channel: u8) -> (u32, Vec<u8>) {
+        let start = (self.start[channel as usize] as u32) << 8;
+        let data = (0..0x10000)
+            .map(|i| self.ram[(start as usize + i) & 0xFFFF])
+            .take_while(|&value| value != RF5C_LOOP_MARKER)
+            .map(rf5c_to_u8)
+            .collect();
+        (start, data)
+    }
+}
+
+impl VgmFile {
+    /// Every sample played from the PCM data and ROMs of the file, as WAV.
+    ///
+    /// The YM2612 samples are cut where the 0x8n commands and DAC streams play them,
+    /// the RF5C68 and RF5C164 ones from their start address to the end marker, the SegaPCM and
+    /// C140 ones by the addresses of their key on, and the PWM ones are the runs of writes.
+    /// The rate comes from the DAC stream frequency, the timing of the writes or the clock and
+    /// frequency registers of the chip.
+    pub fn extract_samples(&self) -> Vec<ExtractedSample> {
+        let mut set = SampleSet::default();
+        self.extract_ym2612_samples(&mut set);
+        self.extract_rf5c_samples(&mut set);
+        self.extract_sega_pcm_samples(&mut set);
+        self.extract_c140_samples(&mut set);
+        self.extract_pwm_samples(&mut set);
+
+        set.samples
+    }
+
+    fn extract_ym2612_samples(&self, set: &mut SampleSet) {
+        let bank: Vec<u8> = self
+            .commands
+            .iter()
+            .filter_map(|cmd| match cmd {
+                Command::DataBlock {
+                    data_type: DATA_TYPE_YM2612_PCM,
+                    data,
+                    ..
+                } => Some(data.as_slice()),
+                _ => None,
+            })
+            .flatten()
+            .copied()
+            .collect();
+        let slice = |offset: usize, length: usize, step: usize| -> Vec<u8> {
+            bank.iter()
+                .skip(offset)
+                .step_by(step.max(1))
+                .take(length)
+                .copied()
+                .collect()
+        };
+
+        for start in self.dac_stream_starts() {
+            if start.data_bank_id == DATA_TYPE_YM2612_PCM {
+                let data = slice(
+                    start.offset as usize,
+                    start.length as usize,
+                    start.step_size as usize,
+                );
+                set.add(
+                    SampleSource::Ym2612,
+                    start.offset,
+                    WavFile::from_u8(start.frequency, data),
+                );
+            }
+        }
+        for drum in self.ym2612_drum_samples(DEFAULT_SEGMENT_MAX_GAP) {
+            let data = slice(drum.offset, drum.length, 1);
+            set.add(
+                SampleSource::Ym2612,
+                drum.offset as u32,
+                WavFile::from_u8(drum.rate, data),
+            );
+        }
+    }
+
+    fn extract_rf5c_samples(&self, set: &mut SampleSet) {
+        for (system, source, data_type, ram_type) in [
+            (
+                System::Rf5c68,
+                SampleSource::Rf5c68,
+                DATA_TYPE_RF5C68_PCM,
+                DATA_TYPE_RF5C68_RAM,
+            ),
+            (
+                System::Rf5c164,
+                SampleSource::Rf5c164,
+                DATA_TYPE_RF5C164_PCM,
+                DATA_TYPE_RF5C164_RAM,
+            ),
+        ] {
+            let clock = self.header.clock(&system) as u64;
+            let mut chip = Rf5c::new();
+            let mut bank = vec![];
+            for cmd in &self.commands {
+                let (register, value) = match *cmd {
+                    Command::DataBlock {
+                        data_type: block_type,
+                        ref data,
+                        ..
+                    } if block_type == data_type => {
+                        bank.extend_from_slice(data);
+                        continue;
+                    }
+                    Command::DataBlock {
+                        data_type: block_type,
+                        ref data,
+                        ..
+                    } if block_type == ram_type && data.len() >= 2 => {
+                        let address = u16::from_le_bytes([data[0], data[1]]) as u32;
+                        chip.write_ram(address, &data[2..]);
+                        continue;
+                    }
+                    Command::PCMRAMWrite {
+                        chip_type,
+                        read_offset,
+                        write_offset,
+                        size,
+                    } if chip_type == data_type => {
+                        let size = if size == 0 { 0x1000000 } else { size };
+                        let end = read_offset.saturating_add(size).min(bank.len() as u32);
+                        let start = read_offset.min(end);
+                        chip.write_ram(write_offset, &bank[start as usize..end as usize]);
+                        continue;
+                    }
+                    Command::RF5C68WriteOffset { offset, value } if system == System::Rf5c68 => {
+                        let address = (chip.bank as u32) << 12 | (offset & 0x0FFF) as u32;
+                        chip.write_ram(address, &[value]);
+                        continue;
+                    }
+                    Command::RF5C164WriteOffset { offset, value } if system == System::Rf5c164 => {
+                        let address = (chip.bank as u32) << 12 | (offset & 0x0FFF) as u32;
+                        chip.write_ram(address, &[value]);
+                        continue;
+                    }
+                    Command::RF5C68Write { register, value } if system == System::Rf5c68 => {
+                        (register, value)
+                    }
+                    Command::RF5C164Write { register, value } if system == System::Rf5c164 => {
+                        (register, value)
+                    }
+                    _ => continue,
+                };
+
+                for channel in chip.write(register, value) {
+                    // output at clock / 384, the step has 11 bits of fraction
+                    let rate = clock * chip.step[channel as usize] as u64 / 384 / 2048;
+                    let (start, data) = chip.sample(channel);
+                    set.add(source, start, WavFile::from_u8(rate as u32, data));
+                }
+            }
+        }
+    }
+
+    fn extract_sega_pcm_samples(&self, set: &mut SampleSet) {
+        let Some(rom) = self.sega_pcm_rom() else {
+            return;
+        };
+        let clock = self.header.clock(&System::SegaPcm) as u64;
+        let mut sega_pcm = SegaPcm::new(self.header.sega_pcm_interface(), rom.size);
+        for cmd in &self.commands {
+            if let Command::SegaPCMWrite { offset, value } = *cmd {
+                if let Some(sample) = sega_pcm.write(offset, value) {
+                    // output at clock / 128, the step has 8 bits of fraction
+                    let step = sega_pcm.registers[sample.channel as usize * 8 + 7] as u64;
+                    let rate = clock * step / 128 / 256;
+                    let data = rom.slice(sample.start, sample.end).to_vec();
+                    set.add(
+                        SampleSource::SegaPcm,
+                        sample.start,
+                        WavFile::from_u8(rate as u32, data),
+                    );
+                }
+            }
+        }
+    }
+
+    /// ROM address of a C140 voice, following the wiring of the board
+    fn c140_address(&self, bank: u8, address: u16) -> u32 {
+        let address = (bank as u32) << 16 | address as u32;
+        match self.header.c140_chip_type {
+            // System 2
+            0 => (address & 0x200000) >> 2 | address & 0x7FFFF,
+            // System 21
+            1 => ((address & 0x300000) >> 1) + (address & 0x7FFFF),
+            _ => address,
+        }
+    }
+
+    fn extract_c140_samples(&self, set: &mut SampleSet) {
+        let Some(rom) = RomImage::from_commands(&self.commands, DATA_TYPE_C140_ROM) else {
+            return;
+        };
+        let clock = self.header.clock(&System::C140) as u64;
+        let mut registers = [0u8; 0x200];
+        for cmd in &self.commands {
+            let Command::C140Write { register, value } = *cmd else {
+                continue;
+            };
+            let register = register as usize & 0x1FF;
+            let key_on = register < 0x180
+                && register & 0x0F == 0x05
+                && registers[register] & 0x80 == 0
+                && value & 0x80 != 0;
+            registers[register] = value;
+            if !key_on {
+                continue;
+            }
+
+            let voice = &registers[register - 5..register + 7];
+            let word = |index: usize| (voice[index] as u16) << 8 | voice[index + 1] as u16;
+            let step = word(2) as u64;
+            let compressed = voice[5] & 0x08 != 0;
+            let start = self.c140_address(voice[4], word(6));
+            let end = self.c140_address(voice[4], word(8));
+
+            // output at the clock, the step has 16 bits of fraction
+            let rate = clock * step / 65536;
+            let data: Vec<i16> = rom
+                .slice(start, end)
+                .iter()
+                .map(|&value| c140_to_i16(value, compressed))
+                .collect();
+            set.add(
+                SampleSource::C140,
+                start,
+                WavFile::from_i16(rate as u32, &data),
+            );
+        }
+    }
+
+    fn extract_pwm_samples(&self, set: &mut SampleSet) {
+        let clock = self.header.clock(&System::Pwm);
+        let mut cycle = 0;
+        // rate, sample of the first write, sample of the last write and values of the current run
+        let mut run: Option<(u32, u64, u64, Vec<i16>)> = None;
+        let mut runs = vec![];
+        for (sample, _, cmd) in self.timeline() {
+            let Command::PWMWrite { register, value } = *cmd else {
+                continue;
+            };
+            match register {
+                0x01 => cycle = value & 0x0FFF,
+                // left channel or both
+                0x02 | 0x04 if cycle > 1 => {
+                    let center = cycle as i32 / 2;
+                    let level = ((value & 0x0FFF) as i32 - center) * i16::MAX as i32 / center;
+                    let level = level.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+                    // the chip outputs a sample every cycle - 1 clocks
+                    let rate = clock / (cycle as u32 - 1);
+                    match run.as_mut() {
+                        Some((run_rate, _, last, values))
+                            if *run_rate == rate && sample - *last <= DEFAULT_SEGMENT_MAX_GAP =>
+                        {
+                            values.push(level);
+                            *last = sample;
+                        }
+                        _ => {
+                            runs.extend(run.take());
+                            run = Some((rate, sample, sample, vec![level]));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        runs.extend(run);
+
+        for (rate, start, _, values) in runs {
+            set.add(
+                SampleSource::Pwm,
+                start as u32,
+                WavFile::from_i16(rate, &values),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    use super::{SampleSource, DATA_TYPE_RF5C164_PCM};
+
+    #[test]
+    fn rf5c164_and_stream_samples() {
+        let vgm = VgmFile {
+            header: HeaderData {
+                version: 171,
+                rf5c164_clock: 12500000,
+                ..Default::default()
+            },
+            commands: vec![
+                Command::DataBlock {
+                    data_type: 0x00,
+                    data_size: 4,
+                    data: vec![0x80, 0x90, 0xA0, 0xB0],
+                },
+                Command::DataBlock {
+                    data_type: DATA_TYPE_RF5C164_PCM,
+                    data_size: 4,
+                    data: vec![0x81, 0x01, 0x00, 0xFF],
+                },
+                Command::PCMRAMWrite {
+                    chip_type: DATA_TYPE_RF5C164_PCM,
+                    read_offset: 0,
+                    write_offset: 0x0200,
+                    size: 4,
+                },
+                // channel 1 from 0x0200 with a step of 1.0
+                Command::RF5C164Write {
+                    register: 0x07,
+                    value: 0xC1,
+                },
+                Command::RF5C164Write {
+                    register: 0x06,
+                    value: 0x02,
+                },
+                Command::RF5C164Write {
+                    register: 0x03,
+                    value: 0x08,
+                },
+                Command::RF5C164Write {
+                    register: 0x08,
+                    value: 0xFD,
+                },
+                Command::DACStreamSetupControl {
+                    stream_id: 0,
+                    chip_type: 0x02,
+                    port: 0x00,
+                    command: 0x2A,
+                },
+                Command::DACStreamSetData {
+                    stream_id: 0,
+                    data_bank_id: 0x00,
+                    step_size: 1,
+                    step_base: 0,
+                },
+                Command::DACStreamSetFrequency {
+                    stream_id: 0,
+                    frequency: 8000,
+                },
+                Command::DACStreamStart {
+                    stream_id: 0,
+                    data_start_offset: 1,
+                    length_mode: 0x01,
+                    data_length: 2,
+                },
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let samples = vgm.extract_samples();
+        assert_eq!(samples.len(), 2);
+
+        assert_eq!(samples[0].source, SampleSource::Ym2612);
+        assert_eq!(samples[0].start, 1);
+        assert_eq!(samples[0].wav.sample_rate, 8000);
+        assert_eq!(samples[0].wav.data, [0x90, 0xA0]);
+
+        assert_eq!(samples[1].source, SampleSource::Rf5c164);
+        assert_eq!(samples[1].start, 0x0200);
+        assert_eq!(samples[1].wav.sample_rate, 12500000 / 384);
+        assert_eq!(samples[1].wav.data, [0x81, 0x7F, 0x80]);
+
+        let mut buffer = bytes::BytesMut::new();
+        samples[1].wav.to_bytes(&mut buffer);
+        assert_eq!(&buffer[..4], b"RIFF");
+        assert_eq!(buffer.len(), 44 + 4);
+    }
+}
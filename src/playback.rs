@@ -0,0 +1,189 @@
+use crate::command::Command;
+use crate::header::HeaderData;
+use crate::vgmfile::VgmFile;
+
+/// Every sample count in a VGM file is at 44100 Hz, whatever the rate field says
+pub const VGM_SAMPLE_RATE: u32 = 44100;
+
+/// Frame rate assumed when the rate field is 0
+pub const DEFAULT_FRAME_RATE: u32 = 60;
+
+pub fn samples_to_seconds(samples: u64) -> f64 {
+    samples as f64 / VGM_SAMPLE_RATE as f64
+}
+
+/// Index of the frame containing a sample, for a frame rate in Hz
+pub fn samples_to_frames(samples: u64, frame_rate: u32) -> u64 {
+    samples * frame_rate as u64 / VGM_SAMPLE_RATE as u64
+}
+
+impl HeaderData {
+    /// Volume modifier as a signed value, 0 before 1.60 where the field did not exist.
+    /// Values above 0xC0 wrap to negative numbers, except 0xC1 which stands for -64
+    /// instead of -63 (same as VGMPlay).
+    pub fn volume_modifier_value(&self) -> i32 {
+        if self.version < 160 {
+            return 0;
+        }
+
+        match self.volume_modifier {
+            0x00..=0xC0 => self.volume_modifier as i32,
+            0xC1 => 0xC0 - 0x100,
+            _ => self.volume_modifier as i32 - 0x100,
+        }
+    }
+
+    /// Linear gain to apply to the output, `2 ^ (volume_modifier / 0x20)`
+    pub fn master_gain(&self) -> f64 {
+        2f64.powf(self.volume_modifier_value() as f64 / 0x20 as f64)
+    }
+
+    /// Number of times the loop is played for a requested number of loops,
+    /// `loops * loop_modifier / 0x10 - loop_base`, played at least once.
+    pub fn effective_loop_count(&self, requested_loops: u32) -> u32 {
+        let loop_modifier = match self.loop_modifier {
+            0 => 0x10,
+            _ if self.version < 151 => 0x10,
+            loop_modifier => loop_modifier as i64,
+        };
+        let loop_base = if self.version < 160 {
+            0
+        } else {
+            self.loop_base as i8 as i64
+        };
+
+        let loops = (requested_loops as i64 * loop_modifier + 0x08) / 0x10 - loop_base;
+        loops.max(1) as u32
+    }
+
+    /// Length of the playback in samples, with the loop played `effective_loop_count` times
+    pub fn playback_samples(&self, requested_loops: u32) -> u64 {
+        if self.loop_offset == 0 || self.loop_nb_samples == 0 {
+            return self.total_nb_samples as u64;
+        }
+
+        let loops = self.effective_loop_count(requested_loops) as u64;
+        self.total_nb_samples as u64 + self.loop_nb_samples as u64 * (loops - 1)
+    }
+
+    pub fn playback_seconds(&self, requested_loops: u32) -> f64 {
+        samples_to_seconds(self.playback_samples(requested_loops))
+    }
+
+    /// Refresh rate of the recorded system, 50 or 60 Hz in practice, `DEFAULT_FRAME_RATE` if unknown
+    pub fn frame_rate(&self) -> u32 {
+        match self.effective_rate() {
+            0 => DEFAULT_FRAME_RATE,
+            rate => rate,
+        }
+    }
+
+    /// Index of the frame containing a sample, at the rate of the recorded system
+    pub fn samples_to_frames(&self, samples: u64) -> u64 {
+        samples_to_frames(samples, self.frame_rate())
+    }
+}
+
+impl VgmFile {
+    /// Commands in playback order: the whole file once, then the loop played again until it ran
+    /// `effective_loop_count(requested_loops)` times, the same length as `playback_samples`.
+    /// A loop index past the commands plays the file once.
+    pub fn looped_commands(&self, requested_loops: u32) -> impl Iterator<Item = &Command> + '_ {
+        let (loop_body, repeats) = match self.loop_index {
+            Some(index) if index < self.commands.len() => (
+                &self.commands[index..],
+                self.header.effective_loop_count(requested_loops) as usize - 1,
+            ),
+            _ => (&self.commands[..0], 0),
+        };
+
+        self.commands
+            .iter()
+            .chain(std::iter::repeat_n(loop_body, repeats).flatten())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    #[test]
+    fn gain_and_loops() {
+        let mut header = HeaderData {
+            version: 171,
+            total_nb_samples: 44100 * 10,
+            loop_offset: 0x40,
+            loop_nb_samples: 44100 * 8,
+            ..Default::default()
+        };
+        assert_eq!(header.master_gain(), 1.0);
+        assert_eq!(header.effective_loop_count(2), 2);
+        assert_eq!(header.playback_seconds(2), 18.0);
+
+        header.volume_modifier = 0x20;
+        assert_eq!(header.master_gain(), 2.0);
+        header.volume_modifier = 0xE0;
+        assert_eq!(header.master_gain(), 0.5);
+        header.volume_modifier = 0xC1;
+        assert_eq!(header.volume_modifier_value(), -64);
+
+        // half the loops, minus one
+        header.loop_modifier = 0x08;
+        header.loop_base = 1;
+        assert_eq!(header.effective_loop_count(4), 1);
+        assert_eq!(header.effective_loop_count(8), 3);
+        assert_eq!(header.playback_samples(8), 44100 * (10 + 2 * 8));
+
+        // loop base is signed
+        header.loop_base = 0xFF;
+        assert_eq!(header.effective_loop_count(2), 2);
+
+        assert_eq!(header.frame_rate(), 60);
+        header.rate = 50;
+        assert_eq!(header.samples_to_frames(44100 + 881), 50);
+        assert_eq!(header.samples_to_frames(44100 + 882), 51);
+    }
+
+    #[test]
+    fn looped_playback() {
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 171,
+                loop_modifier: 0x20,
+                ..Default::default()
+            },
+            commands: vec![
+                Command::PSGWrite { value: 0x9F },
+                Command::Wait735Samples,
+                Command::PSGWrite { value: 0x90 },
+                Command::Wait882Samples,
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+        assert_eq!(vgm.looped_commands(2).count(), 4);
+
+        // the loop is played 4 times
+        vgm.loop_index = Some(2);
+        let samples: u32 = vgm.looped_commands(2).map(|cmd| cmd.wait_samples()).sum();
+        assert_eq!(samples, 735 + 882 * 4);
+
+        // past the commands
+        vgm.loop_index = Some(5);
+        assert_eq!(vgm.looped_commands(2).count(), 4);
+        vgm.loop_index = Some(2);
+
+        // the loop follows the commands
+        vgm.insert_command(0, Command::Wait735Samples);
+        vgm.remove_command(1);
+        assert_eq!(vgm.loop_index, Some(2));
+        vgm.insert_command(2, Command::Wait735Samples);
+        assert_eq!(vgm.loop_index, Some(3));
+        vgm.remove_command(4);
+        vgm.remove_command(3);
+        assert_eq!(vgm.loop_index, None);
+    }
+}
@@ -0,0 +1,569 @@
+//! Meaning of register writes for the most common chips, used to annotate disassembly.
+//! Channel and operator numbers start at 1 for the FM chips as in the Yamaha manuals,
+//! PSG channels are numbered from 0 as in the SN76489 documentation.
+
+use crate::chip_state::ChipState;
+use crate::command::Command;
+
+/// YM2612/OPN operator registers, indexed by `(register >> 4) - 3`
+const OPN_OPERATOR_REGISTERS: [&str; 7] = [
+    "detune/multiple",
+    "total level",
+    "rate scaling/attack rate",
+    "AM/decay rate",
+    "sustain rate",
+    "sustain level/release rate",
+    "SSG-EG",
+];
+
+/// YM2151 operator registers, indexed by `(register >> 5) - 2`
+const OPM_OPERATOR_REGISTERS: [&str; 6] = [
+    "detune 1/multiple",
+    "total level",
+    "key scaling/attack rate",
+    "AM enable/decay rate 1",
+    "detune 2/decay rate 2",
+    "decay level 1/release rate",
+];
+
+/// AY8910 registers, also the SSG of the YM2203, YM2608 and YM2610
+const SSG_REGISTERS: [&str; 16] = [
+    "tone A fine",
+    "tone A coarse",
+    "tone B fine",
+    "tone B coarse",
+    "tone C fine",
+    "tone C coarse",
+    "noise period",
+    "mixer",
+    "volume A",
+    "volume B",
+    "volume C",
+    "envelope period fine",
+    "envelope period coarse",
+    "envelope shape",
+    "I/O port A",
+    "I/O port B",
+];
+
+/// YM2413 user instrument registers 0x00 - 0x07
+const OPLL_INSTRUMENT_REGISTERS: [&str; 8] = [
+    "user patch modulator AM/VIB/EG/KSR/multiple",
+    "user patch carrier AM/VIB/EG/KSR/multiple",
+    "user patch modulator key scale/total level",
+    "user patch carrier key scale/waveforms/feedback",
+    "user patch modulator attack/decay rate",
+    "user patch carrier attack/decay rate",
+    "user patch modulator sustain level/release rate",
+    "user patch carrier sustain level/release rate",
+];
+
+/// Game Boy registers, from NR10 at 0xFF10
+const DMG_REGISTERS: [&str; 0x17] = [
+    "square 1 sweep",
+    "square 1 duty/length",
+    "square 1 envelope",
+    "square 1 freq lo",
+    "square 1 trigger/freq hi",
+    "unused",
+    "square 2 duty/length",
+    "square 2 envelope",
+    "square 2 freq lo",
+    "square 2 trigger/freq hi",
+    "wave DAC enable",
+    "wave length",
+    "wave volume",
+    "wave freq lo",
+    "wave trigger/freq hi",
+    "unused",
+    "noise length",
+    "noise envelope",
+    "noise clock/width",
+    "noise trigger",
+    "master volume/VIN",
+    "panning",
+    "sound on",
+];
+
+/// NES APU registers, from 0x4000
+const APU_REGISTERS: [&str; 0x18] = [
+    "pulse 1 duty/envelope",
+    "pulse 1 sweep",
+    "pulse 1 timer lo",
+    "pulse 1 length/timer hi",
+    "pulse 2 duty/envelope",
+    "pulse 2 sweep",
+    "pulse 2 timer lo",
+    "pulse 2 length/timer hi",
+    "triangle linear counter",
+    "unused",
+    "triangle timer lo",
+    "triangle length/timer hi",
+    "noise envelope",
+    "unused",
+    "noise mode/period",
+    "noise length",
+    "DMC flags/rate",
+    "DMC direct load",
+    "DMC sample address",
+    "DMC sample length",
+    "unused",
+    "channel enable",
+    "unused",
+    "frame counter",
+];
+
+/// FM chips of the OPN family, they differ by the number of channels and the extra units
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Opn {
+    Ym2203,
+    Ym2608,
+    Ym2610,
+    Ym2612,
+}
+
+/// FM chips of the OPL family
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Opl {
+    Ym3526,
+    Ym3812,
+    Y8950,
+    Ymf262,
+}
+
+fn operator_list(bits: u8, names: [&str; 4]) -> String {
+    let ops: Vec<&str> = (0..4)
+        .filter(|i| bits & (1 << i) != 0)
+        .map(|i| names[i])
+        .collect();
+    ops.join("")
+}
+
+pub fn describe_sn76489(value: u8, state: &ChipState) -> String {
+    let latch = if value & 0x80 != 0 {
+        value
+    } else {
+        state.sn76489_latch()
+    };
+    let channel = (latch >> 5) & 0x03;
+    let is_volume = latch & 0x10 != 0;
+
+    match (channel, is_volume, value & 0x80 != 0) {
+        (3, true, _) => format!("noise volume 0x{:02X}", value & 0x0F),
+        (_, true, _) => format!("tone {channel} volume 0x{:02X}", value & 0x0F),
+        (3, false, _) => {
+            let mode = if value & 0x04 != 0 {
+                "white"
+            } else {
+                "periodic"
+            };
+            let rate = match value & 0x03 {
+                3 => "tone 2".to_string(),
+                shift => format!("clock/{}", 0x200 << shift),
+            };
+            format!("noise mode {mode}, rate {rate}")
+        }
+        (_, false, true) => format!("tone {channel} freq lo 0x{:X}", value & 0x0F),
+        (_, false, false) => format!("tone {channel} freq hi 0x{:02X}", value & 0x3F),
+    }
+}
+
+pub fn describe_ssg(register: u8, value: u8) -> String {
+    let Some(name) = SSG_REGISTERS.get(register as usize) else {
+        return format!("unknown register 0x{register:02X}");
+    };
+
+    match register {
+        0x07 => {
+            let enabled = |shift: u8| -> String {
+                let channels: Vec<&str> = ["A", "B", "C"]
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| value & (1 << (i + shift as usize)) == 0)
+                    .map(|(_, name)| name)
+                    .collect();
+                if channels.is_empty() {
+                    "off".to_string()
+                } else {
+                    channels.join(",")
+                }
+            };
+            format!("mixer: tone {} noise {}", enabled(0), enabled(3))
+        }
+        0x08..=0x0A if value & 0x10 != 0 => format!("{name} envelope"),
+        0x08..=0x0A => format!("{name} 0x{:X}", value & 0x0F),
+        _ => name.to_string(),
+    }
+}
+
+fn describe_opn_fm(chip: Opn, port: u8, register: u8, value: u8) -> String {
+    let channel_offset = port * 3;
+    let channel = register & 0x03;
+    let channel_name = |channel: u8| format!("ch{}", channel + channel_offset + 1);
+
+    match register {
+        0x22 if port == 0 => "LFO".to_string(),
+        0x24 if port == 0 => "timer A hi".to_string(),
+        0x25 if port == 0 => "timer A lo".to_string(),
+        0x26 if port == 0 => "timer B".to_string(),
+        0x27 if port == 0 => "ch3 mode/timer control".to_string(),
+        0x28 if port == 0 => {
+            // channels 4-6 are selected with bit 2, not on the YM2203
+            let channel = match value & 0x07 {
+                channel @ 0..=2 => channel + 1,
+                channel @ 4..=6 if chip != Opn::Ym2203 => channel,
+                _ => return "key on, invalid channel".to_string(),
+            };
+            let ops = operator_list(value >> 4, ["1", "2", "3", "4"]);
+            if ops.is_empty() {
+                format!("key off ch{channel}")
+            } else {
+                format!("key on ch{channel} ops {ops}")
+            }
+        }
+        0x29 if port == 0 && chip == Opn::Ym2608 => "IRQ enable/6 channel mode".to_string(),
+        0x2A if port == 0 && chip == Opn::Ym2612 => "DAC data".to_string(),
+        0x2B if port == 0 && chip == Opn::Ym2612 => "DAC enable".to_string(),
+        0x2D..=0x2F if port == 0 && chip != Opn::Ym2612 => "prescaler".to_string(),
+        0x30..=0x9F if channel != 3 => {
+            // operators are laid out as 1, 3, 2, 4
+            let op = [1, 3, 2, 4][((register >> 2) & 0x03) as usize];
+            let name = OPN_OPERATOR_REGISTERS[((register >> 4) - 3) as usize];
+            format!("{} op{op} {name}", channel_name(channel))
+        }
+        0xA0..=0xA2 => format!("{} fnum lo", channel_name(channel)),
+        0xA4..=0xA6 => format!("{} block/fnum hi", channel_name(channel)),
+        0xA8..=0xAA => format!("ch3 op{} fnum lo", [3, 1, 2][channel as usize]),
+        0xAC..=0xAE => format!("ch3 op{} block/fnum hi", [3, 1, 2][channel as usize]),
+        0xB0..=0xB2 => format!("{} feedback/algorithm", channel_name(channel)),
+        0xB4..=0xB6 => format!("{} stereo/LFO sensitivity", channel_name(channel)),
+        _ => format!("unknown register 0x{register:02X}"),
+    }
+}
+
+fn describe_ym2608_rhythm(register: u8) -> String {
+    match register {
+        0x10 => "rhythm key on/dump".to_string(),
+        0x11 => "rhythm total level".to_string(),
+        0x18..=0x1D => {
+            let instrument = [
+                "bass drum",
+                "snare",
+                "top cymbal",
+                "hi-hat",
+                "tom",
+                "rim shot",
+            ][(register - 0x18) as usize];
+            format!("rhythm {instrument} pan/level")
+        }
+        _ => format!("unknown register 0x{register:02X}"),
+    }
+}
+
+fn describe_adpcm_b(register: u8) -> String {
+    match register {
+        0x00 => "ADPCM-B control 1",
+        0x01 => "ADPCM-B control 2",
+        0x02 => "ADPCM-B start address lo",
+        0x03 => "ADPCM-B start address hi",
+        0x04 => "ADPCM-B stop address lo",
+        0x05 => "ADPCM-B stop address hi",
+        0x06 => "ADPCM-B prescale lo",
+        0x07 => "ADPCM-B prescale hi",
+        0x08 => "ADPCM-B data",
+        0x09 => "ADPCM-B delta-N lo",
+        0x0A => "ADPCM-B delta-N hi",
+        0x0B => "ADPCM-B level",
+        0x0C => "ADPCM-B limit address lo",
+        0x0D => "ADPCM-B limit address hi",
+        0x0E => "DAC data",
+        0x0F => "PCM data",
+        0x10 => "flag control",
+        _ => return format!("unknown register 0x{register:02X}"),
+    }
+    .to_string()
+}
+
+fn describe_adpcm_a(register: u8) -> String {
+    let channel = (register & 0x07) + 1;
+    match register {
+        0x00 => "ADPCM-A key on/dump".to_string(),
+        0x01 => "ADPCM-A total level".to_string(),
+        0x08..=0x0D => format!("ADPCM-A ch{channel} pan/level"),
+        0x10..=0x15 => format!("ADPCM-A ch{channel} start address lo"),
+        0x18..=0x1D => format!("ADPCM-A ch{channel} start address hi"),
+        0x20..=0x25 => format!("ADPCM-A ch{channel} end address lo"),
+        0x28..=0x2D => format!("ADPCM-A ch{channel} end address hi"),
+        _ => format!("unknown register 0x{register:02X}"),
+    }
+}
+
+fn describe_opn(chip: Opn, port: u8, register: u8, value: u8) -> String {
+    match (chip, port, register) {
+        (Opn::Ym2612, _, _) => describe_opn_fm(chip, port, register, value),
+        (_, 0, 0x00..=0x0F) => format!("SSG {}", describe_ssg(register, value)),
+        (Opn::Ym2608, 0, 0x10..=0x1F) => describe_ym2608_rhythm(register),
+        (Opn::Ym2608, 1, 0x00..=0x10) => describe_adpcm_b(register),
+        (Opn::Ym2610, 0, 0x10..=0x1C) => describe_adpcm_b(register - 0x10),
+        (Opn::Ym2610, 1, 0x00..=0x2F) => describe_adpcm_a(register),
+        _ => describe_opn_fm(chip, port, register, value),
+    }
+}
+
+pub fn describe_ym2151(register: u8, value: u8) -> String {
+    let channel = (register & 0x07) + 1;
+    match register {
+        0x01 => "test/LFO reset".to_string(),
+        0x08 => {
+            let ops = operator_list(value >> 3, ["M1", "C1", "M2", "C2"]);
+            let channel = (value & 0x07) + 1;
+            if ops.is_empty() {
+                format!("key off ch{channel}")
+            } else {
+                format!("key on ch{channel} ops {ops}")
+            }
+        }
+        0x0F => "noise enable/frequency".to_string(),
+        0x10 => "timer A hi".to_string(),
+        0x11 => "timer A lo".to_string(),
+        0x12 => "timer B".to_string(),
+        0x14 => "timer control".to_string(),
+        0x18 => "LFO frequency".to_string(),
+        0x19 => "PM/AM depth".to_string(),
+        0x1B => "CT/LFO waveform".to_string(),
+        0x20..=0x27 => format!("ch{channel} pan/feedback/connection"),
+        0x28..=0x2F => format!("ch{channel} key code"),
+        0x30..=0x37 => format!("ch{channel} key fraction"),
+        0x38..=0x3F => format!("ch{channel} PM/AM sensitivity"),
+        0x40..=0xFF => {
+            // operators are laid out as M1, M2, C1, C2
+            let op = ["M1", "M2", "C1", "C2"][((register >> 3) & 0x03) as usize];
+            let name = OPM_OPERATOR_REGISTERS[((register >> 5) - 2) as usize];
+            format!("ch{channel} {op} {name}")
+        }
+        _ => format!("unknown register 0x{register:02X}"),
+    }
+}
+
+pub fn describe_ym2413(register: u8, value: u8) -> String {
+    let channel = (register & 0x0F) + 1;
+    match register {
+        0x00..=0x07 => OPLL_INSTRUMENT_REGISTERS[register as usize].to_string(),
+        0x0E => {
+            if value & 0x20 == 0 {
+                return "rhythm off".to_string();
+            }
+            let drums: Vec<&str> = ["HH", "CYM", "TOM", "SD", "BD"]
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| value & (1 << i) != 0)
+                .map(|(_, name)| name)
+                .collect();
+            format!("rhythm on, keys {}", drums.join(","))
+        }
+        0x0F => "test".to_string(),
+        0x10..=0x18 => format!("ch{channel} fnum lo"),
+        0x20..=0x28 => {
+            let key = if value & 0x10 != 0 {
+                "key on"
+            } else {
+                "key off"
+            };
+            format!("ch{channel} {key}/sustain/block/fnum hi")
+        }
+        0x30..=0x38 => format!(
+            "ch{channel} instrument {}/volume 0x{:X}",
+            value >> 4,
+            value & 0x0F
+        ),
+        _ => format!("unknown register 0x{register:02X}"),
+    }
+}
+
+fn describe_opl(chip: Opl, port: u8, register: u8, value: u8) -> String {
+    let channel_offset = port * 9;
+    let channel = register & 0x0F;
+
+    match register {
+        0x01 if port == 0 => "test/waveform select enable".to_string(),
+        0x02 if port == 0 => "timer 1".to_string(),
+        0x03 if port == 0 => "timer 2".to_string(),
+        0x04 if port == 0 => "timer control".to_string(),
+        0x04 if chip == Opl::Ymf262 => "4-op connection".to_string(),
+        0x05 if chip == Opl::Ymf262 => "OPL3 enable".to_string(),
+        0x07..=0x12 if chip == Opl::Y8950 && port == 0 => "ADPCM/keyboard".to_string(),
+        0x08 if port == 0 => "CSM/note select".to_string(),
+        0x20..=0x95 | 0xE0..=0xF5 => {
+            let slot = register & 0x1F;
+            if slot & 0x07 > 5 || slot > 0x15 {
+                return format!("unknown register 0x{register:02X}");
+            }
+            let channel = (slot >> 3) * 3 + (slot & 0x07) % 3 + channel_offset + 1;
+            let op = (slot & 0x07) / 3 + 1;
+            let name = match register & 0xE0 {
+                0x20 => "AM/VIB/EG/KSR/multiple",
+                0x40 => "key scale/total level",
+                0x60 => "attack/decay rate",
+                0x80 => "sustain level/release rate",
+                _ => "waveform",
+            };
+            format!("ch{channel} op{op} {name}")
+        }
+        0xA0..=0xA8 => format!("ch{} fnum lo", channel + channel_offset + 1),
+        0xB0..=0xB8 => {
+            let key = if value & 0x20 != 0 {
+                "key on"
+            } else {
+                "key off"
+            };
+            format!("ch{} {key}/block/fnum hi", channel + channel_offset + 1)
+        }
+        0xBD if port == 0 => "AM/vibrato depth/rhythm".to_string(),
+        0xC0..=0xC8 => format!("ch{} feedback/connection", channel + channel_offset + 1),
+        _ => format!("unknown register 0x{register:02X}"),
+    }
+}
+
+pub fn describe_gameboy_dmg(register: u8) -> String {
+    match register {
+        0x00..=0x16 => DMG_REGISTERS[register as usize].to_string(),
+        0x20..=0x2F => format!("wave RAM 0x{:X}", register - 0x20),
+        _ => format!("unknown register 0x{register:02X}"),
+    }
+}
+
+pub fn describe_nes_apu(register: u8) -> String {
+    match register {
+        0x00..=0x17 => APU_REGISTERS[register as usize].to_string(),
+        // 0x20 - 0x3E map to 0x4080, 0x3F to 0x4023 and 0x40 - 0x7F to 0x4040
+        0x20..=0x3E => format!("FDS register 0x{:04X}", 0x4060 + register as u16),
+        0x3F => "FDS I/O enable".to_string(),
+        0x40..=0x7F => format!("FDS wave RAM 0x{:X}", register - 0x40),
+        _ => format!("unknown register 0x{register:02X}"),
+    }
+}
+
+impl Command {
+    /// Meaning of the write for the chips that have a register table, `None` otherwise.
+    /// The state must have seen every previous command, see `ChipState::update`.
+    pub fn describe(&self, chip_state: &ChipState) -> Option<String> {
+        Some(match *self {
+            Command::PSGWrite { value } => describe_sn76489(value, chip_state),
+            Command::YM2413Write { register, value } => describe_ym2413(register, value),
+            Command::YM2612Port0Write { register, value } => {
+                describe_opn(Opn::Ym2612, 0, register, value)
+            }
+            Command::YM2612Port1Write { register, value } => {
+                describe_opn(Opn::Ym2612, 1, register, value)
+            }
+            Command::YM2151Write { register, value } => describe_ym2151(register, value),
+            Command::YM2203Write { register, value } => {
+                describe_opn(Opn::Ym2203, 0, register, value)
+            }
+            Command::YM2608Port0Write { register, value } => {
+                describe_opn(Opn::Ym2608, 0, register, value)
+            }
+            Command::YM2608Port1Write { register, value } => {
+                describe_opn(Opn::Ym2608, 1, register, value)
+            }
+            Command::YM2610Port0Write { register, value } => {
+                describe_opn(Opn::Ym2610, 0, register, value)
+            }
+            Command::YM2610Port1Write { register, value } => {
+                describe_opn(Opn::Ym2610, 1, register, value)
+            }
+            Command::YM3526Write { register, value } => {
+                describe_opl(Opl::Ym3526, 0, register, value)
+            }
+            Command::YM3812Write { register, value } => {
+                describe_opl(Opl::Ym3812, 0, register, value)
+            }
+            Command::Y8950Write { register, value } => describe_opl(Opl::Y8950, 0, register, value),
+            Command::YMF262Port0Write { register, value } => {
+                describe_opl(Opl::Ymf262, 0, register, value)
+            }
+            Command::YMF262Port1Write { register, value } => {
+                describe_opl(Opl::Ymf262, 1, register, value)
+            }
+            Command::AY8910Write { register, value } => describe_ssg(register, value),
+            Command::GameBoyDMGWrite { register, .. } => describe_gameboy_dmg(register),
+            Command::NESAPUWrite { register, .. } => describe_nes_apu(register),
+            Command::YM2612Port0Address2AWriteWait { .. } => "DAC data from PCM bank".to_string(),
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip_state::ChipState;
+    use crate::command::Command;
+
+    fn describe(commands: &[Command]) -> Vec<String> {
+        let mut state = ChipState::new();
+        commands
+            .iter()
+            .map(|cmd| {
+                let description = cmd.describe(&state).unwrap();
+                state.update(cmd);
+                description
+            })
+            .collect()
+    }
+
+    #[test]
+    fn descriptions() {
+        assert_eq!(
+            describe(&[
+                Command::YM2612Port0Write {
+                    register: 0xA4,
+                    value: 0x22
+                },
+                Command::YM2612Port0Write {
+                    register: 0x28,
+                    value: 0xF2
+                },
+                Command::YM2612Port0Write {
+                    register: 0x28,
+                    value: 0x05
+                },
+                Command::YM2612Port1Write {
+                    register: 0x44,
+                    value: 0x7F
+                },
+                Command::PSGWrite { value: 0xDF },
+                Command::PSGWrite { value: 0xC5 },
+                Command::PSGWrite { value: 0x12 },
+                Command::AY8910Write {
+                    register: 0x07,
+                    value: 0x1C
+                },
+                Command::YM2151Write {
+                    register: 0x08,
+                    value: 0x79
+                },
+                Command::YMF262Port1Write {
+                    register: 0x2B,
+                    value: 0x01
+                },
+                Command::NESAPUWrite {
+                    register: 0x15,
+                    value: 0x0F
+                },
+            ]),
+            [
+                "ch1 block/fnum hi",
+                "key on ch3 ops 1234",
+                "key off ch5",
+                "ch4 op3 total level",
+                "tone 2 volume 0x0F",
+                "tone 2 freq lo 0x5",
+                "tone 2 freq hi 0x12",
+                "mixer: tone A,B noise C",
+                "key on ch2 ops M1C1M2C2",
+                "ch13 op2 AM/VIB/EG/KSR/multiple",
+                "channel enable",
+            ]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use crate::chip_state::ChipState;
+use crate::command::Command;
+use crate::playback::VGM_SAMPLE_RATE;
+use crate::timeline::wait_commands;
+use crate::vgmfile::VgmFile;
+
+/// Interval between two keyframes used when none is given, 5 seconds
+pub const DEFAULT_KEYFRAME_INTERVAL: u64 = VGM_SAMPLE_RATE as u64 * 5;
+
+/// State of the chips before `commands[command_index]` runs, at `sample`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyframe {
+    pub sample: u64,
+    pub command_index: usize,
+    pub state: ChipState,
+}
+
+/// Keyframes of a file sorted by sample, the first one is the start of the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeekIndex {
+    pub keyframes: Vec<Keyframe>,
+}
+
+/// Commands to play a file from a sample position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeekResult {
+    /// Data blocks, PCM RAM and stream setup from before the position, then the register writes
+    /// recreating the state of every chip
+    pub prelude: Vec<Command>,
+    /// What is left of a wait running over the position, then every command from `command_index`
+    pub commands: Vec<Command>,
+    /// Index in the file of the first command that was not played yet
+    pub command_index: usize,
+}
+
+/// Commands that load data or set things up rather than write registers, they are all
+/// replayed since later commands may depend on them. DAC streams already playing are not resumed.
+pub fn is_setup_command(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::DataBlock { .. }
+            | Command::PCMRAMWrite { .. }
+            | Command::RF5C68WriteOffset { .. }
+            | Command::RF5C164WriteOffset { .. }
+            | Command::MultiPCMSetBank { .. }
+            | Command::DACStreamSetupControl { .. }
+            | Command::DACStreamSetData { .. }
+            | Command::DACStreamSetFrequency { .. }
+    )
+}
+
+impl VgmFile {
+    /// Snapshot the chips at the first command of every `interval` samples, an interval of 0
+    /// is taken as 1
+    pub fn seek_index(&self, interval: u64) -> SeekIndex {
+        let interval = interval.max(1);
+        let mut keyframes = vec![Keyframe {
+            sample: 0,
+            command_index: 0,
+            state: ChipState::new(),
+        }];
+
+        let mut state = ChipState::new();
+        let mut next_keyframe = interval;
+        for (command_index, (sample, _, cmd)) in self.timeline().enumerate() {
+            if sample >= next_keyframe {
+                keyframes.push(Keyframe {
+                    sample,
+                    command_index,
+                    state: state.clone(),
+                });
+                next_keyframe = (sample / interval + 1) * interval;
+            }
+            state.update(cmd);
+        }
+
+        SeekIndex { keyframes }
+    }
+
+    /// Commands to start playing at a sample position, commands running at exactly that
+    /// position are not part of the prelude
+    pub fn seek(&self, index: &SeekIndex, sample: u64) -> SeekResult {
+        let keyframe_index = index
+            .keyframes
+            .partition_point(|keyframe| keyframe.sample <= sample)
+            .max(1)
+            - 1;
+        let keyframe = &index.keyframes[keyframe_index];
+
+        let mut state = keyframe.state.clone();
+        let mut position = keyframe.sample;
+        let mut command_index = keyframe.command_index;
+        let mut commands = vec![];
+        while command_index < self.commands.len() && position < sample {
+            let cmd = &self.commands[command_index];
+            state.update(cmd);
+            command_index += 1;
+
+            position += cmd.wait_samples() as u64;
+            if position > sample {
+                commands = wait_commands(position - sample);
+                break;
+            }
+        }
+        commands.extend_from_slice(&self.commands[command_index..]);
+
+        let mut prelude: Vec<Command> = self.commands[..command_index]
+            .iter()
+            .filter(|cmd| is_setup_command(cmd))
+            .cloned()
+            .collect();
+        prelude.extend(state.to_commands());
+
+        SeekResult {
+            prelude,
+            commands,
+            command_index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip_state::ChipState;
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    #[test]
+    fn seek_into_wait() {
+        let mut commands = vec![Command::DataBlock {
+            data_type: 0x00,
+            data_size: 4,
+            data: vec![0x80, 0x90, 0xA0, 0xB0],
+        }];
+        for note in 0..20u8 {
+            commands.extend([
+                Command::YM2612Port0Write {
+                    register: 0xA0,
+                    value: note,
+                },
+                Command::YM2612Port0Address2AWriteWait { n: 0 },
+                Command::PSGWrite {
+                    value: 0x90 | (note & 0x0F),
+                },
+                Command::WaitNSamples { n: 1000 },
+            ]);
+        }
+        let vgm = VgmFile {
+            header: HeaderData::default(),
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let index = vgm.seek_index(4000);
+        assert_eq!(index.keyframes.len(), 5);
+        assert_eq!(index.keyframes[1].sample, 4000);
+
+        let result = vgm.seek(&index, 5500);
+        assert_eq!(result.command_index, 1 + 6 * 4);
+        assert_eq!(result.commands[0], Command::WaitNSamples { n: 500 });
+        assert_eq!(result.commands.len(), 1 + 14 * 4);
+        assert_eq!(result.prelude[0], vgm.commands[0]);
+
+        let mut state = ChipState::new();
+        for cmd in &result.prelude {
+            state.update(cmd);
+        }
+        assert_eq!(state, vgm.chip_state_at_sample(5500));
+        assert_eq!(result.prelude.last(), Some(&Command::SeekPCM { offset: 6 }));
+
+        // same result without going through a keyframe
+        let start_only = vgm.seek_index(u64::MAX);
+        assert_eq!(vgm.seek(&start_only, 5500), result);
+        let every_sample = vgm.seek_index(0);
+        assert_eq!(vgm.seek(&every_sample, 5500), result);
+    }
+}
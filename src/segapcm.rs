@@ -0,0 +1,280 @@
+use crate::command::Command;
+use crate::datablock::{RomImage, DATA_TYPE_SEGA_PCM_ROM};
+use crate::header::HeaderData;
+use crate::vgmfile::VgmFile;
+
+/// Bank size of 1 << 11 bytes
+pub const BANK_256: u8 = 11;
+/// Bank size of 1 << 12 bytes
+pub const BANK_512: u8 = 12;
+/// Bank size of 1 << 13 bytes
+pub const BANK_12M: u8 = 13;
+
+/// Bank mask used when the interface register leaves it at 0
+pub const DEFAULT_BANK_MASK: u8 = 0x70;
+
+/// Bank select wiring of the SegaPCM, stored in `HeaderData::spcm_interface`
+/// the same way as the `intf_bank` value of MAME.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SegaPcmInterface {
+    /// Bits 0-7, the bank register selects blocks of `1 << bank_shift` bytes
+    pub bank_shift: u8,
+    /// Bits 16-23, bits of the bank register that are wired to the ROM
+    pub bank_mask: u8,
+}
+
+/// Boards with a known SegaPCM wiring
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SegaPcmBoard {
+    /// Hang-On, Space Harrier, Enduro Racer
+    HangOn,
+    /// OutRun and X-Board (After Burner, Thunder Blade)
+    OutRunXBoard,
+    /// Y-Board (Galaxy Force, Power Drift)
+    YBoard,
+}
+
+impl SegaPcmBoard {
+    pub fn interface(self) -> SegaPcmInterface {
+        match self {
+            SegaPcmBoard::HangOn => SegaPcmInterface {
+                bank_shift: BANK_256,
+                bank_mask: DEFAULT_BANK_MASK,
+            },
+            SegaPcmBoard::OutRunXBoard => SegaPcmInterface {
+                bank_shift: BANK_512,
+                bank_mask: DEFAULT_BANK_MASK,
+            },
+            SegaPcmBoard::YBoard => SegaPcmInterface {
+                bank_shift: BANK_12M,
+                bank_mask: 0xF8,
+            },
+        }
+    }
+}
+
+impl SegaPcmInterface {
+    pub fn from_raw(raw: u32) -> Self {
+        let bank_mask = (raw >> 16) as u8;
+        SegaPcmInterface {
+            bank_shift: raw as u8,
+            bank_mask: if bank_mask == 0 {
+                DEFAULT_BANK_MASK
+            } else {
+                bank_mask
+            },
+        }
+    }
+
+    pub fn to_raw(self) -> u32 {
+        self.bank_shift as u32 | (self.bank_mask as u32) << 16
+    }
+
+    pub fn board(self) -> Option<SegaPcmBoard> {
+        [
+            SegaPcmBoard::HangOn,
+            SegaPcmBoard::OutRunXBoard,
+            SegaPcmBoard::YBoard,
+        ]
+        .into_iter()
+        .find(|board| board.interface() == self)
+    }
+
+    /// Bank mask once limited to the size of the ROM, as done by the chip
+    pub fn effective_bank_mask(self, rom_size: u32) -> u32 {
+        let rom_mask = rom_size.max(1).next_power_of_two() - 1;
+        self.bank_mask as u32 & (rom_mask >> self.bank_shift)
+    }
+}
+
+impl HeaderData {
+    pub fn sega_pcm_interface(&self) -> SegaPcmInterface {
+        SegaPcmInterface::from_raw(self.spcm_interface)
+    }
+
+    pub fn set_sega_pcm_interface(&mut self, interface: SegaPcmInterface) {
+        self.spcm_interface = interface.to_raw();
+    }
+}
+
+/// Sample played by a SegaPCM channel, addresses are absolute in the ROM image
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SegaPcmSample {
+    pub channel: u8,
+    pub start: u32,
+    /// Exclusive end address
+    pub end: u32,
+    /// `None` when the channel does not loop
+    pub loop_start: Option<u32>,
+}
+
+/// Sample started by a command
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SegaPcmTrigger {
+    pub command_index: usize,
+    pub sample: SegaPcmSample,
+}
+
+/// Register file of a SegaPCM, used to turn its writes into ROM addresses.
+/// Each of the 16 channels has 8 registers at `channel * 8` and 8 more at `0x80 + channel * 8`.
+#[derive(Debug, Clone)]
+pub struct SegaPcm {
+    pub interface: SegaPcmInterface,
+    pub rom_size: u32,
+    pub registers: [u8; 0x100],
+}
+
+impl SegaPcm {
+    pub fn new(interface: SegaPcmInterface, rom_size: u32) -> Self {
+        SegaPcm {
+            interface,
+            rom_size,
+            // every channel starts stopped
+            registers: std::array::from_fn(|reg| if reg & 0x87 == 0x86 { 0x01 } else { 0x00 }),
+        }
+    }
+
+    /// Start of the bank selected by a channel
+    pub fn bank_base(&self, channel: u8) -> u32 {
+        let bank = self.registers[0x86 + channel as usize * 8] as u32;
+        (bank & self.interface.effective_bank_mask(self.rom_size)) << self.interface.bank_shift
+    }
+
+    /// Absolute ROM address of a 16 bits address register pair of a channel
+    fn address(&self, channel: u8, low_reg: usize) -> u32 {
+        let base = channel as usize * 8;
+        let address = (self.registers[base + low_reg + 1] as u32) << 8
+            | self.registers[base + low_reg] as u32;
+        self.bank_base(channel) + address
+    }
+
+    /// Apply a write, returns the sample started by it if it keys a channel on
+    pub fn write(&mut self, offset: u16, value: u8) -> Option<SegaPcmSample> {
+        let offset = offset as usize & 0xFF;
+        let channel = ((offset >> 3) & 0x0F) as u8;
+        let was_off = self.registers[0x86 + channel as usize * 8] & 0x01 != 0;
+        self.registers[offset] = value;
+
+        if offset & 0x87 != 0x86 || !was_off || value & 0x01 != 0 {
+            return None;
+        }
+
+        let base = channel as usize * 8;
+        let start = self.address(channel, 0x84);
+        let end = self.bank_base(channel) + ((self.registers[base + 0x06] as u32 + 1) << 8);
+        let loop_start = if value & 0x02 == 0 {
+            Some(self.address(channel, 0x04))
+        } else {
+            None
+        };
+
+        Some(SegaPcmSample {
+            channel,
+            start,
+            end,
+            loop_start,
+        })
+    }
+}
+
+impl VgmFile {
+    /// ROM of the SegaPCM, assembled from the type 0x80 data blocks
+    pub fn sega_pcm_rom(&self) -> Option<RomImage> {
+        RomImage::from_commands(&self.commands, DATA_TYPE_SEGA_PCM_ROM)
+    }
+
+    /// Every sample started by a `SegaPCMWrite`, with its addresses in the ROM
+    pub fn sega_pcm_triggers(&self) -> Vec<SegaPcmTrigger> {
+        let rom_size = self.sega_pcm_rom().map(|rom| rom.size).unwrap_or(0);
+        let mut sega_pcm = SegaPcm::new(self.header.sega_pcm_interface(), rom_size);
+
+        let mut triggers = vec![];
+        for (command_index, cmd) in self.commands.iter().enumerate() {
+            if let Command::SegaPCMWrite { offset, value } = cmd {
+                if let Some(sample) = sega_pcm.write(*offset, *value) {
+                    triggers.push(SegaPcmTrigger {
+                        command_index,
+                        sample,
+                    });
+                }
+            }
+        }
+
+        triggers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    use super::{SegaPcmBoard, SegaPcmInterface, SegaPcmSample, SegaPcmTrigger};
+
+    #[test]
+    fn outrun_triggers() {
+        let interface = SegaPcmBoard::OutRunXBoard.interface();
+        assert_eq!(
+            SegaPcmInterface::from_raw(0x0C).board(),
+            Some(SegaPcmBoard::OutRunXBoard)
+        );
+
+        let mut rom_block = vec![];
+        rom_block.extend(0x80000u32.to_le_bytes());
+        rom_block.extend(0x3000u32.to_le_bytes());
+        rom_block.extend([0x80; 0x10]);
+
+        let writes = [
+            (0x0E, 0x30), // channel 1 end
+            (0x0C, 0x00), // loop address
+            (0x0D, 0x00),
+            (0x8C, 0x00), // start address
+            (0x8D, 0x10),
+            (0x8E, 0x12), // bank 0x10, no loop, key on
+        ];
+
+        let mut header = HeaderData {
+            version: 171,
+            sega_pcm_clock: 4000000,
+            ..Default::default()
+        };
+        header.set_sega_pcm_interface(interface);
+
+        let mut commands = vec![Command::DataBlock {
+            data_type: 0x80,
+            data_size: rom_block.len() as u32,
+            data: rom_block,
+        }];
+        commands.extend(
+            writes
+                .iter()
+                .map(|&(offset, value)| Command::SegaPCMWrite { offset, value }),
+        );
+
+        let vgm = VgmFile {
+            header,
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+        assert_eq!(vgm.sega_pcm_rom().unwrap().size, 0x80000);
+        assert_eq!(
+            vgm.sega_pcm_triggers(),
+            vec![SegaPcmTrigger {
+                command_index: 6,
+                sample: SegaPcmSample {
+                    channel: 1,
+                    start: 0x11000,
+                    end: 0x13100,
+                    loop_start: None,
+                },
+            }]
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::Hash;
+
+use crate::chip_state::register_write;
+use crate::command::Command;
+use crate::playback::VGM_SAMPLE_RATE;
+use crate::systems::System;
+use crate::vgmfile::VgmFile;
+
+/// Opcode of the data blocks, the only commands too large to encode for their opcode
+const DATA_BLOCK_OPCODE: u8 = 0x67;
+
+/// Statistics on the commands of one or many files. Every field is a sum, the statistics of
+/// several files are combined with `merge`.
+/// Exported as JSON through serde, or as CSV with `to_csv`.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stats {
+    pub files: u64,
+    pub commands: u64,
+    /// Length of the files, in samples at 44100 Hz
+    pub samples: u64,
+    /// Commands by opcode for each chip
+    pub opcodes: HashMap<System, HashMap<u8, u64>>,
+    /// Commands by opcode for the waits, data blocks and streams
+    pub other_opcodes: HashMap<u8, u64>,
+    /// Writes by register for each chip, the addresses are the ones of `ChipState`
+    pub register_writes: HashMap<System, HashMap<u32, u64>>,
+    /// Wait commands by length in samples, the 0x8n commands count when they wait
+    pub wait_lengths: HashMap<u32, u64>,
+    /// Bytes of data by data block type
+    pub data_block_bytes: HashMap<u8, u64>,
+    /// Last latch byte written to the SN76489, selects the register of the data bytes
+    #[cfg_attr(feature = "serde", serde(skip))]
+    sn76489_latch: u8,
+}
+
+fn add_all<K: Hash + Eq + Clone>(into: &mut HashMap<K, u64>, from: &HashMap<K, u64>) {
+    for (key, count) in from {
+        *into.entry(key.clone()).or_default() += count;
+    }
+}
+
+/// Entries sorted by key
+fn sorted<K: Ord + Copy>(map: &HashMap<K, u64>) -> Vec<(K, u64)> {
+    let mut entries: Vec<(K, u64)> = map.iter().map(|(&key, &count)| (key, count)).collect();
+    entries.sort_unstable();
+    entries
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Statistics of a single file
+    pub fn from_file(vgm: &VgmFile) -> Self {
+        let mut stats = Self::new();
+        stats.add_file(vgm);
+        stats
+    }
+
+    /// Add every command of a file
+    pub fn add_file(&mut self, vgm: &VgmFile) {
+        self.files += 1;
+        self.sn76489_latch = 0;
+        for cmd in &vgm.commands {
+            self.add_command(cmd);
+        }
+    }
+
+    /// Add a command, in the order of the file. Files read one command at a time are counted
+    /// by calling `end_file` after their last command.
+    pub fn add_command(&mut self, cmd: &Command) {
+        self.commands += 1;
+        let wait = cmd.wait_samples();
+        self.samples += wait as u64;
+        if wait > 0 {
+            *self.wait_lengths.entry(wait).or_default() += 1;
+        }
+
+        let opcode = match cmd {
+            Command::DataBlock {
+                data_type, data, ..
+            } => {
+                *self.data_block_bytes.entry(*data_type).or_default() += data.len() as u64;
+                DATA_BLOCK_OPCODE
+            }
+            _ => cmd.clone().to_bytes()[0],
+        };
+        let Some(system) = cmd.system() else {
+            *self.other_opcodes.entry(opcode).or_default() += 1;
+            return;
+        };
+        *self
+            .opcodes
+            .entry(system.clone())
+            .or_default()
+            .entry(opcode)
+            .or_default() += 1;
+
+        let address = match *cmd {
+            // the SN76489 registers of `ChipState`
+            Command::PSGWrite { value } => {
+                if value & 0x80 != 0 {
+                    self.sn76489_latch = value;
+                }
+                Some(((self.sn76489_latch >> 4) & 0x07) as u32)
+            }
+            Command::YM2612Port0Address2AWriteWait { .. } => Some(0x2A),
+            _ => register_write(cmd).map(|(_, address, _)| address),
+        };
+        if let Some(address) = address {
+            *self
+                .register_writes
+                .entry(system)
+                .or_default()
+                .entry(address)
+                .or_default() += 1;
+        }
+    }
+
+    /// Count a file read with `add_command`
+    pub fn end_file(&mut self) {
+        self.files += 1;
+        self.sn76489_latch = 0;
+    }
+
+    /// Add the statistics of other files
+    pub fn merge(&mut self, other: &Stats) {
+        self.files += other.files;
+        self.commands += other.commands;
+        self.samples += other.samples;
+        for (system, opcodes) in &other.opcodes {
+            add_all(self.opcodes.entry(system.clone()).or_default(), opcodes);
+        }
+        add_all(&mut self.other_opcodes, &other.other_opcodes);
+        for (system, registers) in &other.register_writes {
+            add_all(
+                self.register_writes.entry(system.clone()).or_default(),
+                registers,
+            );
+        }
+        add_all(&mut self.wait_lengths, &other.wait_lengths);
+        add_all(&mut self.data_block_bytes, &other.data_block_bytes);
+    }
+
+    /// Mean number of commands per second of playback, 0 without any wait
+    pub fn commands_per_second(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        self.commands as f64 * VGM_SAMPLE_RATE as f64 / self.samples as f64
+    }
+
+    /// Export as CSV, one `statistic,chip,key,value` row per value. The chips are in the order
+    /// of their clocks in the header and the keys in increasing order.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("statistic,chip,key,value\n");
+        let mut row = |statistic: &str, chip: Option<&System>, key: String, value: String| {
+            let chip = chip.map(|chip| format!("{chip:?}")).unwrap_or_default();
+            writeln!(csv, "{statistic},{chip},{key},{value}").unwrap();
+        };
+
+        row("files", None, String::new(), self.files.to_string());
+        row("commands", None, String::new(), self.commands.to_string());
+        row("samples", None, String::new(), self.samples.to_string());
+        row(
+            "commands_per_second",
+            None,
+            String::new(),
+            format!("{:.3}", self.commands_per_second()),
+        );
+        for system in &System::ALL {
+            for (opcode, count) in self.opcodes.get(system).map(sorted).unwrap_or_default() {
+                row(
+                    "opcode",
+                    Some(system),
+                    format!("{opcode:#04X}"),
+                    count.to_string(),
+                );
+            }
+        }
+        for (opcode, count) in sorted(&self.other_opcodes) {
+            row("opcode", None, format!("{opcode:#04X}"), count.to_string());
+        }
+        for system in &System::ALL {
+            let registers = self.register_writes.get(system).map(sorted);
+            for (address, count) in registers.unwrap_or_default() {
+                row(
+                    "register",
+                    Some(system),
+                    format!("{address:#X}"),
+                    count.to_string(),
+                );
+            }
+        }
+        for (length, count) in sorted(&self.wait_lengths) {
+            row("wait", None, length.to_string(), count.to_string());
+        }
+        for (data_type, bytes) in sorted(&self.data_block_bytes) {
+            row(
+                "data_block",
+                None,
+                format!("{data_type:#04X}"),
+                bytes.to_string(),
+            );
+        }
+
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::systems::System;
+    use crate::vgmfile::VgmFile;
+
+    use super::Stats;
+
+    #[test]
+    fn merge_and_export() {
+        let vgm = VgmFile {
+            header: HeaderData {
+                version: 151,
+                ..Default::default()
+            },
+            commands: vec![
+                Command::DataBlock {
+                    data_type: 0x00,
+                    data_size: 4,
+                    data: vec![0x80; 4],
+                },
+                Command::PSGWrite { value: 0x9F },
+                Command::PSGWrite { value: 0x8A },
+                Command::PSGWrite { value: 0x03 },
+                Command::YM2612Port0Write {
+                    register: 0x28,
+                    value: 0xF0,
+                },
+                Command::YM2612Port0Address2AWriteWait { n: 0 },
+                Command::Wait735Samples,
+                Command::Wait735Samples,
+                Command::EndOfSoundData,
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let mut stats = Stats::from_file(&vgm);
+        stats.merge(&Stats::from_file(&vgm));
+        assert_eq!((stats.files, stats.commands, stats.samples), (2, 18, 2940));
+        assert_eq!(stats.commands_per_second(), 270.0);
+        assert_eq!(stats.opcodes[&System::Sn76489][&0x50], 6);
+        assert_eq!(stats.opcodes[&System::Ym2612][&0x80], 2);
+        assert_eq!(stats.other_opcodes[&0x62], 4);
+        assert_eq!(stats.other_opcodes[&0x67], 2);
+        // latch of channel 0 volume, then channel 0 tone with its data byte
+        assert_eq!(stats.register_writes[&System::Sn76489][&1], 2);
+        assert_eq!(stats.register_writes[&System::Sn76489][&0], 4);
+        assert_eq!(stats.register_writes[&System::Ym2612][&0x2A], 2);
+        assert_eq!(stats.wait_lengths.len(), 1);
+        assert_eq!(stats.data_block_bytes[&0x00], 8);
+
+        let csv = stats.to_csv();
+        assert!(csv.starts_with("statistic,chip,key,value\nfiles,,,2\n"));
+        assert!(csv.contains("commands_per_second,,,270.000\n"));
+        assert!(csv.contains("opcode,Ym2612,0x52,2\n"));
+        assert!(csv.contains("register,Ym2612,0x28,2\n"));
+        assert!(csv.contains("wait,,735,4\n"));
+        assert!(csv.contains("data_block,,0x00,8\n"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json() {
+        let mut stats = Stats::new();
+        stats.add_command(&Command::YM2151Write {
+            register: 0x08,
+            value: 0x78,
+        });
+        stats.add_command(&Command::WaitNSamples { n: 100 });
+        stats.end_file();
+
+        let json = serde_json::to_string(&stats).unwrap();
+        assert!(json.contains(r#""opcodes":{"Ym2151":{"84":1}}"#));
+        let parsed: Stats = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, stats);
+    }
+}
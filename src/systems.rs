@@ -1,4 +1,5 @@
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum System {
     Sn76489,
     Ym2413,
@@ -44,3 +45,149 @@ pub enum System {
     X1_010,
     Ga20,
 }
+
+/// Chips in the order of their clocks in the header, the numbering used by the chip type of the
+/// DAC stream commands
+const CHIP_TYPES: [System; 41] = [
+    System::Sn76489,
+    System::Ym2413,
+    System::Ym2612,
+    System::Ym2151,
+    System::SegaPcm,
+    System::Rf5c68,
+    System::Ym2203,
+    System::Ym2608,
+    System::Ym2610,
+    System::Ym3812,
+    System::Ym3526,
+    System::Y8950,
+    System::Ymf262,
+    System::Ymf278B,
+    System::Ymf271,
+    System::Ymz280b,
+    System::Rf5c164,
+    System::Pwm,
+    System::Ay8910,
+    System::GameboyDmg,
+    System::NesApu,
+    System::MultiPcm,
+    System::Upd7759,
+    System::Okim6258,
+    System::Okim6295,
+    System::K051649,
+    System::K054539,
+    System::HuC6280,
+    System::C140,
+    System::K053260,
+    System::Pokey,
+    System::QSound,
+    System::Scsp,
+    System::WonderSwan,
+    System::Vsu,
+    System::Saa1099,
+    System::Es5503,
+    System::Es5506,
+    System::X1_010,
+    System::C352,
+    System::Ga20,
+];
+
+impl System {
+    pub const ALL: [System; 43] = [
+        System::Sn76489,
+        System::Ym2413,
+        System::Ym2612,
+        System::Ym2151,
+        System::SegaPcm,
+        System::Rf5c68,
+        System::Ym2203,
+        System::Ym2608,
+        System::Ym2610,
+        System::Ym3812,
+        System::Ym3526,
+        System::Y8950,
+        System::Ymf262,
+        System::Ymf278B,
+        System::Ymf271,
+        System::Ymz280b,
+        System::Rf5c164,
+        System::Pwm,
+        System::Ay8910,
+        System::GameboyDmg,
+        System::NesApu,
+        System::MultiPcm,
+        System::Upd7759,
+        System::Okim6258,
+        System::K054539,
+        System::C140,
+        System::Okim6295,
+        System::K051649,
+        System::K052539,
+        System::HuC6280,
+        System::K053260,
+        System::Pokey,
+        System::QSound,
+        System::Scsp,
+        System::WonderSwan,
+        System::Vsu,
+        System::Saa1099,
+        System::Es5503,
+        System::Es5505,
+        System::Es5506,
+        System::C352,
+        System::X1_010,
+        System::Ga20,
+    ];
+
+    /// First VGM version with a header clock for the chip.
+    /// The YM2612 and YM2151 got their own clocks in 1.10 but could already be used through the YM2413 clock.
+    pub fn min_version(&self) -> u32 {
+        match self {
+            System::Sn76489 | System::Ym2413 | System::Ym2612 | System::Ym2151 => 100,
+            System::SegaPcm
+            | System::Rf5c68
+            | System::Ym2203
+            | System::Ym2608
+            | System::Ym2610
+            | System::Ym3812
+            | System::Ym3526
+            | System::Y8950
+            | System::Ymf262
+            | System::Ymf278B
+            | System::Ymf271
+            | System::Ymz280b
+            | System::Rf5c164
+            | System::Pwm
+            | System::Ay8910 => 151,
+            System::GameboyDmg
+            | System::NesApu
+            | System::MultiPcm
+            | System::Upd7759
+            | System::Okim6258
+            | System::K054539
+            | System::C140
+            | System::Okim6295
+            | System::K051649
+            | System::K052539
+            | System::HuC6280
+            | System::K053260
+            | System::Pokey
+            | System::QSound => 161,
+            System::Scsp
+            | System::WonderSwan
+            | System::Vsu
+            | System::Saa1099
+            | System::Es5503
+            | System::Es5505
+            | System::Es5506
+            | System::C352
+            | System::X1_010
+            | System::Ga20 => 171,
+        }
+    }
+
+    /// Chip of a DAC stream chip type, bit 7 selects the second chip and is ignored
+    pub fn from_chip_type(chip_type: u8) -> Option<System> {
+        CHIP_TYPES.get((chip_type & 0x7F) as usize).cloned()
+    }
+}
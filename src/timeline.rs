@@ -0,0 +1,128 @@
+use std::slice::Iter;
+
+use crate::command::Command;
+use crate::vgmfile::VgmFile;
+
+/// Iterator over the commands of a file with the sample at which each one runs
+/// and its absolute position in the file, see `VgmFile::timeline`.
+#[derive(Debug, Clone)]
+pub struct Timeline<'a> {
+    commands: Iter<'a, Command>,
+    sample_position: u64,
+    byte_offset: usize,
+}
+
+impl<'a> Timeline<'a> {
+    pub fn new(vgm: &'a VgmFile) -> Self {
+        Timeline {
+            commands: vgm.commands.iter(),
+            sample_position: 0,
+            byte_offset: vgm.header.vgm_data_pos(),
+        }
+    }
+
+    /// Sample at which the next command runs, the length of the file once the iterator is done
+    pub fn sample_position(&self) -> u64 {
+        self.sample_position
+    }
+
+    /// Position of the next command, the end of sound data command once the iterator is done
+    pub fn byte_offset(&self) -> usize {
+        self.byte_offset
+    }
+}
+
+impl<'a> Iterator for Timeline<'a> {
+    type Item = (u64, usize, &'a Command);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cmd = self.commands.next()?;
+        let item = (self.sample_position, self.byte_offset, cmd);
+        self.sample_position += cmd.wait_samples() as u64;
+        self.byte_offset += cmd.encoded_len();
+
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.commands.size_hint()
+    }
+}
+
+/// Wait commands for a number of samples, using as few 0x61 commands as possible
+pub fn wait_commands(samples: u64) -> Vec<Command> {
+    let mut commands = vec![];
+    let mut samples = samples;
+    while samples > 0 {
+        let n = samples.min(u16::MAX as u64);
+        commands.push(Command::WaitNSamples { n: n as u16 });
+        samples -= n;
+    }
+
+    commands
+}
+
+impl VgmFile {
+    /// Every command as `(sample_position, byte_offset, command)`
+    pub fn timeline(&self) -> Timeline<'_> {
+        Timeline::new(self)
+    }
+
+    /// Length of the file in samples, from the waits rather than the header
+    pub fn total_samples(&self) -> u64 {
+        self.commands
+            .iter()
+            .map(|cmd| cmd.wait_samples() as u64)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    #[test]
+    fn timestamps() {
+        let vgm = VgmFile {
+            header: HeaderData {
+                version: 150,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands: vec![
+                Command::PSGWrite { value: 0x9F },
+                Command::Wait735Samples,
+                Command::YM2612Port0Address2AWriteWait { n: 3 },
+                Command::WaitNSamplesPlus1 { n: 0 },
+                Command::WaitNSamples { n: 1000 },
+                Command::Wait882Samples,
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let timeline: Vec<(u64, usize)> = vgm
+            .timeline()
+            .map(|(sample, offset, _)| (sample, offset))
+            .collect();
+        assert_eq!(
+            timeline,
+            [
+                (0, 0x40),
+                (0, 0x42),
+                (735, 0x43),
+                (738, 0x44),
+                (739, 0x45),
+                (1739, 0x48)
+            ]
+        );
+        assert_eq!(vgm.total_samples(), 2621);
+
+        let mut timeline = vgm.timeline();
+        timeline.by_ref().for_each(drop);
+        assert_eq!(timeline.byte_offset(), 0x49);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::command::Command;
+use crate::errors::LibError;
+use crate::timeline::wait_commands;
+use crate::vgmfile::VgmFile;
+
+/// First `samples` samples of a command that waits longer
+fn shorten_wait(cmd: &Command, samples: u64) -> Vec<Command> {
+    match cmd {
+        Command::YM2612Port0Address2AWriteWait { .. } => {
+            vec![Command::YM2612Port0Address2AWriteWait { n: samples as u8 }]
+        }
+        _ => wait_commands(samples),
+    }
+}
+
+impl VgmFile {
+    /// Cut the file between two sample positions, like vgm_trim.
+    /// The new file starts with the writes recreating the chip state at `start` and loops at
+    /// `loop_start` if given, the header offsets and sample counts are recomputed.
+    pub fn trim(&self, start: u64, loop_start: Option<u64>, end: u64) -> Result<VgmFile, LibError> {
+        let valid_loop =
+            loop_start.is_none_or(|loop_start| start <= loop_start && loop_start < end);
+        if start >= end || !valid_loop {
+            return Err(LibError::InvalidTrimRange {
+                start,
+                loop_start,
+                end,
+            });
+        }
+
+        let seek = self.seek(&self.seek_index(u64::MAX), start);
+        let mut commands = seek.prelude;
+        let mut loop_index = None;
+        let mut position = start;
+        for cmd in &seek.commands {
+            if position >= end {
+                break;
+            }
+
+            let looping = loop_start.filter(|_| loop_index.is_none());
+            if looping.is_some_and(|loop_start| position >= loop_start) {
+                loop_index = Some(commands.len());
+            }
+
+            let wait = cmd.wait_samples() as u64;
+            let next_position = (position + wait).min(end);
+            match looping {
+                // the wait is split to put the loop point at the right sample
+                Some(loop_start) if position < loop_start && loop_start < next_position => {
+                    commands.extend(shorten_wait(cmd, loop_start - position));
+                    loop_index = Some(commands.len());
+                    commands.extend(wait_commands(next_position - loop_start));
+                }
+                _ if position + wait > end => commands.extend(shorten_wait(cmd, end - position)),
+                _ => commands.push(cmd.clone()),
+            }
+            position = next_position;
+        }
+
+        let mut header = self.header.clone();
+        // the loop of the source file does not apply, `update_offsets` sets the new one
+        header.loop_offset = 0;
+        header.total_nb_samples = (position - start) as u32;
+        header.loop_nb_samples = match (loop_index, loop_start) {
+            (Some(_), Some(loop_start)) => (position - loop_start) as u32,
+            _ => 0,
+        };
+
+        let mut vgm = VgmFile {
+            header,
+            commands,
+            metadata: self.metadata.clone(),
+            loop_index,
+        };
+        vgm.update_offsets();
+
+        Ok(vgm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    #[test]
+    fn trim() {
+        let mut commands = vec![];
+        for note in 0..10u8 {
+            commands.extend([
+                Command::PSGWrite { value: 0x90 | note },
+                Command::WaitNSamples { n: 1000 },
+            ]);
+        }
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 150,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let trimmed = vgm.trim(1500, Some(3000), 4200).unwrap();
+        assert_eq!(
+            trimmed.commands,
+            [
+                // state at 1500
+                Command::PSGWrite { value: 0x91 },
+                Command::WaitNSamples { n: 500 },
+                Command::PSGWrite { value: 0x92 },
+                Command::WaitNSamples { n: 1000 },
+                Command::PSGWrite { value: 0x93 },
+                Command::WaitNSamples { n: 1000 },
+                Command::PSGWrite { value: 0x94 },
+                Command::WaitNSamples { n: 200 },
+            ]
+        );
+        assert_eq!(trimmed.loop_index, Some(4));
+        assert_eq!(trimmed.header.total_nb_samples, 2700);
+        assert_eq!(trimmed.header.loop_nb_samples, 1200);
+
+        let mut buffer = BytesMut::new();
+        trimmed.to_bytes(&mut buffer);
+        assert_eq!(buffer.len(), trimmed.header.end_of_file_offset as usize + 4);
+        let parsed = VgmFile::from_bytes(&mut Bytes::from(buffer.to_vec())).unwrap();
+        assert_eq!(parsed.loop_index, Some(4));
+
+        // loop point in the middle of a wait
+        let trimmed = vgm.trim(0, Some(2500), 3000).unwrap();
+        assert_eq!(trimmed.loop_index, Some(6));
+        assert_eq!(trimmed.commands[5], Command::WaitNSamples { n: 500 });
+        assert_eq!(trimmed.commands[6], Command::WaitNSamples { n: 500 });
+
+        assert!(vgm.trim(3000, Some(2000), 4000).is_err());
+
+        // the loop of the source file is dropped
+        vgm.loop_index = Some(3);
+        vgm.update_offsets();
+        let trimmed = vgm.trim(1500, None, 4200).unwrap();
+        assert_eq!(trimmed.loop_index, None);
+        assert_eq!(trimmed.header.loop_offset, 0);
+    }
+}
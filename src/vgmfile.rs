@@ -4,52 +4,266 @@ use std::{
 };
 
 use crate::command::{parse_commands, write_commands, Command};
+use crate::errors::LibError;
 use crate::header::HeaderData;
 use crate::metadata::VgmMetadata;
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use flate2::bufread::GzDecoder;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VgmFile {
     pub header: HeaderData,
     pub commands: Vec<Command>,
     pub metadata: VgmMetadata,
+    /// Index in `commands` of the first command of the loop, `None` when the file does not loop.
+    /// `update_offsets` writes it back to `HeaderData::loop_offset`.
+    pub loop_index: Option<usize>,
 }
 
 impl VgmFile {
-    pub fn from_path_gz(path: &str) -> Self {
-        Self::from_bytes(&mut bytes::Bytes::from_iter(
-            GzDecoder::new(BufReader::new(File::open(path).unwrap()))
-                .bytes()
-                .map(|x| x.unwrap()),
-        ))
+    pub fn from_path_gz(path: &str) -> Result<Self, LibError> {
+        let mut file_data = vec![];
+        GzDecoder::new(BufReader::new(File::open(path).unwrap()))
+            .read_to_end(&mut file_data)
+            .unwrap();
+        Self::from_bytes(&mut Bytes::from(file_data))
     }
 
-    pub fn from_path(path: &str) -> Self {
+    pub fn from_path(path: &str) -> Result<Self, LibError> {
         let file_data = fs::read(path).unwrap();
         let mut data = Bytes::from(file_data);
         VgmFile::from_bytes(&mut data)
     }
 
-    pub fn from_bytes(data: &mut bytes::Bytes) -> Self {
+    /// Parse a file, a loop offset that does not point to the start of a command is an error
+    pub fn from_bytes(data: &mut bytes::Bytes) -> Result<Self, LibError> {
         let len_data = data.len();
         let header_data = HeaderData::from_bytes(data);
-        let vgm_start_pos = header_data.vgm_data_offset as usize + 0x34;
+        let vgm_start_pos = header_data.vgm_data_pos();
 
         while len_data - data.len() < vgm_start_pos {
             data.get_u8();
         }
 
-        Self {
+        let commands = parse_commands(data);
+
+        // GD3 tag is optional
+        let metadata = if header_data.gd3_offset == 0 {
+            VgmMetadata::default()
+        } else {
+            let gd3_pos = header_data.gd3_offset as usize + 0x14;
+            while len_data - data.len() < gd3_pos {
+                data.get_u8();
+            }
+            VgmMetadata::from_bytes(data)
+        };
+
+        let mut vgm = Self {
             header: header_data,
-            commands: parse_commands(data),
-            metadata: VgmMetadata::from_bytes(data),
-        }
+            commands,
+            metadata,
+            loop_index: None,
+        };
+        vgm.loop_index = vgm.resolve_loop_index()?;
+
+        Ok(vgm)
     }
 
     pub fn to_bytes(&self, buffer: &mut bytes::BytesMut) {
         self.header.to_bytes(buffer);
         write_commands(buffer, &self.commands);
-        self.metadata.to_bytes(buffer);
+        buffer.put(&Command::EndOfSoundData.to_bytes()[..]);
+        if self.header.gd3_offset != 0 {
+            self.metadata.to_bytes(buffer);
+        }
+    }
+
+    /// Absolute position in the file of every command, followed by the position of the end of sound data command
+    pub fn command_positions(&self) -> Vec<usize> {
+        let mut pos = self.header.vgm_data_pos();
+        let mut positions = Vec::with_capacity(self.commands.len() + 1);
+        for cmd in &self.commands {
+            positions.push(pos);
+            pos += cmd.encoded_len();
+        }
+        positions.push(pos);
+
+        positions
+    }
+
+    /// Index of the command starting at the given absolute position
+    pub fn command_index_at(&self, pos: usize) -> Option<usize> {
+        self.command_positions()
+            .binary_search(&pos)
+            .ok()
+            .filter(|&index| index < self.commands.len())
+    }
+
+    /// Command index of `HeaderData::loop_offset`, an error if it does not point to the start of a command
+    pub fn resolve_loop_index(&self) -> Result<Option<usize>, LibError> {
+        if self.header.loop_offset == 0 {
+            return Ok(None);
+        }
+
+        let loop_pos = self.header.loop_offset as usize + 0x1C;
+        match self.command_index_at(loop_pos) {
+            Some(index) => Ok(Some(index)),
+            None => Err(LibError::InvalidLoopOffset { offset: loop_pos }),
+        }
+    }
+
+    /// Insert a command, a command inserted at the loop point goes before the loop
+    pub fn insert_command(&mut self, index: usize, cmd: Command) {
+        self.commands.insert(index, cmd);
+        if let Some(loop_index) = self.loop_index.as_mut() {
+            if index <= *loop_index {
+                *loop_index += 1;
+            }
+        }
+    }
+
+    /// Remove a command, if it starts the loop the loop starts at the next command instead
+    pub fn remove_command(&mut self, index: usize) -> Command {
+        let cmd = self.commands.remove(index);
+        self.loop_index = match self.loop_index {
+            Some(loop_index) if index < loop_index => Some(loop_index - 1),
+            Some(loop_index) if loop_index >= self.commands.len() => None,
+            loop_index => loop_index,
+        };
+
+        cmd
+    }
+
+    /// Recompute the loop, GD3 and end of file offsets after the header or the commands changed size.
+    /// A file without GD3 tag (offset of 0) stays without one, a loop offset that does not
+    /// resolve to a command is kept when there is no `loop_index`.
+    pub fn update_offsets(&mut self) {
+        let positions = self.command_positions();
+        self.header.loop_offset = match self.loop_index {
+            Some(index) if index < self.commands.len() => (positions[index] - 0x1C) as u32,
+            Some(_) => {
+                self.loop_index = None;
+                0
+            }
+            None if self.resolve_loop_index().is_err() => self.header.loop_offset,
+            None => 0,
+        };
+
+        let end_of_data = *positions.last().unwrap() + 1;
+
+        let mut total_len = end_of_data;
+        if self.header.gd3_offset != 0 {
+            self.header.gd3_offset = (end_of_data - 0x14) as u32;
+
+            let mut gd3 = BytesMut::new();
+            self.metadata.to_bytes(&mut gd3);
+            total_len += gd3.len();
+        }
+
+        self.header.end_of_file_offset = (total_len - 0x04) as u32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+
+    use crate::command::Command;
+    use crate::errors::LibError;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+
+    use super::VgmFile;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 171,
+                ym2612_clock: 7670453,
+                sn76489_clock: 3579545,
+                ..Default::default()
+            },
+            commands: vec![
+                Command::DataBlock {
+                    data_type: 0x00,
+                    data_size: 4,
+                    data: vec![0x80, 0x90, 0xA0, 0xB0],
+                },
+                Command::YM2612Port0Write {
+                    register: 0x28,
+                    value: 0xF0,
+                },
+                Command::PSGWrite { value: 0x9F },
+                Command::WaitNSamples { n: 1000 },
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+        vgm.header.vgm_data_offset = 0x100 - 0x34;
+        vgm.update_offsets();
+
+        let mut original = BytesMut::new();
+        vgm.to_bytes(&mut original);
+
+        let json = serde_json::to_string(&vgm).unwrap();
+        assert!(json.contains("\"gJCgsA==\""));
+
+        let parsed: VgmFile = serde_json::from_str(&json).unwrap();
+        let mut out = BytesMut::new();
+        parsed.to_bytes(&mut out);
+        assert_eq!(out, original);
+
+        let reparsed = VgmFile::from_bytes(&mut Bytes::from(original.to_vec())).unwrap();
+        assert_eq!(reparsed.commands, vgm.commands);
+    }
+
+    #[test]
+    fn loop_point() {
+        let mut vgm = VgmFile {
+            header: HeaderData {
+                version: 150,
+                vgm_data_offset: 0x0C,
+                ..Default::default()
+            },
+            commands: vec![
+                Command::PSGWrite { value: 0x9F },
+                Command::WaitNSamples { n: 100 },
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: Some(1),
+        };
+        vgm.update_offsets();
+        assert_eq!(vgm.header.loop_offset, 0x42 - 0x1C);
+
+        let mut buffer = BytesMut::new();
+        vgm.to_bytes(&mut buffer);
+        let parsed = VgmFile::from_bytes(&mut Bytes::from(buffer.to_vec())).unwrap();
+        assert_eq!(parsed.loop_index, Some(1));
+
+        // in the middle of the wait
+        vgm.header.loop_offset += 1;
+        assert_eq!(
+            vgm.resolve_loop_index(),
+            Err(LibError::InvalidLoopOffset { offset: 0x43 })
+        );
+
+        // reported when parsing, kept by `update_offsets` without a loop index
+        buffer.clear();
+        vgm.to_bytes(&mut buffer);
+        assert_eq!(
+            VgmFile::from_bytes(&mut Bytes::from(buffer.to_vec())).unwrap_err(),
+            LibError::InvalidLoopOffset { offset: 0x43 }
+        );
+        vgm.loop_index = None;
+        vgm.update_offsets();
+        assert_eq!(vgm.header.loop_offset, 0x43 - 0x1C);
+
+        // a loop past the commands is dropped
+        vgm.loop_index = Some(2);
+        vgm.update_offsets();
+        assert_eq!((vgm.loop_index, vgm.header.loop_offset), (None, 0));
     }
 }
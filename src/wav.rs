@@ -0,0 +1,78 @@
+use bytes::{BufMut, BytesMut};
+
+/// Uncompressed PCM audio in a WAV container
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WavFile {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// 8 bits samples are unsigned, 16 bits samples are signed little endian
+    pub bits_per_sample: u16,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_base64"))]
+    pub data: Vec<u8>,
+}
+
+impl WavFile {
+    /// Mono 8 bits unsigned samples
+    pub fn from_u8(sample_rate: u32, samples: Vec<u8>) -> Self {
+        WavFile {
+            sample_rate,
+            channels: 1,
+            bits_per_sample: 8,
+            data: samples,
+        }
+    }
+
+    /// Mono 16 bits signed samples
+    pub fn from_i16(sample_rate: u32, samples: &[i16]) -> Self {
+        WavFile {
+            sample_rate,
+            channels: 1,
+            bits_per_sample: 16,
+            data: samples
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect(),
+        }
+    }
+
+    /// Number of samples per channel
+    pub fn len(&self) -> usize {
+        self.data.len() / (self.channels as usize * self.bits_per_sample as usize / 8).max(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn to_bytes(&self, buffer: &mut BytesMut) {
+        let block_align = self.channels * self.bits_per_sample / 8;
+        let padding = self.data.len() % 2;
+
+        buffer.put_slice(b"RIFF");
+        buffer.put_u32_le((36 + self.data.len() + padding) as u32);
+        buffer.put_slice(b"WAVE");
+
+        buffer.put_slice(b"fmt ");
+        buffer.put_u32_le(16);
+        // PCM
+        buffer.put_u16_le(1);
+        buffer.put_u16_le(self.channels);
+        buffer.put_u32_le(self.sample_rate);
+        buffer.put_u32_le(self.sample_rate * block_align as u32);
+        buffer.put_u16_le(block_align);
+        buffer.put_u16_le(self.bits_per_sample);
+
+        buffer.put_slice(b"data");
+        buffer.put_u32_le(self.data.len() as u32);
+        buffer.put_slice(&self.data);
+        // chunks are word aligned
+        buffer.put_bytes(0, padding);
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut buffer = BytesMut::new();
+        self.to_bytes(&mut buffer);
+        std::fs::write(path, buffer)
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashSet;
+
+use crate::adpcm::{adpcm_b_rate, decode_adpcm_a, decode_adpcm_b, DecodedSample};
+use crate::command::Command;
+use crate::datablock::RomImage;
+use crate::systems::System;
+use crate::vgmfile::VgmFile;
+
+/// YM2608 DELTA-T (ADPCM-B) ROM dump
+pub const DATA_TYPE_YM2608_DELTA_T_ROM: u8 = 0x81;
+
+/// Size of the internal rhythm ROM
+pub const YM2608_RHYTHM_ROM_SIZE: usize = 0x2000;
+
+/// Instruments of the rhythm section, in the order of the key on bits of register 0x10
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Ym2608Rhythm {
+    BassDrum,
+    SnareDrum,
+    TopCymbal,
+    HiHat,
+    Tom,
+    RimShot,
+}
+
+impl Ym2608Rhythm {
+    pub const ALL: [Ym2608Rhythm; 6] = [
+        Ym2608Rhythm::BassDrum,
+        Ym2608Rhythm::SnareDrum,
+        Ym2608Rhythm::TopCymbal,
+        Ym2608Rhythm::HiHat,
+        Ym2608Rhythm::Tom,
+        Ym2608Rhythm::RimShot,
+    ];
+
+    /// Start and exclusive end of the instrument in the rhythm ROM
+    pub fn rom_range(self) -> (u32, u32) {
+        match self {
+            Ym2608Rhythm::BassDrum => (0x0000, 0x01C0),
+            Ym2608Rhythm::SnareDrum => (0x01C0, 0x0440),
+            Ym2608Rhythm::TopCymbal => (0x0440, 0x1B80),
+            Ym2608Rhythm::HiHat => (0x1B80, 0x1D00),
+            Ym2608Rhythm::Tom => (0x1D00, 0x1F80),
+            Ym2608Rhythm::RimShot => (0x1F80, 0x2000),
+        }
+    }
+}
+
+/// Rhythm instrument decoded from the rhythm ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RhythmSample {
+    pub instrument: Ym2608Rhythm,
+    pub sample: DecodedSample,
+}
+
+/// ADPCM-B memory and registers (port 1, 0x00 - 0x10) of a YM2608
+struct AdpcmB {
+    registers: [u8; 0x11],
+    memory: Vec<u8>,
+    write_address: usize,
+}
+
+impl AdpcmB {
+    /// Addresses count 4 bytes with a 1 bit DRAM and 32 bytes with a 8 bits DRAM or a ROM
+    fn address_shift(&self) -> u32 {
+        if self.registers[0x01] & 0x03 == 0 {
+            2
+        } else {
+            5
+        }
+    }
+
+    fn start(&self) -> u32 {
+        ((self.registers[0x03] as u32) << 8 | self.registers[0x02] as u32) << self.address_shift()
+    }
+
+    /// Exclusive end of the sample
+    fn end(&self) -> u32 {
+        (((self.registers[0x05] as u32) << 8 | self.registers[0x04] as u32) + 1)
+            << self.address_shift()
+    }
+
+    fn delta_n(&self) -> u16 {
+        (self.registers[0x0A] as u16) << 8 | self.registers[0x09] as u16
+    }
+
+    /// Apply a write, returns true if it starts a playback from the memory
+    fn write(&mut self, register: u8, value: u8) -> bool {
+        let register = register as usize;
+        if register >= self.registers.len() {
+            return false;
+        }
+        // record to the memory with the CPU writing the data
+        let memory_write = self.registers[0x00] & 0x60 == 0x60;
+        if register == 0x08 && memory_write {
+            if self.memory.len() <= self.write_address {
+                self.memory.resize(self.write_address + 1, 0);
+            }
+            self.memory[self.write_address] = value;
+            self.write_address += 1;
+            return false;
+        }
+
+        self.registers[register] = value;
+        if matches!(register, 0x00..=0x03) {
+            self.write_address = self.start() as usize;
+        }
+
+        register == 0x00 && value & 0xE0 == 0xA0
+    }
+}
+
+impl VgmFile {
+    /// Every sample played by the ADPCM-B unit of the YM2608, from the DELTA-T ROM blocks and the
+    /// data written to its RAM through port 1 register 0x08. A sample is listed again when the
+    /// memory changed between two playbacks.
+    pub fn ym2608_adpcm_b_samples(&self) -> Vec<DecodedSample> {
+        let rom = RomImage::from_commands(&self.commands, DATA_TYPE_YM2608_DELTA_T_ROM);
+        let mut chip = AdpcmB {
+            registers: [0; 0x11],
+            memory: rom.map(|rom| rom.data).unwrap_or_default(),
+            write_address: 0,
+        };
+        let chip_rate = self.header.clock(&System::Ym2608) / 144;
+
+        let mut samples = vec![];
+        let mut seen = HashSet::new();
+        for (command_index, cmd) in self.commands.iter().enumerate() {
+            let Command::YM2608Port1Write { register, value } = *cmd else {
+                continue;
+            };
+            if !chip.write(register, value) {
+                continue;
+            }
+
+            let end = (chip.end() as usize).min(chip.memory.len());
+            let start = (chip.start() as usize).min(end);
+            let data = &chip.memory[start..end];
+            if seen.insert((start, end, data.to_vec())) {
+                samples.push(DecodedSample {
+                    command_index,
+                    start: start as u32,
+                    end: end as u32,
+                    rate: adpcm_b_rate(chip_rate, chip.delta_n()),
+                    samples: decode_adpcm_b(data),
+                });
+            }
+        }
+
+        samples
+    }
+
+    /// Rhythm instruments keyed on in the file, decoded from a dump of the internal rhythm ROM
+    /// which is not part of VGM files. They play at the clock divided by 432.
+    pub fn ym2608_rhythm_samples(&self, rhythm_rom: &[u8]) -> Vec<RhythmSample> {
+        let rate = self.header.clock(&System::Ym2608) / 432;
+        let mut samples: Vec<RhythmSample> = vec![];
+        for (command_index, cmd) in self.commands.iter().enumerate() {
+            // register 0x10 keys on the instruments of its low bits, or dumps them with bit 7
+            let Command::YM2608Port0Write {
+                register: 0x10,
+                value,
+            } = *cmd
+            else {
+                continue;
+            };
+            if value & 0x80 != 0 {
+                continue;
+            }
+
+            for (bit, instrument) in Ym2608Rhythm::ALL.into_iter().enumerate() {
+                if value & 1 << bit == 0 || samples.iter().any(|s| s.instrument == instrument) {
+                    continue;
+                }
+                let (start, end) = instrument.rom_range();
+                let data = rhythm_rom
+                    .get(start as usize..end as usize)
+                    .unwrap_or_default();
+                samples.push(RhythmSample {
+                    instrument,
+                    sample: DecodedSample {
+                        command_index,
+                        start,
+                        end,
+                        rate,
+                        samples: decode_adpcm_a(data),
+                    },
+                });
+            }
+        }
+
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adpcm::decode_adpcm_b;
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    use super::Ym2608Rhythm;
+
+    #[test]
+    fn adpcm_b_from_ram() {
+        let port1 = |register, value| Command::YM2608Port1Write { register, value };
+        let mut commands = vec![
+            // 1 bit DRAM, addresses 0x0004 - 0x0007 are bytes 0x10 - 0x1F
+            port1(0x01, 0x00),
+            port1(0x00, 0x60),
+            port1(0x02, 0x04),
+            port1(0x03, 0x00),
+            port1(0x04, 0x07),
+            port1(0x05, 0x00),
+        ];
+        commands.extend((0..16).map(|i| port1(0x08, 0x70 + i)));
+        commands.extend([
+            port1(0x00, 0x00),
+            // about 16kHz
+            port1(0x09, 0xBA),
+            port1(0x0A, 0x49),
+            port1(0x00, 0xA0),
+            Command::YM2608Port0Write {
+                register: 0x10,
+                value: 0x05,
+            },
+        ]);
+        let vgm = VgmFile {
+            header: HeaderData {
+                version: 151,
+                ym2608_clock: 7987200,
+                ..Default::default()
+            },
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let samples = vgm.ym2608_adpcm_b_samples();
+        assert_eq!(samples.len(), 1);
+        assert_eq!((samples[0].start, samples[0].end), (0x10, 0x20));
+        assert_eq!(samples[0].rate, 15973);
+        let data: Vec<u8> = (0x70..0x80).collect();
+        assert_eq!(samples[0].samples, decode_adpcm_b(&data));
+
+        let rom = vec![0x08; 0x2000];
+        let rhythm = vgm.ym2608_rhythm_samples(&rom);
+        assert_eq!(rhythm.len(), 2);
+        assert_eq!(rhythm[0].instrument, Ym2608Rhythm::BassDrum);
+        assert_eq!(rhythm[1].instrument, Ym2608Rhythm::TopCymbal);
+        assert_eq!(rhythm[1].sample.samples.len(), 2 * (0x1B80 - 0x0440));
+        assert_eq!(rhythm[1].sample.rate, 18488);
+    }
+}
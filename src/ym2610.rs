@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use crate::adpcm::{adpcm_b_rate, decode_adpcm_a, decode_adpcm_b, DecodedSample};
+use crate::command::Command;
+use crate::datablock::RomImage;
+use crate::vgmfile::VgmFile;
+
+/// YM2610 ADPCM-A ROM dump
+pub const DATA_TYPE_YM2610_ADPCM_A_ROM: u8 = 0x82;
+/// YM2610 DELTA-T (ADPCM-B) ROM dump
+pub const DATA_TYPE_YM2610_ADPCM_B_ROM: u8 = 0x83;
+
+/// Bit of the clock field telling a YM2610B
+const YM2610B_FLAG: u32 = 0x80000000;
+
+/// Unit of the sample playing a key on
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Ym2610Unit {
+    /// One of the 6 ADPCM-A channels
+    AdpcmA(u8),
+    AdpcmB,
+}
+
+/// Sample played by a key on, `sample_index` is its index in the list of samples of its unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ym2610KeyOn {
+    pub command_index: usize,
+    pub sample_position: u64,
+    pub unit: Ym2610Unit,
+    pub sample_index: usize,
+}
+
+/// Samples decoded from the ROMs of a YM2610 and the key ons playing them
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ym2610Samples {
+    pub adpcm_a: Vec<DecodedSample>,
+    pub adpcm_b: Vec<DecodedSample>,
+    pub key_ons: Vec<Ym2610KeyOn>,
+}
+
+/// Add a sample to a list unless it is already in it, returns its index
+fn sample_index(
+    samples: &mut Vec<DecodedSample>,
+    indexes: &mut HashMap<(u32, u32), usize>,
+    rom: &RomImage,
+    sample: DecodedSample,
+    decode: fn(&[u8]) -> Vec<i16>,
+) -> usize {
+    *indexes
+        .entry((sample.start, sample.end))
+        .or_insert_with(|| {
+            samples.push(DecodedSample {
+                samples: decode(rom.slice(sample.start, sample.end)),
+                ..sample
+            });
+            samples.len() - 1
+        })
+}
+
+impl VgmFile {
+    /// Decode the ADPCM-A and ADPCM-B samples played from the ROM images, cut by the start and
+    /// end addresses set when each channel is keyed on. Addresses count 256 bytes.
+    pub fn ym2610_samples(&self) -> Ym2610Samples {
+        let rom_a = RomImage::from_commands(&self.commands, DATA_TYPE_YM2610_ADPCM_A_ROM)
+            .unwrap_or_default();
+        let rom_b = RomImage::from_commands(&self.commands, DATA_TYPE_YM2610_ADPCM_B_ROM)
+            .unwrap_or_default();
+        let clock = self.header.ym2610b_clock & !YM2610B_FLAG;
+
+        let mut result = Ym2610Samples::default();
+        let mut indexes_a = HashMap::new();
+        let mut indexes_b = HashMap::new();
+        let mut port0 = [0u8; 0x100];
+        let mut port1 = [0u8; 0x100];
+        let address = |registers: &[u8; 0x100], low: usize, high: usize| -> u32 {
+            ((registers[high] as u32) << 8 | registers[low] as u32) << 8
+        };
+        for (command_index, (sample_position, _, cmd)) in self.timeline().enumerate() {
+            match *cmd {
+                Command::YM2610Port0Write { register, value } => {
+                    port0[register as usize] = value;
+                    // ADPCM-B start from the ROM
+                    if register != 0x10 || value & 0x80 == 0 {
+                        continue;
+                    }
+                    let delta_n = (port0[0x1A] as u16) << 8 | port0[0x19] as u16;
+                    let sample = DecodedSample {
+                        command_index,
+                        start: address(&port0, 0x12, 0x13),
+                        end: address(&port0, 0x14, 0x15) + 0x100,
+                        rate: adpcm_b_rate(clock / 144, delta_n),
+                        samples: vec![],
+                    };
+                    let sample_index = sample_index(
+                        &mut result.adpcm_b,
+                        &mut indexes_b,
+                        &rom_b,
+                        sample,
+                        decode_adpcm_b,
+                    );
+                    result.key_ons.push(Ym2610KeyOn {
+                        command_index,
+                        sample_position,
+                        unit: Ym2610Unit::AdpcmB,
+                        sample_index,
+                    });
+                }
+                Command::YM2610Port1Write { register, value } => {
+                    port1[register as usize] = value;
+                    // ADPCM-A key on of the channels of the low bits, bit 7 dumps them
+                    if register != 0x00 || value & 0x80 != 0 {
+                        continue;
+                    }
+                    for channel in (0..6).filter(|channel| value & 1 << channel != 0) {
+                        let sample = DecodedSample {
+                            command_index,
+                            start: address(&port1, 0x10 + channel, 0x18 + channel),
+                            end: address(&port1, 0x20 + channel, 0x28 + channel) + 0x100,
+                            rate: clock / 432,
+                            samples: vec![],
+                        };
+                        let sample_index = sample_index(
+                            &mut result.adpcm_a,
+                            &mut indexes_a,
+                            &rom_a,
+                            sample,
+                            decode_adpcm_a,
+                        );
+                        result.key_ons.push(Ym2610KeyOn {
+                            command_index,
+                            sample_position,
+                            unit: Ym2610Unit::AdpcmA(channel as u8),
+                            sample_index,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adpcm::{decode_adpcm_a, decode_adpcm_b};
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    use super::{Ym2610Unit, DATA_TYPE_YM2610_ADPCM_A_ROM, DATA_TYPE_YM2610_ADPCM_B_ROM};
+
+    fn rom_block(data_type: u8, data: &[u8]) -> Command {
+        let mut block = vec![];
+        block.extend((data.len() as u32).to_le_bytes());
+        block.extend(0u32.to_le_bytes());
+        block.extend(data);
+        Command::DataBlock {
+            data_type,
+            data_size: block.len() as u32,
+            data: block,
+        }
+    }
+
+    #[test]
+    fn key_on_index() {
+        let rom: Vec<u8> = (0..0x300).map(|i| (i / 3) as u8).collect();
+        let port0 = |register, value| Command::YM2610Port0Write { register, value };
+        let port1 = |register, value| Command::YM2610Port1Write { register, value };
+        let vgm = VgmFile {
+            header: HeaderData {
+                version: 151,
+                ym2610b_clock: 8000000,
+                ..Default::default()
+            },
+            commands: vec![
+                rom_block(DATA_TYPE_YM2610_ADPCM_A_ROM, &rom),
+                rom_block(DATA_TYPE_YM2610_ADPCM_B_ROM, &rom),
+                // channels 1 and 3 play 0x100 - 0x2FF, then channel 1 plays 0x000 - 0x0FF
+                port1(0x11, 0x01),
+                port1(0x21, 0x02),
+                port1(0x13, 0x01),
+                port1(0x23, 0x02),
+                port1(0x00, 0x0A),
+                Command::Wait735Samples,
+                port1(0x11, 0x00),
+                port1(0x21, 0x00),
+                port1(0x00, 0x02),
+                // ADPCM-B plays 0x200 - 0x2FF
+                port0(0x12, 0x02),
+                port0(0x14, 0x02),
+                port0(0x19, 0x00),
+                port0(0x1A, 0x80),
+                port0(0x10, 0x80),
+            ],
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let samples = vgm.ym2610_samples();
+        assert_eq!(samples.adpcm_a.len(), 2);
+        assert_eq!(
+            (samples.adpcm_a[0].start, samples.adpcm_a[0].end),
+            (0x100, 0x300)
+        );
+        assert_eq!(
+            samples.adpcm_a[0].samples,
+            decode_adpcm_a(&rom[0x100..0x300])
+        );
+        assert_eq!(samples.adpcm_a[0].rate, 18518);
+        assert_eq!(samples.adpcm_b.len(), 1);
+        assert_eq!(
+            samples.adpcm_b[0].samples,
+            decode_adpcm_b(&rom[0x200..0x300])
+        );
+        assert_eq!(samples.adpcm_b[0].rate, 27777);
+
+        let key_ons: Vec<(u64, Ym2610Unit, usize)> = samples
+            .key_ons
+            .iter()
+            .map(|key_on| (key_on.sample_position, key_on.unit, key_on.sample_index))
+            .collect();
+        assert_eq!(
+            key_ons,
+            [
+                (0, Ym2610Unit::AdpcmA(1), 0),
+                (0, Ym2610Unit::AdpcmA(3), 0),
+                (735, Ym2610Unit::AdpcmA(1), 1),
+                (735, Ym2610Unit::AdpcmB, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+
+use crate::chip_state::ChipState;
+use crate::command::Command;
+use crate::playback::VGM_SAMPLE_RATE;
+use crate::vgmfile::VgmFile;
+
+/// Longest time between two 0x8n writes of the same sample played, in samples, used when none is given
+pub const DEFAULT_SEGMENT_MAX_GAP: u64 = 64;
+
+/// Byte written to the YM2612 DAC by a 0x8n command
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PcmWrite {
+    pub command_index: usize,
+    pub sample: u64,
+    /// Position in the YM2612 PCM data
+    pub offset: usize,
+    /// `None` when the offset is past the end of the data loaded so far
+    pub value: Option<u8>,
+}
+
+/// Consecutive bytes of the PCM data played by 0x8n commands at a steady rate
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PcmSegment {
+    pub start_sample: u64,
+    pub command_index: usize,
+    pub offset: usize,
+    pub length: usize,
+    /// Average rate of the writes in Hz, 0 for a single write
+    pub rate: u32,
+}
+
+/// Sample of the PCM data played from the same offset one or more times, usually a drum
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DrumSample {
+    pub offset: usize,
+    /// Longest length played
+    pub length: usize,
+    /// Rate of the longest playback
+    pub rate: u32,
+    /// Sample at which each playback starts
+    pub triggers: Vec<u64>,
+}
+
+impl VgmFile {
+    /// Every 0x8n command with the byte of the YM2612 PCM data it writes, following
+    /// the data blocks and SeekPCM commands before it
+    pub fn ym2612_pcm_writes(&self) -> Vec<PcmWrite> {
+        let mut writes = vec![];
+        let mut state = ChipState::new();
+        for (command_index, (sample, _, cmd)) in self.timeline().enumerate() {
+            if let Command::YM2612Port0Address2AWriteWait { .. } = cmd {
+                let offset = state.ym2612_pcm_offset();
+                writes.push(PcmWrite {
+                    command_index,
+                    sample,
+                    offset,
+                    value: state.ym2612_pcm().get(offset).copied(),
+                });
+            }
+            state.update(cmd);
+        }
+
+        writes
+    }
+
+    /// Split the 0x8n writes into runs reading consecutive bytes, a run ends on a jump in the
+    /// data or when no byte is written for more than `max_gap` samples
+    pub fn ym2612_pcm_segments(&self, max_gap: u64) -> Vec<PcmSegment> {
+        let mut segments: Vec<PcmSegment> = vec![];
+        let mut last_sample = 0;
+        for write in self.ym2612_pcm_writes() {
+            match segments.last_mut() {
+                Some(segment)
+                    if segment.offset + segment.length == write.offset
+                        && write.sample - last_sample <= max_gap =>
+                {
+                    segment.length += 1;
+                    let span = write.sample - segment.start_sample;
+                    segment.rate = match span {
+                        0 => 0,
+                        _ => ((segment.length as u64 - 1) * VGM_SAMPLE_RATE as u64 / span) as u32,
+                    };
+                }
+                _ => segments.push(PcmSegment {
+                    start_sample: write.sample,
+                    command_index: write.command_index,
+                    offset: write.offset,
+                    length: 1,
+                    rate: 0,
+                }),
+            }
+            last_sample = write.sample;
+        }
+
+        segments
+    }
+
+    /// Distinct samples of the YM2612 PCM data, the segments starting at the same offset
+    /// are playbacks of the same sample
+    pub fn ym2612_drum_samples(&self, max_gap: u64) -> Vec<DrumSample> {
+        let mut samples: BTreeMap<usize, DrumSample> = BTreeMap::new();
+        for segment in self.ym2612_pcm_segments(max_gap) {
+            let sample = samples.entry(segment.offset).or_insert(DrumSample {
+                offset: segment.offset,
+                length: 0,
+                rate: 0,
+                triggers: vec![],
+            });
+            if segment.length > sample.length {
+                sample.length = segment.length;
+                sample.rate = segment.rate;
+            }
+            sample.triggers.push(segment.start_sample);
+        }
+
+        samples.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+    use crate::header::HeaderData;
+    use crate::metadata::VgmMetadata;
+    use crate::vgmfile::VgmFile;
+
+    use super::DEFAULT_SEGMENT_MAX_GAP;
+
+    #[test]
+    fn drum_samples() {
+        let mut commands = vec![Command::DataBlock {
+            data_type: 0x00,
+            data_size: 8,
+            data: vec![0x80, 0x90, 0xA0, 0xB0, 0x10, 0x20, 0x30, 0x40],
+        }];
+        for offset in [0, 4, 0] {
+            commands.push(Command::SeekPCM { offset });
+            commands.extend(vec![Command::YM2612Port0Address2AWriteWait { n: 5 }; 4]);
+            commands.push(Command::WaitNSamples { n: 1000 });
+        }
+        // runs over the end of the data
+        commands.push(Command::SeekPCM { offset: 7 });
+        commands.extend(vec![Command::YM2612Port0Address2AWriteWait { n: 10 }; 2]);
+        let vgm = VgmFile {
+            header: HeaderData::default(),
+            commands,
+            metadata: VgmMetadata::default(),
+            loop_index: None,
+        };
+
+        let writes = vgm.ym2612_pcm_writes();
+        assert_eq!(writes.len(), 14);
+        assert_eq!(writes[1].value, Some(0x90));
+        assert_eq!(writes[5].offset, 5);
+        assert_eq!(writes[5].value, Some(0x20));
+        assert_eq!(writes[13].offset, 8);
+        assert_eq!(writes[13].value, None);
+
+        let segments = vgm.ym2612_pcm_segments(DEFAULT_SEGMENT_MAX_GAP);
+        assert_eq!(segments.len(), 4);
+        assert_eq!(segments[0].rate, 8820);
+        assert_eq!(segments[3].length, 2);
+
+        let drums = vgm.ym2612_drum_samples(DEFAULT_SEGMENT_MAX_GAP);
+        assert_eq!(drums.len(), 3);
+        assert_eq!(drums[0].offset, 0);
+        assert_eq!(drums[0].triggers, [0, 2040]);
+        assert_eq!(drums[1].offset, 4);
+        assert_eq!(drums[1].length, 4);
+    }
+}